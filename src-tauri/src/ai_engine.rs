@@ -1,22 +1,83 @@
+use crate::backend::{
+    CompletionRequest, EngineStatus, FinishReason, InferenceBackend, StatusReporter,
+};
+use crate::calculators::CalculationResult;
+use crate::interactions::InteractionReport;
+use crate::knowledge::{self, Citation, RetrievedPassage, SourceReference};
+use crate::postprocess::{without_footer, ReplyWarning};
+use crate::prompt_template::PromptMessage;
+use crate::red_flags::RedFlagAlert;
+use crate::settings::GenerationSettings;
+use crate::tools::{self, ToolCallFilter, ToolCallRecord, ToolRegistry};
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use tokio::time::Duration;
+use tokio::sync::oneshot;
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub id: String,
-    pub role: String, // "user" or "assistant"
+    pub role: String, // "user", "assistant" or "tool"
     pub content: String,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Set when generation was stopped by the user and `content` only holds
+    /// the partial answer produced up to that point.
+    #[serde(default)]
+    pub cancelled: bool,
+    /// The message before this one in its branch; `None` for the first
+    /// message. Messages sharing a parent are alternatives (regenerated
+    /// answers, edited questions) of which one is active.
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// Knowledge base passages an assistant message cites
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    /// The call a tool message records. Tool messages are children of the
+    /// reply they were made for and shown before it, in the order made.
+    #[serde(default)]
+    pub tool_call: Option<ToolCallRecord>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatRequest {
     pub message: String,
     pub conversation_id: Option<String>,
+    /// Overrides the conversation's stored settings for this request only
+    #[serde(default)]
+    pub generation_settings: Option<GenerationSettings>,
+    /// Engine to answer with; the active engine when unset
+    #[serde(default)]
+    pub engine_id: Option<String>,
+    /// Prompt profile to use from now on; the conversation's current one
+    /// when unset
+    #[serde(default)]
+    pub profile_id: Option<String>,
+}
+
+/// Replaces an earlier user message with corrected text in a new branch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditMessageRequest {
+    pub conversation_id: String,
+    pub message_id: String,
+    pub content: String,
+    #[serde(default)]
+    pub generation_settings: Option<GenerationSettings>,
+    #[serde(default)]
+    pub engine_id: Option<String>,
+}
+
+/// Asks for another answer to the question an assistant message replied to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegenerateRequest {
+    pub conversation_id: String,
+    /// The assistant message to generate an alternative to
+    pub message_id: String,
+    #[serde(default)]
+    pub generation_settings: Option<GenerationSettings>,
+    #[serde(default)]
+    pub engine_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,211 +85,541 @@ pub struct ChatResponse {
     pub message: String,
     pub conversation_id: String,
     pub message_id: String,
+    #[serde(default)]
+    pub cancelled: bool,
+    /// Red flags found in the question or the reply by the rule-based
+    /// detector, whatever the model said about them
+    #[serde(default)]
+    pub alerts: Vec<RedFlagAlert>,
+    /// Interaction check for the drugs named in the question and the reply,
+    /// when there are at least two
+    #[serde(default)]
+    pub interactions: Option<InteractionReport>,
+    /// Calculators the model ran for the reply, with their exact results
+    #[serde(default)]
+    pub calculations: Vec<CalculationResult>,
+    /// Knowledge base passages the model was given with the question
+    #[serde(default)]
+    pub sources: Vec<SourceReference>,
+    /// The passages the reply actually cites
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    /// Problems with the reply itself, such as being cut off
+    #[serde(default)]
+    pub warnings: Vec<ReplyWarning>,
+}
+
+/// Red flags found in a question, sent before the reply is generated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedFlagEvent {
+    pub conversation_id: String,
+    pub message_id: String,
+    pub alerts: Vec<RedFlagAlert>,
+}
+
+/// A single token (or group of tokens) streamed to the frontend while a reply
+/// is being generated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatStreamChunk {
+    pub conversation_id: String,
+    pub message_id: String,
+    pub content: String,
+}
+
+/// What the conversation contributes to the prompt besides the new message.
+#[derive(Debug, Clone)]
+pub struct ConversationContext {
+    /// From the conversation's prompt profile
+    pub system_prompt: String,
+    /// Earlier messages, oldest first
+    pub history: Vec<ChatMessage>,
+    /// Summary of older messages that are no longer part of `history`
+    pub summary: Option<String>,
+    /// Knowledge base passages for the new message, best first
+    pub passages: Vec<RetrievedPassage>,
 }
 
+/// The outcome of a generation request.
+#[derive(Debug, Clone)]
+pub struct Generation {
+    pub content: String,
+    pub cancelled: bool,
+    /// `None` when cancelled or when the backend didn't report one
+    pub finish_reason: Option<FinishReason>,
+    /// Knowledge base passages that fit in the prompt, best first
+    pub sources: Vec<SourceReference>,
+    /// Tool calls made for the reply, in order
+    pub tool_calls: Vec<ToolCallRecord>,
+    /// The model asked for more tool calls than allowed and was made to
+    /// answer without them
+    pub tool_limit_reached: bool,
+}
+
+// Tokens added around each message by the chat template (role headers and
+// end-of-turn markers). Generous for the templates we support.
+const MESSAGE_OVERHEAD_TOKENS: usize = 8;
+
+// Tool calls per reply; a call after that is refused and the model asked to
+// answer without tools
+const MAX_TOOL_ROUNDS: usize = 3;
+
+// Prompt space kept free for tool calls and their results
+const TOOL_ROUNDS_TOKENS: usize = 512;
+
+// The reply and tool rounds get at most this share of the context, so small
+// contexts (512 tokens per slot is allowed) still leave room for the prompt
+const MAX_REPLY_SHARE: usize = 2;
+
+/// A summary of a loaded engine for the frontend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineInfo {
+    pub id: String,
+    pub endpoint: String,
+    pub is_ready: bool,
+    pub status: EngineStatus,
+}
+
+// A generation's id and the sender that cancels it
+type Generating = (Uuid, oneshot::Sender<()>);
+
+/// A loaded model that can answer chat messages. Which inference backend
+/// does the work is decided when the engine is created; everything here is
+/// the same for all of them.
 #[derive(Clone)]
 pub struct AIEngine {
-    llama_process: Arc<Mutex<Option<Child>>>,
-    server_port: u16,
-    model_path: PathBuf,
-    pub is_ready: Arc<Mutex<bool>>,
+    pub id: String,
+    backend: Arc<dyn InferenceBackend>,
+    status: StatusReporter,
+    // In-flight generations keyed by conversation id. Each has an id of its
+    // own, so overlapping ones (a regenerate while a reply streams) don't
+    // clear each other's cancel handle.
+    active_generations: Arc<Mutex<HashMap<String, Vec<Generating>>>>,
 }
 
 impl AIEngine {
-    pub fn new(model_path: PathBuf) -> Self {
+    /// `status` must be the reporter the backend was created with.
+    pub fn new(id: String, backend: Arc<dyn InferenceBackend>, status: StatusReporter) -> Self {
         Self {
-            llama_process: Arc::new(Mutex::new(None)),
-            server_port: 8080,
-            model_path,
-            is_ready: Arc::new(Mutex::new(false)),
+            id,
+            backend,
+            status,
+            active_generations: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     pub async fn initialize(&self) -> Result<()> {
-        // First, check if model exists
-        if !self.model_path.exists() {
-            return Err(anyhow!("Model file not found at {:?}", self.model_path));
+        self.backend.start().await
+    }
+
+    pub fn info(&self) -> EngineInfo {
+        EngineInfo {
+            id: self.id.clone(),
+            endpoint: self.backend.endpoint(),
+            is_ready: self.status.is_ready(),
+            status: self.status(),
         }
+    }
 
-        // Start llama.cpp server
-        self.start_llama_server().await?;
+    pub fn status(&self) -> EngineStatus {
+        self.status.get()
+    }
 
-        // Wait for server to be ready
-        self.wait_for_server().await?;
+    pub async fn generate_response(
+        &self,
+        conversation_id: &str,
+        prompt: &str,
+        context: &ConversationContext,
+        tools: &ToolRegistry,
+        settings: &GenerationSettings,
+    ) -> Result<Generation> {
+        self.generate_response_stream(conversation_id, prompt, context, tools, settings, |_| {})
+            .await
+    }
 
-        // Mark as ready
-        *self.is_ready.lock().unwrap() = true;
+    /// Generates a response with the engine's backend, calling `on_token` for
+    /// every piece of content as it arrives. The model may call `tools` along
+    /// the way. Returns the full reply once the backend is done, or the
+    /// partial reply if `cancel_generation` was called for the conversation.
+    pub async fn generate_response_stream<F>(
+        &self,
+        conversation_id: &str,
+        prompt: &str,
+        context: &ConversationContext,
+        tools: &ToolRegistry,
+        settings: &GenerationSettings,
+        mut on_token: F,
+    ) -> Result<Generation>
+    where
+        F: FnMut(&str) + Send,
+    {
+        self.ensure_ready()?;
+
+        let mut settings = settings.clone();
+        let reserved = self.reserve_reply_room(&mut settings);
+        let (messages, passages_used) = self
+            .build_medical_prompt(prompt, context, tools, reserved)
+            .await?;
+        let request = CompletionRequest {
+            messages,
+            settings,
+            cache_key: Some(conversation_id.to_string()),
+            json_schema: None,
+        };
 
-        Ok(())
+        let generation_id = Uuid::new_v4();
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        self.active_generations
+            .lock()
+            .unwrap()
+            .entry(conversation_id.to_string())
+            .or_default()
+            .push((generation_id, cancel_tx));
+
+        // Collect the reply here as well so a cancelled generation still
+        // returns what was produced up to that point
+        let mut content = String::new();
+        let mut tool_calls = Vec::new();
+        let mut tool_limit_reached = false;
+        let completion = {
+            let rounds = self.complete_with_tools(
+                request,
+                tools,
+                &mut content,
+                &mut tool_calls,
+                &mut tool_limit_reached,
+                &mut on_token,
+            );
+
+            // Dropping the backend's future aborts the request
+            tokio::select! {
+                Ok(()) = cancel_rx => None,
+                result = rounds => Some(result),
+            }
+        };
+
+        {
+            let mut active_generations = self.active_generations.lock().unwrap();
+            if let Some(generations) = active_generations.get_mut(conversation_id) {
+                generations.retain(|(id, _)| *id != generation_id);
+                if generations.is_empty() {
+                    active_generations.remove(conversation_id);
+                }
+            }
+        }
+
+        let (cancelled, finish_reason) = match completion {
+            Some(result) => (false, result?),
+            None => (true, None),
+        };
+
+        Ok(Generation {
+            content: content.trim().to_string(),
+            cancelled,
+            finish_reason,
+            sources: context.passages[..passages_used]
+                .iter()
+                .map(SourceReference::from)
+                .collect(),
+            tool_calls,
+            tool_limit_reached,
+        })
     }
 
-    async fn start_llama_server(&self) -> Result<()> {
-        let binary_path = self.get_llama_binary_path()?;
+    /// Runs `request`, letting the model call `tools`: each call is run,
+    /// recorded in `tool_calls` and its result added to the prompt for
+    /// another round. Visible text from all rounds goes to `content` and
+    /// `on_token`; the calls themselves are never shown. A call past
+    /// `MAX_TOOL_ROUNDS` is refused and sets `tool_limit_reached`, and the
+    /// model gets one last round to answer without tools.
+    async fn complete_with_tools<F>(
+        &self,
+        mut request: CompletionRequest,
+        tools: &ToolRegistry,
+        content: &mut String,
+        tool_calls: &mut Vec<ToolCallRecord>,
+        tool_limit_reached: &mut bool,
+        on_token: &mut F,
+    ) -> Result<Option<FinishReason>>
+    where
+        F: FnMut(&str) + Send,
+    {
+        if !tools.is_empty() {
+            request
+                .settings
+                .stop
+                .push(tools::TOOL_CALL_CLOSE.to_string());
+        }
 
-        let mut command = Command::new(binary_path);
-        command
-            .arg("-m")
-            .arg(&self.model_path)
-            .arg("--port")
-            .arg(self.server_port.to_string())
-            .arg("--host")
-            .arg("127.0.0.1")
-            .arg("--ctx-size")
-            .arg("4096")
-            .arg("--batch-size")
-            .arg("512")
-            .arg("--threads")
-            .arg("4")
-            .arg("--n-gpu-layers")
-            .arg("0") // CPU only for maximum compatibility
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null());
+        for round in 0..=MAX_TOOL_ROUNDS + 1 {
+            let mut output = String::new();
+            let mut filter = ToolCallFilter::default();
+            // Text after a tool result starts a new paragraph
+            let mut separate = !content.is_empty();
+            let mut show = |text: &str| {
+                if text.is_empty() {
+                    return;
+                }
+                if separate {
+                    content.push_str("\n\n");
+                    on_token("\n\n");
+                    separate = false;
+                }
+                content.push_str(text);
+                on_token(text);
+            };
+
+            let completion = self
+                .backend
+                .complete(&request, &mut |token: &str| {
+                    output.push_str(token);
+                    show(filter.visible(&output));
+                })
+                .await?;
+            show(filter.finish(&output));
+
+            // After the limit nothing more is run, whatever the model wrote
+            let call = match tools::parse_tool_call(&output) {
+                Some(call) if !*tool_limit_reached => call,
+                _ => return Ok(completion.finish_reason),
+            };
+            *tool_limit_reached = round >= MAX_TOOL_ROUNDS;
+
+            // Backends leave out the stop string that ended the call
+            let call_text = output
+                .split(tools::TOOL_CALL_CLOSE)
+                .next()
+                .unwrap_or_default();
+
+            let record = match call {
+                // Still recorded, so the conversation shows the call was made
+                Ok(call) if *tool_limit_reached => {
+                    ToolCallRecord::rejected(call, "tool round limit reached")
+                }
+                Ok(call) => tools.call(call).await,
+                Err(e) => {
+                    let raw_call = call_text
+                        .split(tools::TOOL_CALL_OPEN)
+                        .nth(1)
+                        .unwrap_or_default();
+                    ToolCallRecord::invalid(raw_call, e)
+                }
+            };
+            let result_message = if *tool_limit_reached {
+                format!(
+                    "{}. No more tools can be called for this reply. Answer now with what you have, and say which results could not be obtained.",
+                    record.describe()
+                )
+            } else {
+                record.result_message()
+            };
+            tool_calls.push(record);
+            request.messages.push(PromptMessage::new(
+                "assistant",
+                &format!("{}{}", call_text, tools::TOOL_CALL_CLOSE),
+            ));
+            request
+                .messages
+                .push(PromptMessage::new("user", &result_message));
+        }
 
-        let child = command
-            .spawn()
-            .map_err(|e| anyhow!("Failed to start llama.cpp server: {}", e))?;
+        Ok(None)
+    }
 
-        *self.llama_process.lock().unwrap() = Some(child);
+    /// Runs a one-off completion outside of any conversation, e.g. to
+    /// summarize one. It can't be cancelled and doesn't stream.
+    pub async fn complete(
+        &self,
+        messages: Vec<PromptMessage>,
+        settings: &GenerationSettings,
+    ) -> Result<String> {
+        self.run_completion(CompletionRequest {
+            messages,
+            settings: settings.clone(),
+            cache_key: None,
+            json_schema: None,
+        })
+        .await
+    }
 
-        Ok(())
+    /// Like `complete`, but the reply is constrained to JSON matching
+    /// `schema`.
+    pub async fn complete_json(
+        &self,
+        messages: Vec<PromptMessage>,
+        settings: &GenerationSettings,
+        schema: &serde_json::Value,
+    ) -> Result<String> {
+        self.run_completion(CompletionRequest {
+            messages,
+            settings: settings.clone(),
+            cache_key: None,
+            json_schema: Some(schema.clone()),
+        })
+        .await
     }
 
-    fn get_llama_binary_path(&self) -> Result<PathBuf> {
-        // Try to find llama.cpp binary in various locations
-        let binary_name = if cfg!(windows) {
-            "llama-server.exe"
-        } else {
-            "llama-server"
-        };
+    async fn run_completion(&self, request: CompletionRequest) -> Result<String> {
+        self.ensure_ready()?;
 
-        // Check system PATH first
-        if let Ok(path) = which::which(binary_name) {
-            return Ok(path);
-        }
+        let completion = self.backend.complete(&request, &mut |_| {}).await?;
 
-        // Check common installation directories
-        let common_paths = vec!["/usr/local/bin", "/usr/bin", "/opt/llama.cpp/bin"];
+        Ok(completion.content.trim().to_string())
+    }
 
-        for path_str in common_paths {
-            let path = PathBuf::from(path_str).join(binary_name);
-            if path.exists() {
-                return Ok(path);
-            }
-        }
+    /// The model the backend serves, which embeddings are recorded with.
+    pub fn model(&self) -> String {
+        self.backend.model()
+    }
 
-        Err(anyhow!("llama.cpp binary not found. Please install llama.cpp or ensure 'llama-server' is in your PATH."))
+    pub fn provides_embeddings(&self) -> bool {
+        self.backend.provides_embeddings()
     }
 
-    async fn wait_for_server(&self) -> Result<()> {
-        let client = reqwest::Client::new();
-        let health_url = format!("http://127.0.0.1:{}/health", self.server_port);
+    /// Embedding vectors for `texts`, one per text.
+    pub async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.ensure_ready()?;
+        self.backend.embed(texts).await
+    }
 
-        for _ in 0..30 {
-            // Wait up to 30 seconds
-            tokio::time::sleep(Duration::from_secs(1)).await;
+    fn ensure_ready(&self) -> Result<()> {
+        if self.status.is_ready() {
+            return Ok(());
+        }
 
-            if let Ok(response) = client.get(&health_url).send().await {
-                if response.status().is_success() {
-                    return Ok(());
-                }
+        Err(match self.status() {
+            EngineStatus::Crashed { reason } | EngineStatus::Failed { reason } => {
+                anyhow!("AI engine not ready: {}", reason)
             }
-        }
+            EngineStatus::Restarting { .. } => {
+                anyhow!("AI engine not ready: restarting after a crash")
+            }
+            _ => anyhow!("AI engine not ready"),
+        })
+    }
 
-        Err(anyhow!("llama.cpp server failed to start within timeout"))
+    /// Stops the generations running for `conversation_id`, if any. Returns
+    /// whether a generation was found.
+    pub fn cancel_generation(&self, conversation_id: &str) -> bool {
+        let generations = self
+            .active_generations
+            .lock()
+            .unwrap()
+            .remove(conversation_id)
+            .unwrap_or_default();
+
+        let mut cancelled = false;
+        for (_, cancel_tx) in generations {
+            cancelled |= cancel_tx.send(()).is_ok();
+        }
+        cancelled
     }
 
-    pub async fn generate_response(
+    /// Assembles the prompt within the model's context window: the system
+    /// prompt (with the conversation summary, if any) and the new user
+    /// message are always included, then as many knowledge base passages as
+    /// fit, best first, then as much of the history as still fits, newest
+    /// first. `reserved` tokens are kept free for the reply and tool rounds.
+    /// Returns the messages and how many passages made it in.
+    async fn build_medical_prompt(
         &self,
-        prompt: &str,
-        conversation_context: &[ChatMessage],
-    ) -> Result<String> {
-        if !*self.is_ready.lock().unwrap() {
-            return Err(anyhow!("AI engine not ready"));
+        user_message: &str,
+        context: &ConversationContext,
+        tools: &ToolRegistry,
+        reserved: usize,
+    ) -> Result<(Vec<PromptMessage>, usize)> {
+        let mut system_prompt = PromptMessage::new("system", &context.system_prompt);
+        if let Some(summary) = &context.summary {
+            system_prompt.content.push_str(&format!(
+                "\n\nSummary of the earlier conversation:\n{}",
+                summary
+            ));
         }
+        system_prompt.content.push_str(&tools.instructions());
+        let mut current_message = PromptMessage::new("user", user_message);
 
-        let full_prompt = self.build_medical_prompt(prompt, conversation_context);
-
-        let client = reqwest::Client::new();
-        let completion_url = format!("http://127.0.0.1:{}/completion", self.server_port);
-
-        let request_body = serde_json::json!({
-            "prompt": full_prompt,
-            "n_predict": 512,
-            "temperature": 0.7,
-            "top_p": 0.9,
-            "top_k": 40,
-            "repeat_penalty": 1.1,
-            "stop": ["Human:", "User:", "\n\n"]
-        });
-
-        let response = client
-            .post(&completion_url)
-            .json(&request_body)
-            .send()
-            .await
-            .map_err(|e| anyhow!("Failed to send request to llama.cpp: {}", e))?;
+        let budget = (self.backend.context_size() as usize).saturating_sub(reserved);
+        let system_tokens = self.prompt_tokens(&system_prompt).await?;
+        let mut used = system_tokens + self.prompt_tokens(&current_message).await?;
 
-        if !response.status().is_success() {
+        if used > budget {
             return Err(anyhow!(
-                "llama.cpp server returned error: {}",
-                response.status()
+                "Message is too long: the prompt needs about {} tokens but only {} fit in the context window after reserving {} for the reply and tool calls",
+                used,
+                budget,
+                reserved
             ));
         }
 
-        let response_json: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| anyhow!("Failed to parse response: {}", e))?;
+        // Passages go with the new message rather than the system prompt, so
+        // the cached prompt prefix stays the same from turn to turn
+        let mut passages_used = 0;
+        for count in 1..=context.passages.len() {
+            let candidate = PromptMessage::new(
+                "user",
+                &knowledge::with_passages(user_message, &context.passages[..count]),
+            );
+            let tokens = system_tokens + self.prompt_tokens(&candidate).await?;
+            if tokens > budget {
+                break;
+            }
 
-        let content = response_json["content"]
-            .as_str()
-            .unwrap_or("I apologize, but I encountered an error generating a response.")
-            .trim()
-            .to_string();
+            used = tokens;
+            passages_used = count;
+            current_message = candidate;
+        }
 
-        Ok(content)
-    }
+        // Walk back from the newest message and stop at the first one that
+        // doesn't fit, so the history stays contiguous
+        let mut history = Vec::new();
+        for message in context.history.iter().rev() {
+            if !matches!(message.role.as_str(), "user" | "assistant") {
+                continue;
+            }
 
-    fn build_medical_prompt(
-        &self,
-        user_message: &str,
-        conversation_context: &[ChatMessage],
-    ) -> String {
-        let mut prompt = String::new();
-
-        // System prompt for medical context
-        prompt.push_str("You are an AI medical assistant designed to help healthcare professionals, particularly resident doctors in remote locations. You provide information about medical conditions, symptoms, differential diagnoses, and treatment options. Always remind users that your responses are for educational purposes and should not replace clinical judgment or proper medical evaluation.\n\n");
-
-        // Add conversation context
-        for message in conversation_context.iter().rev().take(10).rev() {
-            // Last 10 messages
-            match message.role.as_str() {
-                "user" => prompt.push_str(&format!("Human: {}\n", message.content)),
-                "assistant" => prompt.push_str(&format!("Assistant: {}\n", message.content)),
-                _ => {}
+            let message = PromptMessage::new(&message.role, without_footer(&message.content));
+            let tokens = self.prompt_tokens(&message).await?;
+            if used + tokens > budget {
+                break;
             }
+
+            used += tokens;
+            history.push(message);
         }
 
-        // Add current user message
-        prompt.push_str(&format!("Human: {}\nAssistant: ", user_message));
+        let mut messages = vec![system_prompt];
+        messages.extend(history.into_iter().rev());
+        messages.push(current_message);
 
-        prompt
+        Ok((messages, passages_used))
     }
 
-    pub fn shutdown(&self) -> Result<()> {
-        if let Some(mut child) = self.llama_process.lock().unwrap().take() {
-            child
-                .kill()
-                .map_err(|e| anyhow!("Failed to kill llama.cpp process: {}", e))?;
-        }
-        *self.is_ready.lock().unwrap() = false;
-        Ok(())
+    /// Lowers `settings.n_predict` where needed so the reply and tool rounds
+    /// take at most `1 / MAX_REPLY_SHARE` of the context, and returns the
+    /// tokens to keep free for them.
+    fn reserve_reply_room(&self, settings: &mut GenerationSettings) -> usize {
+        let room = self.backend.context_size() as usize / MAX_REPLY_SHARE;
+        let tool_tokens = TOOL_ROUNDS_TOKENS.min(room / 2);
+        let n_predict = (settings.n_predict as usize).min(room - tool_tokens);
+        settings.n_predict = n_predict as u32;
+        n_predict + tool_tokens
     }
-}
 
-impl Drop for AIEngine {
-    fn drop(&mut self) {
-        let _ = self.shutdown();
+    /// Tokens a message takes up in the prompt, including its chat template
+    /// markup.
+    pub async fn prompt_tokens(&self, message: &PromptMessage) -> Result<usize> {
+        Ok(self.backend.count_tokens(&message.content).await? + MESSAGE_OVERHEAD_TOKENS)
+    }
+
+    /// Tokens the model's context window holds.
+    pub fn context_size(&self) -> usize {
+        self.backend.context_size() as usize
+    }
+
+    pub async fn save_cache(&self) -> Result<()> {
+        self.backend.save_cache().await
+    }
+
+    pub fn shutdown(&self) -> Result<()> {
+        self.backend.shutdown()
     }
 }
@@ -0,0 +1,847 @@
+use super::slots::{SlotAssignment, SlotTable};
+use super::{
+    parse_embedding, read_event_stream, tokenize_count, Completion, CompletionRequest,
+    EngineStatus, FinishReason, InferenceBackend, StatusReporter, TokenCallback,
+};
+use crate::engine_log::EngineLog;
+use crate::prompt_template::ChatTemplate;
+use crate::settings::{EngineLaunchOptions, GenerationSettings};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+// How often the supervisor checks on llama-server and how many times it may
+// restart a crashed server within `RESTART_WINDOW` before giving up
+const SUPERVISOR_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RESTART_ATTEMPTS: u32 = 3;
+const RESTART_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+// The port we pick is free when checked but may be taken before llama-server
+// binds it; launching is retried on a fresh port this many times in total
+const PORT_ATTEMPTS: u32 = 3;
+
+// Readiness polling. The expected load time scales with the model file since
+// loading is mostly disk-bound on the laptops we target; the timeout leaves
+// generous room on top of it.
+const HEALTH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const BASE_LOAD_SECS: f64 = 5.0;
+const LOAD_SECS_PER_GB: f64 = 10.0;
+const MIN_STARTUP_TIMEOUT_SECS: f64 = 30.0;
+const MAX_STARTUP_TIMEOUT_SECS: f64 = 600.0;
+
+// Lines of llama-server output attached to startup errors and crash reports
+const ERROR_LOG_LINES: usize = 20;
+const CRASH_LOG_LINES: usize = 5;
+
+/// A llama-server process we start, supervise and restart ourselves.
+#[derive(Clone)]
+pub struct LlamaServerBackend {
+    llama_process: Arc<Mutex<Option<Child>>>,
+    server_port: Arc<Mutex<u16>>,
+    model_path: PathBuf,
+    chat_template: ChatTemplate,
+    launch_options: EngineLaunchOptions,
+    status: StatusReporter,
+    log: Option<EngineLog>,
+    slots: Arc<Mutex<SlotTable>>,
+    slot_cache_dir: Option<PathBuf>,
+    persist_slots: bool,
+    embedding: bool,
+}
+
+impl LlamaServerBackend {
+    pub fn new(
+        model_path: PathBuf,
+        chat_template: ChatTemplate,
+        launch_options: EngineLaunchOptions,
+        status: StatusReporter,
+    ) -> Self {
+        let slots = SlotTable::new(launch_options.parallel_slots);
+
+        Self {
+            llama_process: Arc::new(Mutex::new(None)),
+            server_port: Arc::new(Mutex::new(0)),
+            model_path,
+            chat_template,
+            launch_options,
+            status,
+            log: None,
+            slots: Arc::new(Mutex::new(slots)),
+            slot_cache_dir: None,
+            persist_slots: false,
+            embedding: false,
+        }
+    }
+
+    /// Pipes llama-server's stdout and stderr into `log`. Without a log the
+    /// output is discarded.
+    pub fn with_log(mut self, log: EngineLog) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Saves a conversation's prompt cache to `dir` when its slot is handed
+    /// to another conversation or the engine is unloaded, and restores it
+    /// when the conversation comes back.
+    pub fn with_slot_cache(mut self, dir: PathBuf) -> Self {
+        self.slot_cache_dir = Some(dir);
+        self.persist_slots = true;
+        self
+    }
+
+    /// Only parks a conversation's cache in `dir` while its slot is lent to
+    /// a request without a cache key; nothing is kept there afterwards.
+    pub fn with_slot_scratch(mut self, dir: PathBuf) -> Self {
+        self.slot_cache_dir = Some(dir);
+        self.persist_slots = false;
+        self
+    }
+
+    /// Serves `/embedding` with a mean-pooled vector per text, for an
+    /// embedding model rather than a chat model.
+    pub fn for_embeddings(mut self) -> Self {
+        self.embedding = true;
+        self
+    }
+
+    async fn start_and_wait(&self) -> Result<()> {
+        // A fresh server starts with empty slots
+        self.slots.lock().unwrap().clear();
+
+        for attempt in 1..=PORT_ATTEMPTS {
+            // Start llama.cpp server
+            self.start_llama_server().await?;
+
+            // Wait for server to be ready, explaining failures with its output
+            match self.wait_for_server().await {
+                Ok(()) => break,
+                Err(_) if attempt < PORT_ATTEMPTS && self.lost_port() => {
+                    self.log_event("Port was taken before llama-server bound it; retrying");
+                }
+                Err(e) => {
+                    return Err(anyhow!("{}{}", e, self.log_tail_for_error(ERROR_LOG_LINES)));
+                }
+            }
+        }
+
+        // Mark as ready
+        self.status.set(EngineStatus::Ready);
+
+        Ok(())
+    }
+
+    /// Polls the llama-server child until the backend is shut down. When the
+    /// process exits on its own the engine is marked crashed and restarted
+    /// with exponential backoff, up to `MAX_RESTART_ATTEMPTS` times within
+    /// `RESTART_WINDOW`, so a server that keeps crashing soon after a
+    /// successful restart still ends up `Failed`.
+    async fn supervise(self) {
+        let mut recent_restarts = VecDeque::new();
+
+        loop {
+            tokio::time::sleep(SUPERVISOR_POLL_INTERVAL).await;
+
+            // Every other handle is gone; dropping ours stops the server
+            if Arc::strong_count(&self.llama_process) == 1 {
+                return;
+            }
+
+            let exit_status = {
+                let mut process_guard = self.llama_process.lock().unwrap();
+                let wait_result = match process_guard.as_mut() {
+                    // Taken by shutdown()
+                    None => return,
+                    Some(child) => child.try_wait(),
+                };
+
+                match wait_result {
+                    Ok(None) => continue,
+                    Ok(Some(exit_status)) => {
+                        process_guard.take();
+                        exit_status.to_string()
+                    }
+                    Err(e) => format!("failed to query process status: {}", e),
+                }
+            };
+
+            let reason = format!(
+                "llama.cpp server exited unexpectedly ({}){}",
+                exit_status,
+                self.log_tail_for_error(CRASH_LOG_LINES)
+            );
+            self.status.set(EngineStatus::Crashed {
+                reason: reason.clone(),
+            });
+
+            if !self
+                .restart_with_backoff(&reason, &mut recent_restarts)
+                .await
+            {
+                return;
+            }
+        }
+    }
+
+    async fn restart_with_backoff(
+        &self,
+        crash_reason: &str,
+        recent_restarts: &mut VecDeque<Instant>,
+    ) -> bool {
+        let mut last_error = crash_reason.to_string();
+
+        // Make sure nothing is left of the crashed process before respawning
+        let _ = self.kill_process();
+
+        loop {
+            while recent_restarts
+                .front()
+                .is_some_and(|started| started.elapsed() > RESTART_WINDOW)
+            {
+                recent_restarts.pop_front();
+            }
+            if recent_restarts.len() >= MAX_RESTART_ATTEMPTS as usize {
+                break;
+            }
+            recent_restarts.push_back(Instant::now());
+            let attempt = recent_restarts.len() as u32;
+
+            tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+
+            // The engine may have been unloaded while we were waiting
+            if Arc::strong_count(&self.llama_process) == 1 {
+                return false;
+            }
+
+            self.status.set(EngineStatus::Restarting {
+                attempt,
+                max_attempts: MAX_RESTART_ATTEMPTS,
+            });
+
+            match self.start_and_wait().await {
+                Ok(()) => return true,
+                Err(e) => {
+                    // Don't leave a half-started server behind
+                    let _ = self.kill_process();
+                    last_error = e.to_string();
+                }
+            }
+        }
+
+        self.status.set(EngineStatus::Failed {
+            reason: format!(
+                "Giving up after {} restart attempts in {} minutes: {}",
+                MAX_RESTART_ATTEMPTS,
+                RESTART_WINDOW.as_secs() / 60,
+                last_error
+            ),
+        });
+        false
+    }
+
+    fn server_url(&self, path: &str) -> String {
+        format!(
+            "http://127.0.0.1:{}{}",
+            *self.server_port.lock().unwrap(),
+            path
+        )
+    }
+
+    async fn start_llama_server(&self) -> Result<()> {
+        let binary_path = self.get_llama_binary_path()?;
+        let port = pick_free_port(self.launch_options.port_range)?;
+        *self.server_port.lock().unwrap() = port;
+
+        let mut command = Command::new(binary_path);
+        command
+            .arg("-m")
+            .arg(&self.model_path)
+            .arg("--port")
+            .arg(port.to_string())
+            .arg("--host")
+            .arg("127.0.0.1")
+            .arg("--ctx-size")
+            .arg(self.launch_options.context_size.to_string())
+            .arg("--batch-size")
+            .arg(self.launch_options.batch_size.to_string())
+            .arg("--threads")
+            .arg(self.launch_options.threads.to_string())
+            .arg("--parallel")
+            .arg(self.launch_options.parallel_slots.to_string())
+            .arg("--n-gpu-layers")
+            .arg("0"); // CPU only for maximum compatibility
+
+        if self.launch_options.use_mlock {
+            command.arg("--mlock");
+        }
+        if !self.launch_options.use_mmap {
+            command.arg("--no-mmap");
+        }
+        if self.embedding {
+            command.arg("--embedding").arg("--pooling").arg("mean");
+        }
+        if let Some(slot_cache_dir) = &self.slot_cache_dir {
+            // Older llama-server builds append the file name without a
+            // separator
+            let mut slot_save_path = slot_cache_dir.clone().into_os_string();
+            slot_save_path.push(std::path::MAIN_SEPARATOR_STR);
+            command.arg("--slot-save-path").arg(slot_save_path);
+        }
+
+        command.stdin(Stdio::null());
+        if self.log.is_some() {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        } else {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| anyhow!("Failed to start llama.cpp server: {}", e))?;
+
+        if let Some(log) = &self.log {
+            log.append("engine", &format!("Started llama-server on port {}", port));
+            if let Some(stdout) = child.stdout.take() {
+                log.capture(stdout, "stdout");
+            }
+            if let Some(stderr) = child.stderr.take() {
+                log.capture(stderr, "stderr");
+            }
+        }
+
+        *self.llama_process.lock().unwrap() = Some(child);
+
+        Ok(())
+    }
+
+    fn log_event(&self, message: &str) {
+        if let Some(log) = &self.log {
+            log.append("engine", message);
+        }
+    }
+
+    fn log_tail_for_error(&self, lines: usize) -> String {
+        self.log
+            .as_ref()
+            .map(|log| log.tail_for_error(lines))
+            .unwrap_or_default()
+    }
+
+    fn get_llama_binary_path(&self) -> Result<PathBuf> {
+        // Try to find llama.cpp binary in various locations
+        let binary_name = if cfg!(windows) {
+            "llama-server.exe"
+        } else {
+            "llama-server"
+        };
+
+        // Check system PATH first
+        if let Ok(path) = which::which(binary_name) {
+            return Ok(path);
+        }
+
+        // Check common installation directories
+        let common_paths = vec!["/usr/local/bin", "/usr/bin", "/opt/llama.cpp/bin"];
+
+        for path_str in common_paths {
+            let path = PathBuf::from(path_str).join(binary_name);
+            if path.exists() {
+                return Ok(path);
+            }
+        }
+
+        Err(anyhow!("llama.cpp binary not found. Please install llama.cpp or ensure 'llama-server' is in your PATH."))
+    }
+
+    /// Polls `/health` until the model is loaded. Gives up early when the
+    /// process exits or the server reports an error, and otherwise after a
+    /// timeout that scales with the model file size. Progress is reported
+    /// through `EngineStatus::Loading`.
+    async fn wait_for_server(&self) -> Result<()> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()?;
+        let health_url = self.server_url("/health");
+
+        let model_size_gb = std::fs::metadata(&self.model_path)
+            .map(|m| m.len() as f64 / (1024.0 * 1024.0 * 1024.0))
+            .unwrap_or(0.0);
+        let expected_secs = BASE_LOAD_SECS + LOAD_SECS_PER_GB * model_size_gb;
+        let timeout_secs =
+            (MIN_STARTUP_TIMEOUT_SECS + 3.0 * expected_secs).min(MAX_STARTUP_TIMEOUT_SECS);
+
+        let started = std::time::Instant::now();
+
+        loop {
+            tokio::time::sleep(HEALTH_POLL_INTERVAL).await;
+
+            if let Some(exit_status) = self.exited_process_status() {
+                return Err(anyhow!(
+                    "llama.cpp server exited while loading the model ({})",
+                    exit_status
+                ));
+            }
+
+            match self.check_health(&client, &health_url).await {
+                HealthState::Ok => return Ok(()),
+                HealthState::Error(message) => {
+                    return Err(anyhow!("llama.cpp server reported an error: {}", message));
+                }
+                HealthState::Loading | HealthState::Unreachable => {}
+            }
+
+            let elapsed_secs = started.elapsed().as_secs_f64();
+            if elapsed_secs > timeout_secs {
+                return Err(anyhow!(
+                    "llama.cpp server did not finish loading the model within {} seconds",
+                    timeout_secs.round()
+                ));
+            }
+
+            self.status.set(EngineStatus::Loading {
+                progress: (elapsed_secs / expected_secs).min(0.99) as f32,
+                elapsed_secs: elapsed_secs as u64,
+            });
+        }
+    }
+
+    async fn check_health(&self, client: &reqwest::Client, health_url: &str) -> HealthState {
+        let Ok(response) = client.get(health_url).send().await else {
+            // Not listening yet
+            return HealthState::Unreachable;
+        };
+
+        let status = response.status();
+        let body: serde_json::Value = response.json().await.unwrap_or_default();
+
+        if status.is_success() {
+            return HealthState::Ok;
+        }
+
+        let message = body["error"]["message"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        // llama-server answers 503 "Loading model" until the weights are in
+        if status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+            HealthState::Loading
+        } else if message.is_empty() {
+            HealthState::Error(format!("HTTP {}", status))
+        } else {
+            HealthState::Error(message)
+        }
+    }
+
+    /// The exit status if the llama-server child has already terminated.
+    fn exited_process_status(&self) -> Option<String> {
+        let mut process_guard = self.llama_process.lock().unwrap();
+        match process_guard.as_mut()?.try_wait() {
+            Ok(Some(exit_status)) => {
+                process_guard.take();
+                Some(exit_status.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether llama-server exited because another process holds its port.
+    fn lost_port(&self) -> bool {
+        let port = *self.server_port.lock().unwrap();
+        self.llama_process.lock().unwrap().is_none()
+            && TcpListener::bind(("127.0.0.1", port)).is_err()
+    }
+
+    fn completion_request_body(
+        &self,
+        full_prompt: &str,
+        settings: &GenerationSettings,
+        json_schema: Option<&serde_json::Value>,
+        id_slot: i64,
+        stream: bool,
+    ) -> serde_json::Value {
+        let mut stop: Vec<String> = self
+            .chat_template
+            .stop_sequences()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        stop.extend(settings.stop.iter().cloned());
+
+        let mut body = serde_json::json!({
+            "prompt": full_prompt,
+            "n_predict": settings.n_predict,
+            "temperature": settings.temperature,
+            "top_p": settings.top_p,
+            "top_k": settings.top_k,
+            "repeat_penalty": settings.repeat_penalty,
+            "stop": stop,
+            // Only the part of the prompt after the cached prefix is evaluated
+            "cache_prompt": true,
+            "id_slot": id_slot,
+            "stream": stream
+        });
+        // llama-server converts the schema to a GBNF grammar
+        if let Some(schema) = json_schema {
+            body["json_schema"] = schema.clone();
+        }
+        body
+    }
+
+    /// Pins the conversation to a slot so its cached prompt is reused. When
+    /// the slot was just taken over, the previous owner's cache is saved and
+    /// this conversation's restored, if persistence is on.
+    async fn claim_slot(&self, cache_key: &str) -> usize {
+        let assignment = self.slots.lock().unwrap().assign(cache_key);
+
+        if assignment.reused || !self.persist_slots {
+            return assignment.slot_id;
+        }
+
+        self.save_evicted(&assignment).await;
+
+        if self.slot_file(cache_key).is_some_and(|path| path.exists()) {
+            if let Err(e) = self
+                .slot_action(assignment.slot_id, "restore", cache_key)
+                .await
+            {
+                self.log_event(&format!("Failed to restore prompt cache: {}", e));
+            }
+        }
+
+        assignment.slot_id
+    }
+
+    /// Finds a slot for a request without a cache key, such as a background
+    /// summary. A conversation holding the slot keeps it: its cache is saved
+    /// and put back by `return_slot` once the request is done. When it can't
+    /// be saved the conversation loses the slot instead, so its file is never
+    /// overwritten with this request's prompt.
+    async fn lend_slot(&self) -> (usize, Option<String>) {
+        let (slot_id, owner) = self.slots.lock().unwrap().lend();
+        let Some(owner) = owner else {
+            return (slot_id, None);
+        };
+
+        if self.slot_cache_dir.is_some() {
+            match self.slot_action(slot_id, "save", &owner).await {
+                Ok(()) => return (slot_id, Some(owner)),
+                Err(e) => self.log_event(&format!("Failed to save prompt cache: {}", e)),
+            }
+        }
+
+        self.slots.lock().unwrap().release(slot_id, &owner);
+        (slot_id, None)
+    }
+
+    async fn return_slot(&self, slot_id: usize, owner: &str) {
+        if let Err(e) = self.slot_action(slot_id, "restore", owner).await {
+            self.log_event(&format!("Failed to restore prompt cache: {}", e));
+        }
+        if !self.persist_slots {
+            if let Some(path) = self.slot_file(owner) {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    async fn save_evicted(&self, assignment: &SlotAssignment) {
+        if let Some(evicted) = &assignment.evicted {
+            if let Err(e) = self.slot_action(assignment.slot_id, "save", evicted).await {
+                self.log_event(&format!("Failed to save prompt cache: {}", e));
+            }
+        }
+    }
+
+    async fn stream_completion(
+        &self,
+        request: &CompletionRequest,
+        id_slot: usize,
+        on_token: &mut TokenCallback<'_>,
+    ) -> Result<Completion> {
+        let full_prompt = self.chat_template.render(&request.messages);
+        let request_body = self.completion_request_body(
+            &full_prompt,
+            &request.settings,
+            request.json_schema.as_ref(),
+            id_slot as i64,
+            true,
+        );
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(self.server_url("/completion"))
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to send request to llama.cpp: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "llama.cpp server returned error: {}",
+                response.status()
+            ));
+        }
+
+        // Dropping the response stream closes the connection, which makes
+        // llama-server abort the task and release its slot.
+        let mut content = String::new();
+        let mut finish_reason = None;
+        read_event_stream(response, |event| {
+            if let Some(token) = event["content"].as_str() {
+                if !token.is_empty() {
+                    content.push_str(token);
+                    on_token(token);
+                }
+            }
+
+            let stop = event["stop"].as_bool().unwrap_or(false);
+            if stop {
+                // Older servers report `stopped_limit`, newer ones `stop_type`
+                let hit_limit = event["stop_type"].as_str() == Some("limit")
+                    || event["stopped_limit"].as_bool().unwrap_or(false);
+                finish_reason = Some(if hit_limit {
+                    FinishReason::Length
+                } else {
+                    FinishReason::Stop
+                });
+            }
+            Ok(stop)
+        })
+        .await?;
+
+        Ok(Completion {
+            content,
+            finish_reason,
+        })
+    }
+
+    fn slot_file(&self, cache_key: &str) -> Option<PathBuf> {
+        self.slot_cache_dir
+            .as_ref()
+            .map(|dir| dir.join(slot_file_name(cache_key)))
+    }
+
+    async fn slot_action(&self, slot_id: usize, action: &str, cache_key: &str) -> Result<()> {
+        let url = self.server_url(&format!("/slots/{}?action={}", slot_id, action));
+        let response = reqwest::Client::new()
+            .post(url)
+            .json(&serde_json::json!({ "filename": slot_file_name(cache_key) }))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to send slot {} request: {}", action, e))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "llama.cpp server returned error for slot {}: {}",
+                action,
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn kill_process(&self) -> Result<()> {
+        if let Some(mut child) = self.llama_process.lock().unwrap().take() {
+            child
+                .kill()
+                .map_err(|e| anyhow!("Failed to kill llama.cpp process: {}", e))?;
+            // Reap the process so it doesn't linger as a zombie
+            let _ = child.wait();
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for LlamaServerBackend {
+    fn endpoint(&self) -> String {
+        format!(
+            "llama-server {} on port {}",
+            self.model_path.display(),
+            *self.server_port.lock().unwrap()
+        )
+    }
+
+    fn model(&self) -> String {
+        self.model_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    async fn start(&self) -> Result<()> {
+        // First, check if model exists
+        if !self.model_path.exists() {
+            return Err(anyhow!("Model file not found at {:?}", self.model_path));
+        }
+
+        self.status.set(EngineStatus::Starting);
+
+        if let Err(e) = self.start_and_wait().await {
+            self.status.set(EngineStatus::Failed {
+                reason: e.to_string(),
+            });
+            return Err(e);
+        }
+
+        // Watch the process for crashes from now on
+        tokio::spawn(self.clone().supervise());
+
+        Ok(())
+    }
+
+    /// Renders the messages with the model's chat template and streams the
+    /// reply from `/completion`.
+    async fn complete(
+        &self,
+        request: &CompletionRequest,
+        on_token: &mut TokenCallback<'_>,
+    ) -> Result<Completion> {
+        let (id_slot, lent_by) = match &request.cache_key {
+            Some(cache_key) => (self.claim_slot(cache_key).await, None),
+            None => self.lend_slot().await,
+        };
+
+        let completion = self.stream_completion(request, id_slot, on_token).await;
+        if let Some(owner) = lent_by {
+            self.return_slot(id_slot, &owner).await;
+        }
+        completion
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.kill_process()?;
+        self.status.set(EngineStatus::Stopped);
+        Ok(())
+    }
+
+    async fn save_cache(&self) -> Result<()> {
+        if !self.persist_slots || !self.status.is_ready() {
+            return Ok(());
+        }
+
+        let owners = self.slots.lock().unwrap().owners();
+        for (slot_id, cache_key) in owners {
+            self.slot_action(slot_id, "save", &cache_key).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Each slot gets an equal share of the context.
+    fn context_size(&self) -> u32 {
+        self.launch_options.context_size / self.launch_options.parallel_slots.max(1)
+    }
+
+    /// Counts with the loaded model's own tokenizer via `/tokenize`.
+    async fn count_tokens(&self, text: &str) -> Result<usize> {
+        tokenize_count(&reqwest::Client::new(), &self.server_url("/tokenize"), text).await
+    }
+
+    /// Only when started with `for_embeddings`; chat servers run without
+    /// `--embedding`.
+    fn provides_embeddings(&self) -> bool {
+        self.embedding
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if !self.embedding {
+            return Err(anyhow!("llama-server was not started for embeddings"));
+        }
+
+        let client = reqwest::Client::new();
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            let response = client
+                .post(self.server_url("/embedding"))
+                .json(&serde_json::json!({ "content": text }))
+                .send()
+                .await
+                .map_err(|e| anyhow!("Failed to send embedding request: {}", e))?;
+
+            if !response.status().is_success() {
+                return Err(anyhow!("Embedding request failed: {}", response.status()));
+            }
+
+            let body: serde_json::Value = response
+                .json()
+                .await
+                .map_err(|e| anyhow!("Failed to parse embedding response: {}", e))?;
+            embeddings.push(parse_embedding(&body)?);
+        }
+
+        Ok(embeddings)
+    }
+}
+
+enum HealthState {
+    Ok,
+    Loading,
+    Unreachable,
+    Error(String),
+}
+
+/// Finds a loopback port nothing is listening on, either anywhere (letting the
+/// OS choose) or within the configured range.
+fn pick_free_port(port_range: Option<(u16, u16)>) -> Result<u16> {
+    match port_range {
+        Some((start, end)) => (start..=end)
+            .find(|&port| TcpListener::bind(("127.0.0.1", port)).is_ok())
+            .ok_or_else(|| anyhow!("No free port between {} and {}", start, end)),
+        None => {
+            let listener = TcpListener::bind(("127.0.0.1", 0))
+                .map_err(|e| anyhow!("Failed to find a free port: {}", e))?;
+            Ok(listener.local_addr()?.port())
+        }
+    }
+}
+
+/// Conversation ids are UUIDs, but keep whatever reaches llama-server a plain
+/// file name.
+fn slot_file_name(cache_key: &str) -> String {
+    let name: String = cache_key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.bin", name)
+}
+
+/// Deletes the saved prompt caches of a conversation for every model under
+/// `cache_root`.
+pub fn remove_prompt_cache(cache_root: &Path, cache_key: &str) -> Result<()> {
+    let Ok(model_dirs) = std::fs::read_dir(cache_root) else {
+        // Nothing was ever cached
+        return Ok(());
+    };
+
+    for model_dir in model_dirs {
+        let path = model_dir?.path().join(slot_file_name(cache_key));
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+impl Drop for LlamaServerBackend {
+    fn drop(&mut self) {
+        // Clones share the server process; only the last one shuts it down
+        if Arc::strong_count(&self.llama_process) == 1 {
+            let _ = self.shutdown();
+        }
+    }
+}
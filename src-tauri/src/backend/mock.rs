@@ -0,0 +1,130 @@
+use super::{
+    Completion, CompletionRequest, EngineStatus, FinishReason, InferenceBackend, StatusReporter,
+    TokenCallback, DEFAULT_CONTEXT_SIZE,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+
+const MOCK_EMBEDDING_SIZE: usize = 64;
+
+/// Answers every request with a fixed reply derived from the last user
+/// message, so the app can be exercised without a model.
+pub struct MockBackend {
+    status: StatusReporter,
+}
+
+impl MockBackend {
+    pub fn new(status: StatusReporter) -> Self {
+        Self { status }
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for MockBackend {
+    fn endpoint(&self) -> String {
+        "mock".to_string()
+    }
+
+    fn model(&self) -> String {
+        "mock".to_string()
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.status.set(EngineStatus::Ready);
+        Ok(())
+    }
+
+    async fn complete(
+        &self,
+        request: &CompletionRequest,
+        on_token: &mut TokenCallback<'_>,
+    ) -> Result<Completion> {
+        let last_user_message = request
+            .messages
+            .iter()
+            .rev()
+            .find(|message| message.role == "user")
+            .map(|message| message.content.as_str())
+            .unwrap_or_default();
+
+        let reply = format!(
+            "Mock response to \"{}\" ({} messages in context).",
+            last_user_message,
+            request.messages.len()
+        );
+        let reply = match &request.json_schema {
+            Some(schema) => mock_json(schema, &reply).to_string(),
+            None => reply,
+        };
+
+        // Stream word by word, like a real backend would
+        for token in reply.split_inclusive(' ') {
+            on_token(token);
+            tokio::task::yield_now().await;
+        }
+
+        Ok(Completion {
+            content: reply,
+            finish_reason: Some(FinishReason::Stop),
+        })
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.status.set(EngineStatus::Stopped);
+        Ok(())
+    }
+
+    fn context_size(&self) -> u32 {
+        DEFAULT_CONTEXT_SIZE
+    }
+
+    /// Hashed bag of words: texts sharing words come out similar, which is
+    /// enough to exercise retrieval.
+    fn provides_embeddings(&self) -> bool {
+        true
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts
+            .iter()
+            .map(|text| {
+                let mut embedding = vec![0.0f32; MOCK_EMBEDDING_SIZE];
+                for word in text.to_lowercase().split(|c: char| !c.is_alphanumeric()) {
+                    if word.is_empty() {
+                        continue;
+                    }
+                    let hash = word.bytes().fold(0usize, |hash, b| {
+                        hash.wrapping_mul(31).wrapping_add(b as usize)
+                    });
+                    embedding[hash % MOCK_EMBEDDING_SIZE] += 1.0;
+                }
+                embedding
+            })
+            .collect())
+    }
+}
+
+/// A value matching `schema`, with `text` in every string. Covers the subset
+/// of JSON schema our structured outputs use.
+fn mock_json(schema: &serde_json::Value, text: &str) -> serde_json::Value {
+    match schema["type"].as_str() {
+        Some("object") => {
+            let properties = schema["properties"]
+                .as_object()
+                .cloned()
+                .unwrap_or_default();
+            properties
+                .into_iter()
+                .map(|(name, property)| (name, mock_json(&property, text)))
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        }
+        Some("array") => serde_json::json!([mock_json(&schema["items"], text)]),
+        Some("integer") | Some("number") => 0.into(),
+        Some("boolean") => false.into(),
+        _ => match schema["enum"].as_array().and_then(|values| values.first()) {
+            Some(value) => value.clone(),
+            None => text.into(),
+        },
+    }
+}
@@ -0,0 +1,288 @@
+mod llama_server;
+mod mock;
+mod openai_compat;
+mod slots;
+
+pub use llama_server::{remove_prompt_cache, LlamaServerBackend};
+pub use mock::MockBackend;
+pub use openai_compat::OpenAiCompatBackend;
+
+use crate::prompt_template::PromptMessage;
+use crate::settings::GenerationSettings;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// Something that can turn a conversation into a reply: the llama-server we
+/// manage ourselves, a server someone else runs, or a mock for testing.
+#[async_trait]
+pub trait InferenceBackend: Send + Sync {
+    /// Where requests go, for display in the UI.
+    fn endpoint(&self) -> String;
+
+    /// The model being served: its file name, or the name sent to a server
+    /// we don't run.
+    fn model(&self) -> String;
+
+    /// Brings the backend up and returns once it can serve completions.
+    async fn start(&self) -> Result<()>;
+
+    /// Generates a reply to `request.messages`, calling `on_token` with each
+    /// piece of text as it is produced. Returns the full reply. Dropping the
+    /// returned future aborts the generation.
+    async fn complete(
+        &self,
+        request: &CompletionRequest,
+        on_token: &mut TokenCallback<'_>,
+    ) -> Result<Completion>;
+
+    fn shutdown(&self) -> Result<()>;
+
+    /// Persists prompt caches worth keeping across restarts. Called before
+    /// the engine is unloaded.
+    async fn save_cache(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Size of the model's context window in tokens, prompt and reply
+    /// together.
+    fn context_size(&self) -> u32;
+
+    /// Number of tokens `text` encodes to. Backends without a tokenizer
+    /// endpoint fall back to an estimate.
+    async fn count_tokens(&self, text: &str) -> Result<usize> {
+        Ok(estimate_tokens(text))
+    }
+
+    /// Whether `embed` works with this backend as started.
+    fn provides_embeddings(&self) -> bool {
+        false
+    }
+
+    /// One embedding vector per text, for the knowledge base.
+    async fn embed(&self, _texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Err(anyhow!("This backend doesn't provide embeddings"))
+    }
+}
+
+/// Context size assumed for backends that can't tell us theirs.
+pub const DEFAULT_CONTEXT_SIZE: u32 = 4096;
+
+/// Rough token count for when no tokenizer is available. Errs on the high
+/// side: English medical text averages closer to four bytes per token.
+fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(3)
+}
+
+/// Asks a llama.cpp server's `/tokenize` endpoint how many tokens `text` is.
+async fn tokenize_count(client: &reqwest::Client, url: &str, text: &str) -> Result<usize> {
+    let response = client
+        .post(url)
+        .json(&serde_json::json!({ "content": text }))
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to send tokenize request: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!("Tokenize request failed: {}", response.status()));
+    }
+
+    let body: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| anyhow!("Failed to parse tokenize response: {}", e))?;
+
+    body["tokens"]
+        .as_array()
+        .map(|tokens| tokens.len())
+        .ok_or_else(|| anyhow!("Tokenize response has no tokens"))
+}
+
+/// Reads the vector out of an `/embedding` response. llama-server has
+/// returned `{"embedding": [...]}` as well as `[{"embedding": [[...]]}]`
+/// depending on the version.
+fn parse_embedding(body: &serde_json::Value) -> Result<Vec<f32>> {
+    let mut embedding = match body {
+        serde_json::Value::Array(items) => items.first().map(|item| &item["embedding"]),
+        _ => Some(&body["embedding"]),
+    }
+    .and_then(|embedding| embedding.as_array())
+    .ok_or_else(|| anyhow!("Embedding response has no embedding"))?;
+
+    // Pooled embeddings may still come wrapped in a one-element array
+    if let Some(inner) = embedding.first().and_then(|first| first.as_array()) {
+        embedding = inner;
+    }
+
+    embedding
+        .iter()
+        .map(|value| {
+            value
+                .as_f64()
+                .map(|value| value as f32)
+                .ok_or_else(|| anyhow!("Embedding contains a non-number"))
+        })
+        .collect()
+}
+
+/// Receives each piece of a reply as it is generated. Spelled out as
+/// higher-ranked so `#[async_trait]` doesn't tie the `&str` to the call.
+pub type TokenCallback<'a> = dyn for<'t> FnMut(&'t str) + Send + 'a;
+
+/// A backend-independent completion request. Backends that take a raw prompt
+/// render `messages` with their chat template; chat APIs get them as-is.
+#[derive(Debug, Clone)]
+pub struct CompletionRequest {
+    pub messages: Vec<PromptMessage>,
+    pub settings: GenerationSettings,
+    /// Conversation the request belongs to. Backends that keep a prompt
+    /// cache per conversation use it to find the right one.
+    pub cache_key: Option<String>,
+    /// JSON schema the reply must conform to. Backends turn it into a
+    /// grammar so the model can't produce anything else.
+    pub json_schema: Option<serde_json::Value>,
+}
+
+/// Why the model stopped generating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// End of turn or a stop string
+    Stop,
+    /// Ran into `n_predict` or the end of the context; the reply is cut off
+    Length,
+}
+
+/// A finished reply. `finish_reason` is `None` when the server didn't say.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub content: String,
+    pub finish_reason: Option<FinishReason>,
+}
+
+/// Lifecycle of the backend behind an engine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum EngineStatus {
+    Starting,
+    /// llama-server is up and loading the model. `progress` is an estimate
+    /// based on elapsed time and model size; llama-server doesn't report it.
+    Loading {
+        progress: f32,
+        elapsed_secs: u64,
+    },
+    Ready,
+    Crashed {
+        reason: String,
+    },
+    Restarting {
+        attempt: u32,
+        max_attempts: u32,
+    },
+    Failed {
+        reason: String,
+    },
+    Stopped,
+}
+
+/// Emitted to the frontend whenever an engine's status changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineStatusEvent {
+    pub engine_id: String,
+    pub status: EngineStatus,
+}
+
+pub type StatusListener = Arc<dyn Fn(EngineStatusEvent) + Send + Sync>;
+
+/// Shared status of one engine. The backend updates it as it starts, crashes
+/// or restarts; the engine reads it before generating.
+#[derive(Clone)]
+pub struct StatusReporter {
+    engine_id: String,
+    status: Arc<Mutex<EngineStatus>>,
+    listener: Option<StatusListener>,
+}
+
+impl StatusReporter {
+    pub fn new(engine_id: &str, listener: Option<StatusListener>) -> Self {
+        Self {
+            engine_id: engine_id.to_string(),
+            status: Arc::new(Mutex::new(EngineStatus::Stopped)),
+            listener,
+        }
+    }
+
+    pub fn get(&self) -> EngineStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        *self.status.lock().unwrap() == EngineStatus::Ready
+    }
+
+    pub fn set(&self, status: EngineStatus) {
+        *self.status.lock().unwrap() = status.clone();
+
+        if let Some(listener) = &self.listener {
+            listener(EngineStatusEvent {
+                engine_id: self.engine_id.clone(),
+                status,
+            });
+        }
+    }
+}
+
+/// Reads a server-sent events response, handing each JSON `data:` payload to
+/// `on_event` until it returns `true` or the stream ends.
+async fn read_event_stream<F>(response: reqwest::Response, mut on_event: F) -> Result<()>
+where
+    F: FnMut(serde_json::Value) -> Result<bool>,
+{
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut stream = response.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| anyhow!("Failed to read response stream: {}", e))?;
+        buffer.extend_from_slice(&chunk);
+
+        // SSE events are newline-delimited; keep any partial line for the next chunk
+        while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+
+            let Some(event) = parse_stream_event(&line)? else {
+                continue;
+            };
+
+            if on_event(event)? {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses one line of SSE output. Returns `None` for blank lines, comments,
+/// the OpenAI `[DONE]` sentinel and anything that is not a `data:` event.
+fn parse_stream_event(line: &str) -> Result<Option<serde_json::Value>> {
+    let Some(data) = line.trim().strip_prefix("data:") else {
+        return Ok(None);
+    };
+
+    let data = data.trim();
+    if data.is_empty() || data == "[DONE]" {
+        return Ok(None);
+    }
+
+    let event: serde_json::Value =
+        serde_json::from_str(data).map_err(|e| anyhow!("Failed to parse stream event: {}", e))?;
+
+    if let Some(error) = event.get("error") {
+        return Err(anyhow!("Inference server returned error: {}", error));
+    }
+
+    Ok(Some(event))
+}
@@ -0,0 +1,235 @@
+use super::{
+    estimate_tokens, parse_embedding, read_event_stream, tokenize_count, Completion,
+    CompletionRequest, EngineStatus, FinishReason, InferenceBackend, StatusReporter, TokenCallback,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tokio::time::Duration;
+
+/// A server someone else runs that speaks the OpenAI chat completions API,
+/// such as llama.cpp's llama-server or Ollama. We only connect to it; its
+/// lifecycle is not ours to manage.
+pub struct OpenAiCompatBackend {
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+    context_size: u32,
+    client: reqwest::Client,
+    status: StatusReporter,
+}
+
+impl OpenAiCompatBackend {
+    /// `base_url` is the server root without `/v1`, e.g.
+    /// `http://127.0.0.1:11434` for Ollama.
+    pub fn new(
+        base_url: &str,
+        model: &str,
+        api_key: Option<String>,
+        context_size: u32,
+        status: StatusReporter,
+    ) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            model: model.to_string(),
+            api_key,
+            context_size,
+            client: reqwest::Client::new(),
+            status,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+
+    async fn check_reachable(&self) -> Result<()> {
+        let response = self
+            .request(reqwest::Method::GET, "/v1/models")
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Failed to reach inference server at {}: {}",
+                    self.base_url,
+                    e
+                )
+            })?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Inference server at {} returned error: {}",
+                self.base_url,
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for OpenAiCompatBackend {
+    fn endpoint(&self) -> String {
+        format!("{} ({})", self.base_url, self.model)
+    }
+
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.status.set(EngineStatus::Starting);
+
+        match self.check_reachable().await {
+            Ok(()) => {
+                self.status.set(EngineStatus::Ready);
+                Ok(())
+            }
+            Err(e) => {
+                self.status.set(EngineStatus::Failed {
+                    reason: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// Streams the reply from `/v1/chat/completions`. The server applies its
+    /// own chat template, so the messages are sent as they are.
+    async fn complete(
+        &self,
+        request: &CompletionRequest,
+        on_token: &mut TokenCallback<'_>,
+    ) -> Result<Completion> {
+        let settings = &request.settings;
+        let mut request_body = serde_json::json!({
+            "model": self.model,
+            "messages": request.messages,
+            "max_tokens": settings.n_predict,
+            "temperature": settings.temperature,
+            "top_p": settings.top_p,
+            "stream": true
+        });
+        // Not part of the OpenAI API, but llama.cpp and Ollama both honour them
+        request_body["top_k"] = settings.top_k.into();
+        request_body["repeat_penalty"] = settings.repeat_penalty.into();
+        if !settings.stop.is_empty() {
+            request_body["stop"] = settings.stop.clone().into();
+        }
+        if let Some(schema) = &request.json_schema {
+            request_body["response_format"] = serde_json::json!({
+                "type": "json_schema",
+                "json_schema": { "name": "response", "strict": true, "schema": schema }
+            });
+        }
+
+        let response = self
+            .request(reqwest::Method::POST, "/v1/chat/completions")
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to send request to inference server: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Inference server returned error: {}",
+                response.status()
+            ));
+        }
+
+        let mut content = String::new();
+        let mut finish_reason = None;
+        read_event_stream(response, |event| {
+            let choice = &event["choices"][0];
+            if let Some(token) = choice["delta"]["content"].as_str() {
+                if !token.is_empty() {
+                    content.push_str(token);
+                    on_token(token);
+                }
+            }
+
+            if choice["finish_reason"].is_null() {
+                return Ok(false);
+            }
+            finish_reason = Some(match choice["finish_reason"].as_str() {
+                Some("length") => FinishReason::Length,
+                _ => FinishReason::Stop,
+            });
+            Ok(true)
+        })
+        .await?;
+
+        Ok(Completion {
+            content,
+            finish_reason,
+        })
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.status.set(EngineStatus::Stopped);
+        Ok(())
+    }
+
+    fn context_size(&self) -> u32 {
+        self.context_size
+    }
+
+    /// llama.cpp servers expose `/tokenize`; others (Ollama) don't, and get
+    /// an estimate instead.
+    async fn count_tokens(&self, text: &str) -> Result<usize> {
+        let url = format!("{}/tokenize", self.base_url);
+        Ok(tokenize_count(&self.client, &url, text)
+            .await
+            .unwrap_or_else(|_| estimate_tokens(text)))
+    }
+
+    /// Uses `/v1/embeddings` with the configured model name, which has to
+    /// be an embedding model on the server.
+    fn provides_embeddings(&self) -> bool {
+        true
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let response = self
+            .request(reqwest::Method::POST, "/v1/embeddings")
+            .json(&serde_json::json!({ "model": self.model, "input": texts }))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to send embedding request: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Embedding request failed: {}", response.status()));
+        }
+
+        let body: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| anyhow!("Failed to parse embedding response: {}", e))?;
+        let mut data = body["data"]
+            .as_array()
+            .cloned()
+            .ok_or_else(|| anyhow!("Embedding response has no data"))?;
+        data.sort_by_key(|item| item["index"].as_u64().unwrap_or(0));
+
+        let embeddings = data
+            .iter()
+            .map(parse_embedding)
+            .collect::<Result<Vec<_>>>()?;
+        if embeddings.len() != texts.len() {
+            return Err(anyhow!(
+                "Expected {} embeddings but got {}",
+                texts.len(),
+                embeddings.len()
+            ));
+        }
+
+        Ok(embeddings)
+    }
+}
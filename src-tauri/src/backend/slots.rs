@@ -0,0 +1,223 @@
+use std::time::Instant;
+
+/// Which conversation each llama-server slot holds the KV cache for. A
+/// conversation keeps its slot until the slot is needed by another one, at
+/// which point the least recently used slot is handed over.
+pub struct SlotTable {
+    slots: Vec<Slot>,
+}
+
+struct Slot {
+    owner: Option<String>,
+    last_used: Instant,
+}
+
+/// Result of asking for a conversation's slot.
+pub struct SlotAssignment {
+    pub slot_id: usize,
+    /// False when the slot was just taken over; its cache holds someone
+    /// else's prompt (or nothing)
+    pub reused: bool,
+    /// Conversation whose cache was in the slot before it was taken over
+    pub evicted: Option<String>,
+}
+
+impl SlotTable {
+    pub fn new(slot_count: u32) -> Self {
+        let now = Instant::now();
+        Self {
+            slots: (0..slot_count.max(1))
+                .map(|_| Slot {
+                    owner: None,
+                    last_used: now,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn assign(&mut self, conversation_id: &str) -> SlotAssignment {
+        let now = Instant::now();
+
+        if let Some(slot_id) = self
+            .slots
+            .iter()
+            .position(|slot| slot.owner.as_deref() == Some(conversation_id))
+        {
+            self.slots[slot_id].last_used = now;
+            return SlotAssignment {
+                slot_id,
+                reused: true,
+                evicted: None,
+            };
+        }
+
+        let slot_id = self.free_slot();
+        let slot = &mut self.slots[slot_id];
+        let evicted = slot.owner.replace(conversation_id.to_string());
+        slot.last_used = now;
+
+        SlotAssignment {
+            slot_id,
+            reused: false,
+            evicted,
+        }
+    }
+
+    /// Picks a slot for a request that isn't tied to a conversation and
+    /// returns it with its owner, who keeps it. The caller parks the owner's
+    /// cache while the slot is lent out, or `release`s the slot.
+    pub fn lend(&self) -> (usize, Option<String>) {
+        let slot_id = self.free_slot();
+        (slot_id, self.slots[slot_id].owner.clone())
+    }
+
+    /// Takes the slot away from `owner`, if it still holds it.
+    pub fn release(&mut self, slot_id: usize, owner: &str) {
+        let slot = &mut self.slots[slot_id];
+        if slot.owner.as_deref() == Some(owner) {
+            slot.owner = None;
+        }
+    }
+
+    /// Prefers an empty slot, otherwise the one idle for longest.
+    fn free_slot(&self) -> usize {
+        self.slots
+            .iter()
+            .position(|slot| slot.owner.is_none())
+            .unwrap_or_else(|| {
+                self.slots
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, slot)| slot.last_used)
+                    .map(|(slot_id, _)| slot_id)
+                    .unwrap_or(0)
+            })
+    }
+
+    /// Conversations currently holding a slot, with their slot ids.
+    pub fn owners(&self) -> Vec<(usize, String)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot_id, slot)| slot.owner.clone().map(|owner| (slot_id, owner)))
+            .collect()
+    }
+
+    /// Forgets all assignments, e.g. after llama-server restarted with empty
+    /// caches.
+    pub fn clear(&mut self) {
+        for slot in &mut self.slots {
+            slot.owner = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    // Keeps `last_used` strictly ordered between steps
+    fn tick() {
+        sleep(Duration::from_millis(2));
+    }
+
+    #[test]
+    fn keeps_a_conversation_on_its_slot() {
+        let mut table = SlotTable::new(2);
+
+        let first = table.assign("a");
+        assert!(!first.reused);
+        assert_eq!(first.evicted, None);
+
+        table.assign("b");
+        let again = table.assign("a");
+        assert_eq!(again.slot_id, first.slot_id);
+        assert!(again.reused);
+        assert_eq!(again.evicted, None);
+    }
+
+    #[test]
+    fn hands_over_the_least_recently_used_slot() {
+        let mut table = SlotTable::new(2);
+        let a = table.assign("a").slot_id;
+        tick();
+        let b = table.assign("b").slot_id;
+        tick();
+        table.assign("a");
+        tick();
+
+        let c = table.assign("c");
+        assert_eq!(c.slot_id, b);
+        assert!(!c.reused);
+        assert_eq!(c.evicted.as_deref(), Some("b"));
+        assert_ne!(c.slot_id, a);
+    }
+
+    #[test]
+    fn lends_an_empty_slot_before_an_owned_one() {
+        let mut table = SlotTable::new(2);
+        let a = table.assign("a").slot_id;
+
+        let (slot_id, owner) = table.lend();
+        assert_ne!(slot_id, a);
+        assert_eq!(owner, None);
+    }
+
+    #[test]
+    fn lending_an_owned_slot_leaves_its_owner_in_place() {
+        let mut table = SlotTable::new(1);
+        table.assign("a");
+
+        let (slot_id, owner) = table.lend();
+        assert_eq!(owner.as_deref(), Some("a"));
+        assert_eq!(table.owners(), vec![(slot_id, "a".to_string())]);
+        assert!(table.assign("a").reused);
+    }
+
+    #[test]
+    fn lends_the_least_recently_used_slot() {
+        let mut table = SlotTable::new(2);
+        table.assign("a");
+        tick();
+        let b = table.assign("b").slot_id;
+        tick();
+        table.assign("a");
+
+        assert_eq!(table.lend(), (b, Some("b".to_string())));
+    }
+
+    #[test]
+    fn release_only_drops_the_named_owner() {
+        let mut table = SlotTable::new(1);
+        let slot_id = table.assign("a").slot_id;
+
+        table.release(slot_id, "b");
+        assert_eq!(table.owners(), vec![(slot_id, "a".to_string())]);
+
+        table.release(slot_id, "a");
+        assert!(table.owners().is_empty());
+        assert!(!table.assign("a").reused);
+    }
+
+    #[test]
+    fn clear_forgets_every_owner() {
+        let mut table = SlotTable::new(2);
+        table.assign("a");
+        table.assign("b");
+
+        table.clear();
+        assert!(table.owners().is_empty());
+
+        let a = table.assign("a");
+        assert!(!a.reused);
+        assert_eq!(a.evicted, None);
+    }
+
+    #[test]
+    fn always_has_at_least_one_slot() {
+        let mut table = SlotTable::new(0);
+        assert_eq!(table.assign("a").slot_id, 0);
+    }
+}
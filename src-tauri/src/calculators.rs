@@ -0,0 +1,1125 @@
+use crate::tools::{Tool, ToolOutput, ToolSpec};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// Accepted units and the factor converting each to the unit the formulas
+// use, which comes first
+const WEIGHT_UNITS: &[(&str, f64)] = &[("kg", 1.0), ("g", 0.001), ("lb", 0.453_592_37)];
+const HEIGHT_UNITS: &[(&str, f64)] = &[("cm", 1.0), ("m", 100.0), ("in", 2.54)];
+const CREATININE_UNITS: &[(&str, f64)] = &[("mg/dL", 1.0), ("umol/L", 1.0 / 88.42)];
+// US labs report blood urea nitrogen in mg/dL, which is half the mass of
+// urea; the unit says so to rule out mixing the two up
+const UREA_UNITS: &[(&str, f64)] = &[("mmol/L", 1.0), ("mg/dL BUN", 0.357)];
+const DOSE_PER_KG_UNITS: &[(&str, f64)] = &[("mg/kg", 1.0), ("mcg/kg", 0.001)];
+const DOSE_UNITS: &[(&str, f64)] = &[("mg", 1.0), ("mcg", 0.001), ("g", 1000.0)];
+
+/// A measurement with the unit it was taken in. Units are checked and
+/// converted before any formula runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sex {
+    Female,
+    Male,
+}
+
+/// A calculator as offered to the frontend and the model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalculatorSpec {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments
+    pub parameters: serde_json::Value,
+}
+
+/// An input as it went into the calculation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalculationInput {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalculationResult {
+    pub calculator: String,
+    pub name: String,
+    pub value: f64,
+    pub unit: String,
+    /// The value rounded for display, with its unit
+    pub display: String,
+    pub interpretation: String,
+    pub inputs: Vec<CalculationInput>,
+}
+
+impl CalculationResult {
+    /// The value with its inputs on one line, for quoting.
+    pub fn summary(&self) -> String {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|input| format!("{}: {}", input.name, input.value))
+            .collect();
+        format!(
+            "{} = {} ({}). {}",
+            self.name,
+            self.display,
+            inputs.join("; "),
+            self.interpretation
+        )
+    }
+}
+
+impl Quantity {
+    /// The value in the first of `units`, or an error naming the accepted
+    /// units.
+    fn convert(&self, field: &str, units: &[(&str, f64)]) -> Result<f64> {
+        let unit = normalize_unit(&self.unit);
+        let factor = units
+            .iter()
+            .find(|(name, _)| normalize_unit(name) == unit)
+            .map(|(_, factor)| *factor)
+            .ok_or_else(|| {
+                let accepted: Vec<&str> = units.iter().map(|(name, _)| *name).collect();
+                anyhow!(
+                    "Unsupported unit '{}' for {}; use one of: {}",
+                    self.unit,
+                    field,
+                    accepted.join(", ")
+                )
+            })?;
+        if !self.value.is_finite() {
+            return Err(anyhow!("{} must be a number", field));
+        }
+        Ok(self.value * factor)
+    }
+
+    /// "176 lb (79.8 kg)", or just "80 kg" when no conversion was needed.
+    fn describe(&self, converted: f64, units: &[(&str, f64)]) -> String {
+        let base_unit = units[0].0;
+        if normalize_unit(&self.unit) == normalize_unit(base_unit) {
+            format!("{} {}", format_number(self.value, 2), base_unit)
+        } else {
+            format!(
+                "{} {} ({} {})",
+                format_number(self.value, 2),
+                self.unit,
+                format_number(converted, 1),
+                base_unit
+            )
+        }
+    }
+}
+
+fn normalize_unit(unit: &str) -> String {
+    unit.trim()
+        .to_lowercase()
+        .replace(['µ', 'μ'], "u")
+        .replace("ug", "mcg")
+        .replace(' ', "")
+}
+
+/// Rounds to at most `decimals` places and drops trailing zeros.
+fn format_number(value: f64, decimals: usize) -> String {
+    let formatted = format!("{:.*}", decimals, value);
+    if formatted.contains('.') {
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    } else {
+        formatted
+    }
+}
+
+fn check_range(field: &str, value: f64, min: f64, max: f64) -> Result<()> {
+    if !value.is_finite() || value < min || value > max {
+        return Err(anyhow!(
+            "{} of {} is outside the plausible range {} to {}",
+            field,
+            format_number(value, 2),
+            format_number(min, 2),
+            format_number(max, 2)
+        ));
+    }
+    Ok(())
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn labeled(name: &str, value: impl Into<String>) -> CalculationInput {
+    CalculationInput {
+        name: name.to_string(),
+        value: value.into(),
+    }
+}
+
+fn points(score: f64) -> String {
+    let score = format_number(score, 1);
+    if score == "1" {
+        "1 point".to_string()
+    } else {
+        format!("{} points", score)
+    }
+}
+
+fn quantity_schema(units: &[(&str, f64)]) -> serde_json::Value {
+    let units: Vec<&str> = units.iter().map(|(name, _)| *name).collect();
+    json!({
+        "type": "object",
+        "properties": {
+            "value": { "type": "number" },
+            "unit": { "type": "string", "enum": units }
+        },
+        "required": ["value", "unit"],
+        "additionalProperties": false
+    })
+}
+
+fn object_schema(properties: serde_json::Value, required: &[&str]) -> serde_json::Value {
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false
+    })
+}
+
+fn spec(id: &str, name: &str, description: &str, parameters: serde_json::Value) -> CalculatorSpec {
+    CalculatorSpec {
+        id: id.to_string(),
+        name: name.to_string(),
+        description: description.to_string(),
+        parameters,
+    }
+}
+
+/// Every calculator with the schema of its arguments.
+pub fn calculators() -> Vec<CalculatorSpec> {
+    let boolean = json!({ "type": "boolean" });
+    let number = json!({ "type": "number" });
+    let sex = json!({ "type": "string", "enum": ["female", "male"] });
+
+    vec![
+        spec(
+            "curb65",
+            "CURB-65",
+            "Severity of community-acquired pneumonia.",
+            object_schema(
+                json!({
+                    "confusion": boolean,
+                    "urea": quantity_schema(UREA_UNITS),
+                    "respiratory_rate": number,
+                    "systolic_bp": number,
+                    "diastolic_bp": number,
+                    "age": number
+                }),
+                &[
+                    "confusion",
+                    "urea",
+                    "respiratory_rate",
+                    "systolic_bp",
+                    "diastolic_bp",
+                    "age",
+                ],
+            ),
+        ),
+        spec(
+            "wells_pe",
+            "Wells score for pulmonary embolism",
+            "Pre-test probability of pulmonary embolism.",
+            object_schema(
+                json!({
+                    "clinical_signs_of_dvt": boolean,
+                    "pe_most_likely_diagnosis": boolean,
+                    "heart_rate": number,
+                    "immobilization_or_recent_surgery": boolean,
+                    "previous_dvt_or_pe": boolean,
+                    "hemoptysis": boolean,
+                    "malignancy": boolean
+                }),
+                &[
+                    "clinical_signs_of_dvt",
+                    "pe_most_likely_diagnosis",
+                    "heart_rate",
+                    "immobilization_or_recent_surgery",
+                    "previous_dvt_or_pe",
+                    "hemoptysis",
+                    "malignancy",
+                ],
+            ),
+        ),
+        spec(
+            "cha2ds2_vasc",
+            "CHA2DS2-VASc",
+            "Stroke risk in atrial fibrillation.",
+            object_schema(
+                json!({
+                    "congestive_heart_failure": boolean,
+                    "hypertension": boolean,
+                    "age": number,
+                    "diabetes": boolean,
+                    "stroke_or_tia": boolean,
+                    "vascular_disease": boolean,
+                    "sex": sex
+                }),
+                &[
+                    "congestive_heart_failure",
+                    "hypertension",
+                    "age",
+                    "diabetes",
+                    "stroke_or_tia",
+                    "vascular_disease",
+                    "sex",
+                ],
+            ),
+        ),
+        spec(
+            "gcs",
+            "Glasgow Coma Scale",
+            "Level of consciousness from eye (1-4), verbal (1-5) and motor (1-6) responses.",
+            object_schema(
+                json!({
+                    "eye": { "type": "integer", "minimum": 1, "maximum": 4 },
+                    "verbal": { "type": "integer", "minimum": 1, "maximum": 5 },
+                    "motor": { "type": "integer", "minimum": 1, "maximum": 6 }
+                }),
+                &["eye", "verbal", "motor"],
+            ),
+        ),
+        spec(
+            "creatinine_clearance",
+            "Creatinine clearance (Cockcroft-Gault)",
+            "Estimated creatinine clearance for renal dose adjustment.",
+            object_schema(
+                json!({
+                    "age": number,
+                    "weight": quantity_schema(WEIGHT_UNITS),
+                    "serum_creatinine": quantity_schema(CREATININE_UNITS),
+                    "sex": sex
+                }),
+                &["age", "weight", "serum_creatinine", "sex"],
+            ),
+        ),
+        spec(
+            "bmi",
+            "Body mass index",
+            "Body mass index with the WHO category.",
+            object_schema(
+                json!({
+                    "weight": quantity_schema(WEIGHT_UNITS),
+                    "height": quantity_schema(HEIGHT_UNITS)
+                }),
+                &["weight", "height"],
+            ),
+        ),
+        spec(
+            "bsa",
+            "Body surface area (Mosteller)",
+            "Body surface area for dosing.",
+            object_schema(
+                json!({
+                    "weight": quantity_schema(WEIGHT_UNITS),
+                    "height": quantity_schema(HEIGHT_UNITS)
+                }),
+                &["weight", "height"],
+            ),
+        ),
+        spec(
+            "pediatric_dose",
+            "Weight-based pediatric dose",
+            "Single dose from the child's weight and a per-kg dose, capped at the maximum dose.",
+            object_schema(
+                json!({
+                    "weight": quantity_schema(WEIGHT_UNITS),
+                    "dose_per_kg": quantity_schema(DOSE_PER_KG_UNITS),
+                    "doses_per_day": { "type": "integer", "minimum": 1, "maximum": 24 },
+                    "max_dose": quantity_schema(DOSE_UNITS)
+                }),
+                &["weight", "dose_per_kg"],
+            ),
+        ),
+    ]
+}
+
+/// Runs the calculator `id` on `arguments`, which must match its schema.
+pub fn calculate(id: &str, arguments: serde_json::Value) -> Result<CalculationResult> {
+    match id {
+        "curb65" => curb65(parse_arguments(id, arguments)?),
+        "wells_pe" => wells_pe(parse_arguments(id, arguments)?),
+        "cha2ds2_vasc" => cha2ds2_vasc(parse_arguments(id, arguments)?),
+        "gcs" => gcs(parse_arguments(id, arguments)?),
+        "creatinine_clearance" => creatinine_clearance(parse_arguments(id, arguments)?),
+        "bmi" => bmi(parse_arguments(id, arguments)?),
+        "bsa" => bsa(parse_arguments(id, arguments)?),
+        "pediatric_dose" => pediatric_dose(parse_arguments(id, arguments)?),
+        _ => Err(anyhow!("Unknown calculator: {}", id)),
+    }
+}
+
+/// Each calculator is a tool of the same name.
+#[async_trait]
+impl Tool for CalculatorSpec {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: self.id.clone(),
+            description: self.description.clone(),
+            parameters: self.parameters.clone(),
+        }
+    }
+
+    async fn call(&self, arguments: serde_json::Value) -> Result<ToolOutput> {
+        calculate(&self.id, arguments).map(ToolOutput::Calculation)
+    }
+}
+
+fn parse_arguments<T: DeserializeOwned>(id: &str, arguments: serde_json::Value) -> Result<T> {
+    serde_json::from_value(arguments).map_err(|e| anyhow!("Invalid arguments for {}: {}", id, e))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Curb65Input {
+    confusion: bool,
+    urea: Quantity,
+    respiratory_rate: f64,
+    systolic_bp: f64,
+    diastolic_bp: f64,
+    age: f64,
+}
+
+fn curb65(input: Curb65Input) -> Result<CalculationResult> {
+    let urea = input.urea.convert("urea", UREA_UNITS)?;
+    check_range("Urea", urea, 0.0, 150.0)?;
+    check_range("Respiratory rate", input.respiratory_rate, 0.0, 80.0)?;
+    check_range("Systolic BP", input.systolic_bp, 20.0, 300.0)?;
+    check_range("Diastolic BP", input.diastolic_bp, 10.0, 200.0)?;
+    check_range("Age", input.age, 0.0, 130.0)?;
+
+    let score = [
+        input.confusion,
+        urea > 7.0,
+        input.respiratory_rate >= 30.0,
+        input.systolic_bp < 90.0 || input.diastolic_bp <= 60.0,
+        input.age >= 65.0,
+    ]
+    .iter()
+    .filter(|criterion| **criterion)
+    .count() as f64;
+
+    let interpretation = match score as u32 {
+        0..=1 => "Low risk (30-day mortality under 3%); consider treatment at home.",
+        2 => "Moderate risk (30-day mortality about 9%); consider hospital admission.",
+        _ => "High risk (30-day mortality 15-40%); admit urgently and assess for intensive care at 4-5.",
+    };
+
+    Ok(CalculationResult {
+        calculator: "curb65".to_string(),
+        name: "CURB-65".to_string(),
+        value: score,
+        unit: "points".to_string(),
+        display: points(score),
+        interpretation: interpretation.to_string(),
+        inputs: vec![
+            labeled("Confusion", yes_no(input.confusion)),
+            labeled("Urea", input.urea.describe(urea, UREA_UNITS)),
+            labeled(
+                "Respiratory rate",
+                format!("{}/min", format_number(input.respiratory_rate, 0)),
+            ),
+            labeled(
+                "Blood pressure",
+                format!(
+                    "{}/{} mmHg",
+                    format_number(input.systolic_bp, 0),
+                    format_number(input.diastolic_bp, 0)
+                ),
+            ),
+            labeled("Age", format!("{} years", format_number(input.age, 0))),
+        ],
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WellsPeInput {
+    clinical_signs_of_dvt: bool,
+    pe_most_likely_diagnosis: bool,
+    heart_rate: f64,
+    immobilization_or_recent_surgery: bool,
+    previous_dvt_or_pe: bool,
+    hemoptysis: bool,
+    malignancy: bool,
+}
+
+fn wells_pe(input: WellsPeInput) -> Result<CalculationResult> {
+    check_range("Heart rate", input.heart_rate, 20.0, 300.0)?;
+
+    let score: f64 = [
+        (input.clinical_signs_of_dvt, 3.0),
+        (input.pe_most_likely_diagnosis, 3.0),
+        (input.heart_rate > 100.0, 1.5),
+        (input.immobilization_or_recent_surgery, 1.5),
+        (input.previous_dvt_or_pe, 1.5),
+        (input.hemoptysis, 1.0),
+        (input.malignancy, 1.0),
+    ]
+    .iter()
+    .filter(|(present, _)| *present)
+    .map(|(_, weight)| weight)
+    .sum();
+
+    let interpretation = if score > 4.0 {
+        "PE likely; arrange CT pulmonary angiography, and anticoagulate while waiting unless contraindicated."
+    } else {
+        "PE unlikely; check a D-dimer and image only if it is raised."
+    };
+
+    Ok(CalculationResult {
+        calculator: "wells_pe".to_string(),
+        name: "Wells score for PE".to_string(),
+        value: score,
+        unit: "points".to_string(),
+        display: points(score),
+        interpretation: interpretation.to_string(),
+        inputs: vec![
+            labeled("Clinical signs of DVT", yes_no(input.clinical_signs_of_dvt)),
+            labeled(
+                "PE most likely diagnosis",
+                yes_no(input.pe_most_likely_diagnosis),
+            ),
+            labeled(
+                "Heart rate",
+                format!("{}/min", format_number(input.heart_rate, 0)),
+            ),
+            labeled(
+                "Immobilization or recent surgery",
+                yes_no(input.immobilization_or_recent_surgery),
+            ),
+            labeled("Previous DVT or PE", yes_no(input.previous_dvt_or_pe)),
+            labeled("Hemoptysis", yes_no(input.hemoptysis)),
+            labeled("Malignancy", yes_no(input.malignancy)),
+        ],
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Cha2ds2VascInput {
+    congestive_heart_failure: bool,
+    hypertension: bool,
+    age: f64,
+    diabetes: bool,
+    stroke_or_tia: bool,
+    vascular_disease: bool,
+    sex: Sex,
+}
+
+fn cha2ds2_vasc(input: Cha2ds2VascInput) -> Result<CalculationResult> {
+    check_range("Age", input.age, 0.0, 130.0)?;
+
+    let age_points = if input.age >= 75.0 {
+        2.0
+    } else if input.age >= 65.0 {
+        1.0
+    } else {
+        0.0
+    };
+    let score: f64 = [
+        (input.congestive_heart_failure, 1.0),
+        (input.hypertension, 1.0),
+        (input.diabetes, 1.0),
+        (input.stroke_or_tia, 2.0),
+        (input.vascular_disease, 1.0),
+        (input.sex == Sex::Female, 1.0),
+    ]
+    .iter()
+    .filter(|(present, _)| *present)
+    .map(|(_, weight)| weight)
+    .sum::<f64>()
+        + age_points;
+
+    // Female sex alone only adds risk alongside another factor
+    let other_risk = match input.sex {
+        Sex::Female => score - 1.0,
+        Sex::Male => score,
+    };
+    let interpretation = match other_risk as u32 {
+        0 => "Low risk; anticoagulation not recommended.",
+        1 => "Consider anticoagulation, weighing bleeding risk.",
+        _ => "Anticoagulation recommended unless contraindicated.",
+    };
+
+    Ok(CalculationResult {
+        calculator: "cha2ds2_vasc".to_string(),
+        name: "CHA2DS2-VASc".to_string(),
+        value: score,
+        unit: "points".to_string(),
+        display: points(score),
+        interpretation: interpretation.to_string(),
+        inputs: vec![
+            labeled(
+                "Congestive heart failure",
+                yes_no(input.congestive_heart_failure),
+            ),
+            labeled("Hypertension", yes_no(input.hypertension)),
+            labeled("Age", format!("{} years", format_number(input.age, 0))),
+            labeled("Diabetes", yes_no(input.diabetes)),
+            labeled(
+                "Stroke, TIA or thromboembolism",
+                yes_no(input.stroke_or_tia),
+            ),
+            labeled("Vascular disease", yes_no(input.vascular_disease)),
+            labeled(
+                "Sex",
+                match input.sex {
+                    Sex::Female => "female",
+                    Sex::Male => "male",
+                },
+            ),
+        ],
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GcsInput {
+    eye: u8,
+    verbal: u8,
+    motor: u8,
+}
+
+fn gcs(input: GcsInput) -> Result<CalculationResult> {
+    check_range("Eye response", input.eye as f64, 1.0, 4.0)?;
+    check_range("Verbal response", input.verbal as f64, 1.0, 5.0)?;
+    check_range("Motor response", input.motor as f64, 1.0, 6.0)?;
+
+    let score = (input.eye + input.verbal + input.motor) as f64;
+    let interpretation = match score as u32 {
+        13..=15 => "Mild brain injury.",
+        9..=12 => "Moderate brain injury.",
+        _ => "Severe brain injury; protect the airway (GCS 8 or below).",
+    };
+
+    Ok(CalculationResult {
+        calculator: "gcs".to_string(),
+        name: "Glasgow Coma Scale".to_string(),
+        value: score,
+        unit: "points".to_string(),
+        display: format!(
+            "{} (E{} V{} M{})",
+            format_number(score, 0),
+            input.eye,
+            input.verbal,
+            input.motor
+        ),
+        interpretation: interpretation.to_string(),
+        inputs: vec![
+            labeled("Eye", input.eye.to_string()),
+            labeled("Verbal", input.verbal.to_string()),
+            labeled("Motor", input.motor.to_string()),
+        ],
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CreatinineClearanceInput {
+    age: f64,
+    weight: Quantity,
+    serum_creatinine: Quantity,
+    sex: Sex,
+}
+
+fn creatinine_clearance(input: CreatinineClearanceInput) -> Result<CalculationResult> {
+    let weight = input.weight.convert("weight", WEIGHT_UNITS)?;
+    let creatinine = input
+        .serum_creatinine
+        .convert("serum creatinine", CREATININE_UNITS)?;
+    check_range("Age", input.age, 18.0, 130.0)?;
+    check_range("Weight", weight, 20.0, 400.0)?;
+    check_range("Serum creatinine", creatinine, 0.1, 30.0)?;
+
+    let sex_factor = match input.sex {
+        Sex::Female => 0.85,
+        Sex::Male => 1.0,
+    };
+    let clearance = (140.0 - input.age) * weight / (72.0 * creatinine) * sex_factor;
+
+    let interpretation = if clearance >= 90.0 {
+        "Normal clearance."
+    } else if clearance >= 60.0 {
+        "Mildly reduced clearance."
+    } else if clearance >= 30.0 {
+        "Moderately reduced clearance; check renal dose adjustments."
+    } else if clearance >= 15.0 {
+        "Severely reduced clearance; many drugs need dose reduction or avoidance."
+    } else {
+        "Kidney failure range; avoid renally cleared drugs where possible."
+    };
+
+    Ok(CalculationResult {
+        calculator: "creatinine_clearance".to_string(),
+        name: "Creatinine clearance (Cockcroft-Gault)".to_string(),
+        value: clearance,
+        unit: "mL/min".to_string(),
+        display: format!("{} mL/min", format_number(clearance, 1)),
+        interpretation: format!(
+            "{} Uses actual body weight; consider ideal weight in obesity.",
+            interpretation
+        ),
+        inputs: vec![
+            labeled("Age", format!("{} years", format_number(input.age, 0))),
+            labeled("Weight", input.weight.describe(weight, WEIGHT_UNITS)),
+            labeled(
+                "Serum creatinine",
+                input
+                    .serum_creatinine
+                    .describe(creatinine, CREATININE_UNITS),
+            ),
+            labeled(
+                "Sex",
+                match input.sex {
+                    Sex::Female => "female",
+                    Sex::Male => "male",
+                },
+            ),
+        ],
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BodySizeInput {
+    weight: Quantity,
+    height: Quantity,
+}
+
+impl BodySizeInput {
+    /// Weight in kg and height in cm.
+    fn convert(&self) -> Result<(f64, f64)> {
+        let weight = self.weight.convert("weight", WEIGHT_UNITS)?;
+        let height = self.height.convert("height", HEIGHT_UNITS)?;
+        check_range("Weight", weight, 0.3, 400.0)?;
+        check_range("Height", height, 20.0, 250.0)?;
+        Ok((weight, height))
+    }
+
+    fn inputs(&self, weight: f64, height: f64) -> Vec<CalculationInput> {
+        vec![
+            labeled("Weight", self.weight.describe(weight, WEIGHT_UNITS)),
+            labeled("Height", self.height.describe(height, HEIGHT_UNITS)),
+        ]
+    }
+}
+
+fn bmi(input: BodySizeInput) -> Result<CalculationResult> {
+    let (weight, height) = input.convert()?;
+    let bmi = weight / (height / 100.0).powi(2);
+
+    let interpretation = if bmi < 18.5 {
+        "Underweight (adult WHO category)."
+    } else if bmi < 25.0 {
+        "Normal weight (adult WHO category)."
+    } else if bmi < 30.0 {
+        "Overweight (adult WHO category)."
+    } else {
+        "Obese (adult WHO category)."
+    };
+
+    Ok(CalculationResult {
+        calculator: "bmi".to_string(),
+        name: "Body mass index".to_string(),
+        value: bmi,
+        unit: "kg/m²".to_string(),
+        display: format!("{} kg/m²", format_number(bmi, 1)),
+        interpretation: format!("{} Use BMI-for-age charts for children.", interpretation),
+        inputs: input.inputs(weight, height),
+    })
+}
+
+fn bsa(input: BodySizeInput) -> Result<CalculationResult> {
+    let (weight, height) = input.convert()?;
+    let bsa = (height * weight / 3600.0).sqrt();
+
+    Ok(CalculationResult {
+        calculator: "bsa".to_string(),
+        name: "Body surface area (Mosteller)".to_string(),
+        value: bsa,
+        unit: "m²".to_string(),
+        display: format!("{} m²", format_number(bsa, 2)),
+        interpretation: "Mosteller formula.".to_string(),
+        inputs: input.inputs(weight, height),
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PediatricDoseInput {
+    weight: Quantity,
+    dose_per_kg: Quantity,
+    #[serde(default)]
+    doses_per_day: Option<u32>,
+    #[serde(default)]
+    max_dose: Option<Quantity>,
+}
+
+fn pediatric_dose(input: PediatricDoseInput) -> Result<CalculationResult> {
+    let weight = input.weight.convert("weight", WEIGHT_UNITS)?;
+    let dose_per_kg = input
+        .dose_per_kg
+        .convert("dose per kg", DOSE_PER_KG_UNITS)?;
+    check_range("Weight", weight, 0.3, 150.0)?;
+    check_range("Dose per kg", dose_per_kg, 0.000_001, 1000.0)?;
+    if let Some(doses_per_day) = input.doses_per_day {
+        check_range("Doses per day", doses_per_day as f64, 1.0, 24.0)?;
+    }
+    let max_dose = input
+        .max_dose
+        .as_ref()
+        .map(|max_dose| max_dose.convert("maximum dose", DOSE_UNITS))
+        .transpose()?;
+    if let Some(max_dose) = max_dose {
+        check_range("Maximum dose", max_dose, 0.001, 10_000.0)?;
+    }
+
+    let calculated = weight * dose_per_kg;
+    let dose = match max_dose {
+        Some(max_dose) => calculated.min(max_dose),
+        None => calculated,
+    };
+
+    let mut interpretation = format!("Give {} mg per dose", format_number(dose, 2));
+    if let Some(doses_per_day) = input.doses_per_day {
+        interpretation.push_str(&format!(
+            ", {} times a day ({} mg/day)",
+            doses_per_day,
+            format_number(dose * doses_per_day as f64, 2)
+        ));
+    }
+    interpretation.push('.');
+    if dose < calculated {
+        interpretation.push_str(&format!(
+            " Capped at the maximum dose; the weight-based dose would be {} mg.",
+            format_number(calculated, 2)
+        ));
+    }
+    if weight > 40.0 {
+        interpretation.push_str(" Over 40 kg adult dosing may apply.");
+    }
+
+    let mut inputs = vec![
+        labeled("Weight", input.weight.describe(weight, WEIGHT_UNITS)),
+        labeled(
+            "Dose per kg",
+            input.dose_per_kg.describe(dose_per_kg, DOSE_PER_KG_UNITS),
+        ),
+    ];
+    if let Some(doses_per_day) = input.doses_per_day {
+        inputs.push(labeled("Doses per day", doses_per_day.to_string()));
+    }
+    if let (Some(max_dose), Some(converted)) = (&input.max_dose, max_dose) {
+        inputs.push(labeled(
+            "Maximum dose",
+            max_dose.describe(converted, DOSE_UNITS),
+        ));
+    }
+
+    Ok(CalculationResult {
+        calculator: "pediatric_dose".to_string(),
+        name: "Weight-based dose".to_string(),
+        value: dose,
+        unit: "mg".to_string(),
+        display: format!("{} mg", format_number(dose, 2)),
+        interpretation,
+        inputs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(id: &str, arguments: serde_json::Value) -> f64 {
+        calculate(id, arguments).unwrap().value
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 0.05,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    // Lim et al., Thorax 2003: one point each for confusion, urea > 7
+    // mmol/L, respiratory rate >= 30, SBP < 90 or DBP <= 60, age >= 65
+    #[test]
+    fn curb65_scores_each_criterion() {
+        let curb65 = |urea: serde_json::Value, age: f64| {
+            value(
+                "curb65",
+                json!({
+                    "confusion": true,
+                    "urea": urea,
+                    "respiratory_rate": 32,
+                    "systolic_bp": 85,
+                    "diastolic_bp": 50,
+                    "age": age
+                }),
+            )
+        };
+
+        assert_eq!(curb65(json!({ "value": 8, "unit": "mmol/L" }), 70.0), 5.0);
+        assert_eq!(curb65(json!({ "value": 7, "unit": "mmol/L" }), 64.0), 3.0);
+        // The US cut-off is BUN > 19 mg/dL
+        assert_eq!(
+            curb65(json!({ "value": 20, "unit": "mg/dL BUN" }), 70.0),
+            5.0
+        );
+        assert_eq!(
+            curb65(json!({ "value": 19, "unit": "mg/dL BUN" }), 70.0),
+            4.0
+        );
+    }
+
+    #[test]
+    fn curb65_rejects_urea_in_plain_mg_per_dl() {
+        let error = calculate(
+            "curb65",
+            json!({
+                "confusion": false,
+                "urea": { "value": 20, "unit": "mg/dL" },
+                "respiratory_rate": 16,
+                "systolic_bp": 120,
+                "diastolic_bp": 80,
+                "age": 50
+            }),
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("mg/dL BUN"));
+    }
+
+    // Wells et al., Thromb Haemost 2000: > 4 points makes PE likely
+    #[test]
+    fn wells_pe_adds_weighted_criteria() {
+        let wells = |dvt: bool, heart_rate: f64, all_others: bool| {
+            calculate(
+                "wells_pe",
+                json!({
+                    "clinical_signs_of_dvt": dvt,
+                    "pe_most_likely_diagnosis": all_others,
+                    "heart_rate": heart_rate,
+                    "immobilization_or_recent_surgery": all_others,
+                    "previous_dvt_or_pe": all_others,
+                    "hemoptysis": all_others,
+                    "malignancy": all_others
+                }),
+            )
+            .unwrap()
+        };
+
+        let likely = wells(true, 110.0, false);
+        assert_eq!(likely.value, 4.5);
+        assert_eq!(likely.display, "4.5 points");
+        assert!(likely.interpretation.starts_with("PE likely"));
+
+        let unlikely = wells(true, 100.0, false);
+        assert_eq!(unlikely.value, 3.0);
+        assert!(unlikely.interpretation.starts_with("PE unlikely"));
+
+        assert_eq!(wells(true, 120.0, true).value, 12.5);
+    }
+
+    // Lip et al., Chest 2010
+    #[test]
+    fn cha2ds2_vasc_counts_age_and_sex() {
+        let score = |age: f64, sex: &str, risk_factors: bool| {
+            calculate(
+                "cha2ds2_vasc",
+                json!({
+                    "congestive_heart_failure": false,
+                    "hypertension": risk_factors,
+                    "age": age,
+                    "diabetes": risk_factors,
+                    "stroke_or_tia": false,
+                    "vascular_disease": false,
+                    "sex": sex
+                }),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(score(76.0, "female", true).value, 5.0);
+        assert_eq!(score(67.0, "male", false).value, 1.0);
+
+        // Female sex on its own doesn't call for anticoagulation
+        let female_only = score(60.0, "female", false);
+        assert_eq!(female_only.value, 1.0);
+        assert!(female_only.interpretation.starts_with("Low risk"));
+
+        let all = calculate(
+            "cha2ds2_vasc",
+            json!({
+                "congestive_heart_failure": true,
+                "hypertension": true,
+                "age": 80,
+                "diabetes": true,
+                "stroke_or_tia": true,
+                "vascular_disease": true,
+                "sex": "female"
+            }),
+        )
+        .unwrap();
+        assert_eq!(all.value, 9.0);
+    }
+
+    // Teasdale and Jennett, Lancet 1974
+    #[test]
+    fn gcs_sums_the_components() {
+        let gcs = |eye: u8, verbal: u8, motor: u8| {
+            calculate(
+                "gcs",
+                json!({ "eye": eye, "verbal": verbal, "motor": motor }),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(gcs(4, 5, 6).value, 15.0);
+        assert_eq!(gcs(1, 1, 1).value, 3.0);
+
+        let severe = gcs(2, 2, 4);
+        assert_eq!(severe.display, "8 (E2 V2 M4)");
+        assert!(severe.interpretation.starts_with("Severe"));
+
+        assert!(calculate("gcs", json!({ "eye": 5, "verbal": 5, "motor": 6 })).is_err());
+    }
+
+    // Cockcroft and Gault, Nephron 1976: (140 - age) x weight / (72 x SCr),
+    // x 0.85 for women
+    #[test]
+    fn creatinine_clearance_follows_cockcroft_gault() {
+        let clearance = |creatinine: serde_json::Value, sex: &str| {
+            value(
+                "creatinine_clearance",
+                json!({
+                    "age": 60,
+                    "weight": { "value": 72, "unit": "kg" },
+                    "serum_creatinine": creatinine,
+                    "sex": sex
+                }),
+            )
+        };
+
+        assert_close(
+            clearance(json!({ "value": 1.0, "unit": "mg/dL" }), "male"),
+            80.0,
+        );
+        assert_close(
+            clearance(json!({ "value": 1.0, "unit": "mg/dL" }), "female"),
+            68.0,
+        );
+        assert_close(
+            clearance(json!({ "value": 88.42, "unit": "umol/L" }), "male"),
+            80.0,
+        );
+    }
+
+    #[test]
+    fn bmi_and_bsa_convert_units() {
+        let body = |weight: serde_json::Value, height: serde_json::Value| json!({ "weight": weight, "height": height });
+
+        // WHO: 70 kg at 1.75 m is 22.9 kg/m², normal weight
+        let bmi = calculate(
+            "bmi",
+            body(
+                json!({ "value": 70, "unit": "kg" }),
+                json!({ "value": 1.75, "unit": "m" }),
+            ),
+        )
+        .unwrap();
+        assert_close(bmi.value, 22.9);
+        assert!(bmi.interpretation.starts_with("Normal weight"));
+
+        // Mosteller, NEJM 1987: sqrt(height cm x weight kg / 3600)
+        assert_close(
+            value(
+                "bsa",
+                body(
+                    json!({ "value": 80, "unit": "kg" }),
+                    json!({ "value": 180, "unit": "cm" }),
+                ),
+            ),
+            2.0,
+        );
+        assert_close(
+            value(
+                "bsa",
+                body(
+                    json!({ "value": 176.37, "unit": "lb" }),
+                    json!({ "value": 70.87, "unit": "in" }),
+                ),
+            ),
+            2.0,
+        );
+    }
+
+    #[test]
+    fn pediatric_dose_is_capped_at_the_maximum() {
+        // Amoxicillin 15 mg/kg three times a day for a 20 kg child
+        let dose = calculate(
+            "pediatric_dose",
+            json!({
+                "weight": { "value": 20, "unit": "kg" },
+                "dose_per_kg": { "value": 15, "unit": "mg/kg" },
+                "doses_per_day": 3
+            }),
+        )
+        .unwrap();
+        assert_eq!(dose.value, 300.0);
+        assert!(dose.interpretation.contains("900 mg/day"));
+
+        // Ibuprofen 10 mg/kg for a 45 kg child, at most 400 mg per dose
+        let capped = calculate(
+            "pediatric_dose",
+            json!({
+                "weight": { "value": 45, "unit": "kg" },
+                "dose_per_kg": { "value": 10, "unit": "mg/kg" },
+                "max_dose": { "value": 0.4, "unit": "g" }
+            }),
+        )
+        .unwrap();
+        assert_eq!(capped.value, 400.0);
+        assert!(capped.interpretation.contains("would be 450 mg"));
+    }
+
+    #[test]
+    fn pediatric_dose_rejects_an_implausible_maximum() {
+        for max_dose in [
+            json!({ "value": 0, "unit": "mg" }),
+            json!({ "value": 50, "unit": "g" }),
+        ] {
+            let result = calculate(
+                "pediatric_dose",
+                json!({
+                    "weight": { "value": 20, "unit": "kg" },
+                    "dose_per_kg": { "value": 15, "unit": "mg/kg" },
+                    "max_dose": max_dose
+                }),
+            );
+            assert!(result.is_err());
+        }
+    }
+}
@@ -1,8 +1,14 @@
 use crate::ai_engine::ChatMessage;
+use crate::differential::DifferentialRecord;
+use crate::knowledge::{KnowledgeChunk, KnowledgeDocument};
+use crate::profiles::{builtin_profiles, PromptProfile, DEFAULT_PROFILE_ID};
+use crate::settings::{EngineConfig, GenerationSettings, ReplySettings};
+use crate::soap_note::SoapNoteRecord;
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sled::{Db, IVec};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use uuid::Uuid;
 
@@ -14,6 +20,40 @@ pub struct Conversation {
     pub updated_at: DateTime<Utc>,
 }
 
+/// A message on the conversation's active branch, with its place among the
+/// messages that share its parent (alternative answers or edited questions).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationMessage {
+    #[serde(flatten)]
+    pub message: ChatMessage,
+    pub sibling_index: usize,
+    pub sibling_count: usize,
+}
+
+/// Condensed account of the older part of a conversation, so the model keeps
+/// the patient's history once those turns are left out of the prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    pub conversation_id: String,
+    pub content: String,
+    /// Messages up to and including this time are covered by the summary
+    pub covers_until: DateTime<Utc>,
+    /// The last message covered. The summary only applies to branches that
+    /// run through it.
+    #[serde(default)]
+    pub covers_message_id: Option<String>,
+    pub message_count: usize,
+    pub updated_at: DateTime<Utc>,
+}
+
+const SCHEMA_VERSION_KEY: &str = "meta:schema_version";
+// 1: every message but the first of each branch has a parent
+const SCHEMA_VERSION: u32 = 1;
+
+// Stands in for the parent of a conversation's first messages in
+// `active_child` keys
+const ROOT_PARENT: &str = "root";
+
 #[derive(Clone)]
 pub struct Database {
     db: Db,
@@ -22,7 +62,43 @@ pub struct Database {
 impl Database {
     pub fn new(db_path: PathBuf) -> Result<Self> {
         let db = sled::open(db_path)?;
-        Ok(Database { db })
+        let database = Database { db };
+        database.migrate()?;
+        Ok(database)
+    }
+
+    fn migrate(&self) -> Result<()> {
+        let version = match self.db.get(SCHEMA_VERSION_KEY)? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => 0u32,
+        };
+
+        if version < 1 {
+            for conversation in self.get_conversations()? {
+                self.link_legacy_messages(&conversation.id)?;
+            }
+        }
+
+        self.db
+            .insert(SCHEMA_VERSION_KEY, serde_json::to_vec(&SCHEMA_VERSION)?)?;
+        Ok(())
+    }
+
+    /// Messages used to be a flat, timestamp-ordered list. Chains each one
+    /// to the message before it, so the list becomes a single branch.
+    fn link_legacy_messages(&self, conversation_id: &str) -> Result<()> {
+        let mut previous_id: Option<String> = None;
+
+        for mut message in self.get_conversation_messages(conversation_id)? {
+            if previous_id.is_some() {
+                message.parent_id = previous_id;
+                let key = format!("message:{}:{}", conversation_id, message.id);
+                self.db.insert(key, serde_json::to_vec(&message)?)?;
+            }
+            previous_id = Some(message.id);
+        }
+
+        Ok(())
     }
 
     pub fn create_conversation(&self, title: &str) -> Result<String> {
@@ -58,7 +134,15 @@ impl Database {
         Ok(conversations)
     }
 
-    pub fn add_message(&self, conversation_id: &str, role: &str, content: &str) -> Result<String> {
+    /// Stores a new message below `parent_id` (`None` to start the
+    /// conversation) and makes it the active branch there.
+    pub fn add_message(
+        &self,
+        conversation_id: &str,
+        role: &str,
+        content: &str,
+        parent_id: Option<&str>,
+    ) -> Result<String> {
         let message_id = Uuid::new_v4().to_string();
         let now = Utc::now();
 
@@ -67,16 +151,29 @@ impl Database {
             role: role.to_string(),
             content: content.to_string(),
             timestamp: now,
+            cancelled: false,
+            parent_id: parent_id.map(String::from),
+            citations: Vec::new(),
+            tool_call: None,
         };
 
-        let key = format!("message:{}:{}", conversation_id, message_id);
-        let value = serde_json::to_vec(&message)?;
+        self.insert_message(conversation_id, &message)?;
+        self.set_active_message(conversation_id, &message_id)?;
+
+        Ok(message_id)
+    }
+
+    /// Stores a fully built message, e.g. one whose id was already handed to
+    /// the frontend while the reply was being streamed.
+    pub fn insert_message(&self, conversation_id: &str, message: &ChatMessage) -> Result<()> {
+        let key = format!("message:{}:{}", conversation_id, message.id);
+        let value = serde_json::to_vec(message)?;
         self.db.insert(key, value)?;
 
         // Update conversation's updated_at timestamp
         self.update_conversation_timestamp(conversation_id)?;
 
-        Ok(message_id)
+        Ok(())
     }
 
     pub fn get_conversation_messages(&self, conversation_id: &str) -> Result<Vec<ChatMessage>> {
@@ -95,6 +192,122 @@ impl Database {
         Ok(messages)
     }
 
+    pub fn get_message(
+        &self,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Option<ChatMessage>> {
+        let key = format!("message:{}:{}", conversation_id, message_id);
+
+        if let Some(value) = self.db.get(&key)? {
+            Ok(Some(serde_json::from_slice(&value)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The branch of the conversation the user is currently on: from the
+    /// first message, following the selected child of each message (the
+    /// newest one when nothing was selected). Tool messages come right
+    /// before the reply they were made for.
+    pub fn get_active_messages(&self, conversation_id: &str) -> Result<Vec<ConversationMessage>> {
+        let messages = self.get_conversation_messages(conversation_id)?;
+
+        // Children of each message, oldest first. Tool messages aren't
+        // alternatives to anything, so they're kept apart by reply.
+        let mut children: HashMap<Option<&str>, Vec<&ChatMessage>> = HashMap::new();
+        let mut tool_messages: HashMap<&str, Vec<&ChatMessage>> = HashMap::new();
+        for message in &messages {
+            match (message.role.as_str(), message.parent_id.as_deref()) {
+                ("tool", Some(reply_id)) => {
+                    tool_messages.entry(reply_id).or_default().push(message)
+                }
+                (_, parent_id) => children.entry(parent_id).or_default().push(message),
+            }
+        }
+
+        let mut active = Vec::new();
+        let mut parent_id: Option<&str> = None;
+
+        while let Some(siblings) = children.get(&parent_id) {
+            let selected = self.get_active_child(conversation_id, parent_id)?;
+            let sibling_index = selected
+                .and_then(|id| siblings.iter().position(|sibling| sibling.id == id))
+                .unwrap_or(siblings.len() - 1);
+
+            let message = siblings[sibling_index];
+            for tool_message in tool_messages.get(message.id.as_str()).into_iter().flatten() {
+                active.push(ConversationMessage {
+                    message: (*tool_message).clone(),
+                    sibling_index: 0,
+                    sibling_count: 1,
+                });
+            }
+            active.push(ConversationMessage {
+                message: message.clone(),
+                sibling_index,
+                sibling_count: siblings.len(),
+            });
+            parent_id = Some(&message.id);
+        }
+
+        Ok(active)
+    }
+
+    /// A message and its siblings (including itself), oldest first: the
+    /// alternative answers to a question, or the edited versions of a
+    /// question.
+    pub fn get_message_siblings(
+        &self,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Vec<ChatMessage>> {
+        let message = self
+            .get_message(conversation_id, message_id)?
+            .ok_or_else(|| anyhow!("Message not found"))?;
+
+        Ok(self
+            .get_conversation_messages(conversation_id)?
+            .into_iter()
+            .filter(|sibling| sibling.parent_id == message.parent_id && sibling.role != "tool")
+            .collect())
+    }
+
+    fn get_active_child(
+        &self,
+        conversation_id: &str,
+        parent_id: Option<&str>,
+    ) -> Result<Option<String>> {
+        let key = format!(
+            "active_child:{}:{}",
+            conversation_id,
+            parent_id.unwrap_or(ROOT_PARENT)
+        );
+
+        if let Some(value) = self.db.get(&key)? {
+            Ok(Some(String::from_utf8(value.to_vec())?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Switches to the branch through `message_id`: it becomes the child
+    /// shown (and sent as context) below its parent.
+    pub fn set_active_message(&self, conversation_id: &str, message_id: &str) -> Result<()> {
+        let message = self
+            .get_message(conversation_id, message_id)?
+            .ok_or_else(|| anyhow!("Message not found"))?;
+
+        let key = format!(
+            "active_child:{}:{}",
+            conversation_id,
+            message.parent_id.as_deref().unwrap_or(ROOT_PARENT)
+        );
+        self.db.insert(key, message_id.as_bytes())?;
+
+        Ok(())
+    }
+
     pub fn delete_conversation(&self, conversation_id: &str) -> Result<()> {
         // Delete the conversation
         let conv_key = format!("conversation:{}", conversation_id);
@@ -112,6 +325,39 @@ impl Database {
             self.db.remove(key)?;
         }
 
+        // Delete which alternatives were selected
+        let selection_prefix = format!("active_child:{}:", conversation_id);
+        let keys_to_delete: Vec<IVec> = self
+            .db
+            .scan_prefix(&selection_prefix)
+            .map(|result| result.map(|(key, _)| key))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for key in keys_to_delete {
+            self.db.remove(key)?;
+        }
+
+        // Delete the notes and differentials made from it
+        for prefix in ["soap_note", "differential"] {
+            let record_prefix = format!("{}:{}:", prefix, conversation_id);
+            let keys_to_delete: Vec<IVec> = self
+                .db
+                .scan_prefix(&record_prefix)
+                .map(|result| result.map(|(key, _)| key))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for key in keys_to_delete {
+                self.db.remove(key)?;
+            }
+        }
+
+        // Delete the conversation's settings override, summary and profile choice
+        self.db
+            .remove(format!("generation_settings:{}", conversation_id))?;
+        self.db.remove(format!("summary:{}", conversation_id))?;
+        self.db
+            .remove(format!("conversation_profile:{}", conversation_id))?;
+
         Ok(())
     }
 
@@ -157,6 +403,333 @@ impl Database {
         }
     }
 
+    pub fn get_conversation_summary(
+        &self,
+        conversation_id: &str,
+    ) -> Result<Option<ConversationSummary>> {
+        let key = format!("summary:{}", conversation_id);
+
+        if let Some(value) = self.db.get(&key)? {
+            Ok(Some(serde_json::from_slice(&value)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn save_conversation_summary(&self, summary: &ConversationSummary) -> Result<()> {
+        let key = format!("summary:{}", summary.conversation_id);
+        let value = serde_json::to_vec(summary)?;
+        self.db.insert(key, value)?;
+        Ok(())
+    }
+
+    /// SOAP notes written from a conversation, oldest first.
+    pub fn get_soap_notes(&self, conversation_id: &str) -> Result<Vec<SoapNoteRecord>> {
+        let prefix = format!("soap_note:{}:", conversation_id);
+        let mut notes = Vec::new();
+
+        for result in self.db.scan_prefix(&prefix) {
+            let (_key, value) = result?;
+            let note: SoapNoteRecord = serde_json::from_slice(&value)?;
+            notes.push(note);
+        }
+
+        notes.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+        Ok(notes)
+    }
+
+    pub fn save_soap_note(&self, note: &SoapNoteRecord) -> Result<()> {
+        let key = format!("soap_note:{}:{}", note.conversation_id, note.id);
+        let value = serde_json::to_vec(note)?;
+        self.db.insert(key, value)?;
+        Ok(())
+    }
+
+    /// Differentials made from a conversation, oldest first.
+    pub fn get_differentials(&self, conversation_id: &str) -> Result<Vec<DifferentialRecord>> {
+        let prefix = format!("differential:{}:", conversation_id);
+        let mut differentials = Vec::new();
+
+        for result in self.db.scan_prefix(&prefix) {
+            let (_key, value) = result?;
+            let differential: DifferentialRecord = serde_json::from_slice(&value)?;
+            differentials.push(differential);
+        }
+
+        differentials.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+        Ok(differentials)
+    }
+
+    pub fn save_differential(&self, differential: &DifferentialRecord) -> Result<()> {
+        let key = format!(
+            "differential:{}:{}",
+            differential.conversation_id, differential.id
+        );
+        let value = serde_json::to_vec(differential)?;
+        self.db.insert(key, value)?;
+        Ok(())
+    }
+
+    /// Documents in the knowledge base, in the order they were added.
+    pub fn get_knowledge_documents(&self) -> Result<Vec<KnowledgeDocument>> {
+        let mut documents = Vec::new();
+
+        for result in self.db.scan_prefix("kb_document:") {
+            let (_key, value) = result?;
+            let document: KnowledgeDocument = serde_json::from_slice(&value)?;
+            documents.push(document);
+        }
+
+        documents.sort_by(|a, b| a.added_at.cmp(&b.added_at));
+
+        Ok(documents)
+    }
+
+    /// Stores a document and its chunks in one go, so a failed import leaves
+    /// nothing half-written.
+    pub fn save_knowledge_document(
+        &self,
+        document: &KnowledgeDocument,
+        chunks: &[KnowledgeChunk],
+    ) -> Result<()> {
+        let mut batch = sled::Batch::default();
+
+        for chunk in chunks {
+            // Zero-padded so chunks scan in document order
+            let key = format!("kb_chunk:{}:{:06}", document.id, chunk.index);
+            batch.insert(key.as_bytes(), serde_json::to_vec(chunk)?);
+        }
+        let key = format!("kb_document:{}", document.id);
+        batch.insert(key.as_bytes(), serde_json::to_vec(document)?);
+
+        self.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    pub fn get_knowledge_chunks(&self, document_id: &str) -> Result<Vec<KnowledgeChunk>> {
+        let prefix = format!("kb_chunk:{}:", document_id);
+        let mut chunks = Vec::new();
+
+        for result in self.db.scan_prefix(&prefix) {
+            let (_key, value) = result?;
+            let chunk: KnowledgeChunk = serde_json::from_slice(&value)?;
+            chunks.push(chunk);
+        }
+
+        Ok(chunks)
+    }
+
+    pub fn delete_knowledge_document(&self, document_id: &str) -> Result<()> {
+        self.db.remove(format!("kb_document:{}", document_id))?;
+
+        let chunk_prefix = format!("kb_chunk:{}:", document_id);
+        let keys_to_delete: Vec<IVec> = self
+            .db
+            .scan_prefix(&chunk_prefix)
+            .map(|result| result.map(|(key, _)| key))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for key in keys_to_delete {
+            self.db.remove(key)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_default_generation_settings(&self) -> Result<GenerationSettings> {
+        if let Some(value) = self.db.get("settings:generation")? {
+            Ok(serde_json::from_slice(&value)?)
+        } else {
+            Ok(GenerationSettings::default())
+        }
+    }
+
+    pub fn save_default_generation_settings(&self, settings: &GenerationSettings) -> Result<()> {
+        let value = serde_json::to_vec(settings)?;
+        self.db.insert("settings:generation", value)?;
+        Ok(())
+    }
+
+    pub fn get_conversation_generation_settings(
+        &self,
+        conversation_id: &str,
+    ) -> Result<Option<GenerationSettings>> {
+        let key = format!("generation_settings:{}", conversation_id);
+
+        if let Some(value) = self.db.get(&key)? {
+            Ok(Some(serde_json::from_slice(&value)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Stores a per-conversation override, or removes it when `settings` is
+    /// `None` so the conversation follows the defaults again.
+    pub fn set_conversation_generation_settings(
+        &self,
+        conversation_id: &str,
+        settings: Option<&GenerationSettings>,
+    ) -> Result<()> {
+        let key = format!("generation_settings:{}", conversation_id);
+
+        if let Some(settings) = settings {
+            let value = serde_json::to_vec(settings)?;
+            self.db.insert(key, value)?;
+        } else {
+            self.db.remove(key)?;
+        }
+
+        Ok(())
+    }
+
+    /// The settings in effect for a conversation: its override if it has one,
+    /// otherwise the saved defaults.
+    pub fn resolve_generation_settings(&self, conversation_id: &str) -> Result<GenerationSettings> {
+        match self.get_conversation_generation_settings(conversation_id)? {
+            Some(settings) => Ok(settings),
+            None => self.get_default_generation_settings(),
+        }
+    }
+
+    /// Built-in profiles (with any stored edits) followed by the user's own,
+    /// each group sorted by name.
+    pub fn get_prompt_profiles(&self) -> Result<Vec<PromptProfile>> {
+        let mut stored = Vec::new();
+        for result in self.db.scan_prefix("profile:") {
+            let (_key, value) = result?;
+            let profile: PromptProfile = serde_json::from_slice(&value)?;
+            stored.push(profile);
+        }
+
+        let mut profiles: Vec<PromptProfile> = builtin_profiles()
+            .into_iter()
+            .map(|builtin| {
+                stored
+                    .iter()
+                    .find(|profile| profile.id == builtin.id)
+                    .cloned()
+                    .unwrap_or(builtin)
+            })
+            .collect();
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut custom: Vec<PromptProfile> = stored
+            .into_iter()
+            .filter(|profile| !profile.builtin)
+            .collect();
+        custom.sort_by(|a, b| a.name.cmp(&b.name));
+        profiles.extend(custom);
+
+        Ok(profiles)
+    }
+
+    pub fn get_prompt_profile(&self, profile_id: &str) -> Result<Option<PromptProfile>> {
+        let key = format!("profile:{}", profile_id);
+
+        if let Some(value) = self.db.get(&key)? {
+            return Ok(Some(serde_json::from_slice(&value)?));
+        }
+
+        Ok(builtin_profiles()
+            .into_iter()
+            .find(|profile| profile.id == profile_id))
+    }
+
+    pub fn save_prompt_profile(&self, profile: &PromptProfile) -> Result<()> {
+        profile.validate()?;
+
+        let mut profile = profile.clone();
+        profile.builtin = builtin_profiles()
+            .iter()
+            .any(|builtin| builtin.id == profile.id);
+
+        let key = format!("profile:{}", profile.id);
+        let value = serde_json::to_vec(&profile)?;
+        self.db.insert(key, value)?;
+        Ok(())
+    }
+
+    /// Deletes a custom profile, or restores a built-in one to its shipped
+    /// text. Conversations using a deleted profile fall back to the default.
+    pub fn delete_prompt_profile(&self, profile_id: &str) -> Result<()> {
+        self.db.remove(format!("profile:{}", profile_id))?;
+        Ok(())
+    }
+
+    pub fn get_conversation_profile_id(&self, conversation_id: &str) -> Result<Option<String>> {
+        let key = format!("conversation_profile:{}", conversation_id);
+
+        if let Some(value) = self.db.get(&key)? {
+            Ok(Some(String::from_utf8(value.to_vec())?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Selects the profile a conversation uses, or goes back to the default
+    /// when `profile_id` is `None`.
+    pub fn set_conversation_profile(
+        &self,
+        conversation_id: &str,
+        profile_id: Option<&str>,
+    ) -> Result<()> {
+        let key = format!("conversation_profile:{}", conversation_id);
+
+        if let Some(profile_id) = profile_id {
+            if self.get_prompt_profile(profile_id)?.is_none() {
+                return Err(anyhow!("Profile not found: {}", profile_id));
+            }
+            self.db.insert(key, profile_id.as_bytes())?;
+        } else {
+            self.db.remove(key)?;
+        }
+
+        Ok(())
+    }
+
+    /// The profile in effect for a conversation: the one it selected if that
+    /// still exists, otherwise the default.
+    pub fn resolve_prompt_profile(&self, conversation_id: &str) -> Result<PromptProfile> {
+        if let Some(profile_id) = self.get_conversation_profile_id(conversation_id)? {
+            if let Some(profile) = self.get_prompt_profile(&profile_id)? {
+                return Ok(profile);
+            }
+        }
+
+        self.get_prompt_profile(DEFAULT_PROFILE_ID)?
+            .ok_or_else(|| anyhow!("Default profile missing"))
+    }
+
+    pub fn get_engine_config(&self) -> Result<EngineConfig> {
+        if let Some(value) = self.db.get("settings:engine")? {
+            Ok(serde_json::from_slice(&value)?)
+        } else {
+            Ok(EngineConfig::default())
+        }
+    }
+
+    pub fn save_engine_config(&self, config: &EngineConfig) -> Result<()> {
+        let value = serde_json::to_vec(config)?;
+        self.db.insert("settings:engine", value)?;
+        Ok(())
+    }
+
+    pub fn get_reply_settings(&self) -> Result<ReplySettings> {
+        if let Some(value) = self.db.get("settings:reply")? {
+            Ok(serde_json::from_slice(&value)?)
+        } else {
+            Ok(ReplySettings::default())
+        }
+    }
+
+    pub fn save_reply_settings(&self, settings: &ReplySettings) -> Result<()> {
+        let value = serde_json::to_vec(settings)?;
+        self.db.insert("settings:reply", value)?;
+        Ok(())
+    }
+
     pub fn clear_all_data(&self) -> Result<()> {
         self.db.clear()?;
         Ok(())
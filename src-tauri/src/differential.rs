@@ -0,0 +1,236 @@
+use crate::ai_engine::AIEngine;
+use crate::database::Database;
+use crate::prompt_template::PromptMessage;
+use crate::settings::GenerationSettings;
+use crate::summarizer;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+// Room for several diagnoses with their findings; prose answers are capped
+// far lower
+const DIFFERENTIAL_MAX_TOKENS: u32 = 2048;
+const MAX_DIAGNOSES: usize = 10;
+
+// Tries at getting output that parses and validates
+const DIFFERENTIAL_ATTEMPTS: usize = 2;
+
+const DIFFERENTIAL_SYSTEM_PROMPT: &str = "You support clinicians with differential diagnosis. From the encounter, list the plausible diagnoses, most likely first, including any dangerous conditions that must not be missed. For each give a likelihood tier (high, moderate or low), the findings from the encounter that support and argue against it, the next tests that would confirm or exclude it, and the red flags that would need urgent action. Only cite findings present in the encounter.";
+
+/// How likely a diagnosis is given the findings so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LikelihoodTier {
+    High,
+    Moderate,
+    Low,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DifferentialEntry {
+    pub diagnosis: String,
+    pub likelihood: LikelihoodTier,
+    pub supporting_findings: Vec<String>,
+    pub against_findings: Vec<String>,
+    pub next_tests: Vec<String>,
+    pub red_flags: Vec<String>,
+}
+
+/// A differential diagnosis as produced by the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Differential {
+    pub diagnoses: Vec<DifferentialEntry>,
+}
+
+/// A generated differential as stored with its conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DifferentialRecord {
+    pub id: String,
+    pub conversation_id: String,
+    pub differential: Differential,
+    /// Last message of the branch the differential was made from
+    pub covers_message_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Differential {
+    /// The schema the model's output is constrained to.
+    pub fn json_schema() -> serde_json::Value {
+        let findings = serde_json::json!({
+            "type": "array",
+            "items": { "type": "string", "minLength": 1 }
+        });
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "diagnoses": {
+                    "type": "array",
+                    "minItems": 1,
+                    "maxItems": MAX_DIAGNOSES,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "diagnosis": { "type": "string", "minLength": 1 },
+                            "likelihood": { "type": "string", "enum": ["high", "moderate", "low"] },
+                            "supporting_findings": findings,
+                            "against_findings": findings,
+                            "next_tests": findings,
+                            "red_flags": findings
+                        },
+                        "required": [
+                            "diagnosis",
+                            "likelihood",
+                            "supporting_findings",
+                            "against_findings",
+                            "next_tests",
+                            "red_flags"
+                        ],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["diagnoses"],
+            "additionalProperties": false
+        })
+    }
+
+    /// Checks what the schema can't express, or what a backend without
+    /// grammar support may have ignored.
+    pub fn validate(&self) -> Result<()> {
+        if self.diagnoses.is_empty() {
+            return Err(anyhow!("No diagnoses given"));
+        }
+        if self.diagnoses.len() > MAX_DIAGNOSES {
+            return Err(anyhow!(
+                "Too many diagnoses: {} (at most {})",
+                self.diagnoses.len(),
+                MAX_DIAGNOSES
+            ));
+        }
+
+        let mut seen = HashSet::new();
+        for entry in &self.diagnoses {
+            let name = entry.diagnosis.trim();
+            if name.is_empty() {
+                return Err(anyhow!("Diagnosis without a name"));
+            }
+            if !seen.insert(name.to_lowercase()) {
+                return Err(anyhow!("Diagnosis listed twice: {}", name));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse(output: &str) -> Result<Self> {
+        let differential: Differential = serde_json::from_str(strip_code_fence(output))
+            .map_err(|e| anyhow!("Model returned an invalid differential: {}", e))?;
+        differential.validate()?;
+        Ok(differential)
+    }
+}
+
+/// Backends that ignore the JSON schema tend to wrap their output in a
+/// Markdown code fence.
+fn strip_code_fence(output: &str) -> &str {
+    let trimmed = output.trim();
+    let Some(fenced) = trimmed.strip_prefix("```") else {
+        return trimmed;
+    };
+    let fenced = fenced.strip_suffix("```").unwrap_or(fenced);
+
+    // Skip the info string, e.g. `json`
+    match fenced.split_once('\n') {
+        Some((_, body)) => body.trim(),
+        None => fenced.trim(),
+    }
+}
+
+/// Builds a differential diagnosis from the conversation's active branch and
+/// stores it. Output that doesn't parse or validate is retried once, with the
+/// error passed back to the model.
+pub async fn generate_differential(
+    engine: &AIEngine,
+    database: &Database,
+    conversation_id: &str,
+) -> Result<DifferentialRecord> {
+    let (encounter, last_message_id) = summarizer::encounter_transcript(database, conversation_id)?;
+
+    let mut prompt = vec![
+        PromptMessage::new("system", DIFFERENTIAL_SYSTEM_PROMPT),
+        PromptMessage::new(
+            "user",
+            &format!("Encounter:\n{}Give the differential diagnosis.", encounter),
+        ),
+    ];
+
+    let settings = GenerationSettings {
+        temperature: 0.2,
+        n_predict: DIFFERENTIAL_MAX_TOKENS,
+        ..GenerationSettings::default()
+    };
+    let schema = Differential::json_schema();
+
+    let mut last_error = None;
+    for attempt in 1..=DIFFERENTIAL_ATTEMPTS {
+        let output = engine
+            .complete_json(prompt.clone(), &settings, &schema)
+            .await?;
+
+        match Differential::parse(&output) {
+            Ok(differential) => {
+                let record = DifferentialRecord {
+                    id: Uuid::new_v4().to_string(),
+                    conversation_id: conversation_id.to_string(),
+                    differential,
+                    covers_message_id: Some(last_message_id),
+                    created_at: Utc::now(),
+                };
+                database.save_differential(&record)?;
+                return Ok(record);
+            }
+            Err(e) => {
+                println!("Differential attempt {} unusable: {}", attempt, e);
+                prompt.push(PromptMessage::new("assistant", &output));
+                prompt.push(PromptMessage::new(
+                    "user",
+                    &format!(
+                        "That differential could not be used: {}. Give it again as a single JSON object matching the schema.",
+                        e
+                    ),
+                ));
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| anyhow!("No differential generated")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFFERENTIAL: &str = r#"{"diagnoses": [{"diagnosis": "Pneumonia", "likelihood": "high", "supporting_findings": ["fever"], "against_findings": [], "next_tests": ["chest x-ray"], "red_flags": []}]}"#;
+
+    #[test]
+    fn strips_code_fences_around_json() {
+        assert_eq!(strip_code_fence(DIFFERENTIAL), DIFFERENTIAL);
+        assert_eq!(
+            strip_code_fence(&format!("```json\n{}\n```", DIFFERENTIAL)),
+            DIFFERENTIAL
+        );
+        assert_eq!(
+            strip_code_fence(&format!("  ```\n{}\n```  ", DIFFERENTIAL)),
+            DIFFERENTIAL
+        );
+    }
+
+    #[test]
+    fn parses_a_fenced_differential() {
+        let differential = Differential::parse(&format!("```json\n{}\n```", DIFFERENTIAL)).unwrap();
+        assert_eq!(differential.diagnoses[0].diagnosis, "Pneumonia");
+    }
+}
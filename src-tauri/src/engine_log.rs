@@ -0,0 +1,130 @@
+use anyhow::Result;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// The live log is rotated to `<name>.1` once it grows past this size, so at
+// most twice this much is kept per engine
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+/// Size-rotated log file that llama-server's stdout and stderr are piped
+/// into, one per engine under `<app data>/logs`.
+#[derive(Clone)]
+pub struct EngineLog {
+    path: PathBuf,
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl EngineLog {
+    pub fn new(logs_dir: &Path, engine_id: &str) -> Result<Self> {
+        fs::create_dir_all(logs_dir)?;
+
+        Ok(Self {
+            path: Self::log_path(logs_dir, engine_id),
+            file: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    fn log_path(logs_dir: &Path, engine_id: &str) -> PathBuf {
+        // Engine ids default to model filenames; keep them filesystem-safe
+        let name: String = engine_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        logs_dir.join(format!("engine-{}.log", name))
+    }
+
+    /// Copies every line from `reader` into the log on a background thread
+    /// until the stream closes.
+    pub fn capture<R: Read + Send + 'static>(&self, reader: R, stream: &'static str) {
+        let log = self.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                match line {
+                    Ok(line) => log.append(stream, &line),
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
+    pub fn append(&self, stream: &str, line: &str) {
+        let entry = format!(
+            "{} [{}] {}\n",
+            chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            stream,
+            line
+        );
+
+        let mut file_guard = self.file.lock().unwrap();
+        if let Err(e) = self.write_entry(&mut file_guard, &entry) {
+            println!("Failed to write engine log {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn write_entry(&self, file: &mut Option<File>, entry: &str) -> Result<()> {
+        let current_size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if current_size + entry.len() as u64 > MAX_LOG_BYTES {
+            *file = None;
+            fs::rename(&self.path, self.rotated_path())?;
+        }
+
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+
+        if let Some(file) = file.as_mut() {
+            file.write_all(entry.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn rotated_path(&self) -> PathBuf {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        PathBuf::from(rotated)
+    }
+
+    /// The last `lines` lines of the log, oldest first, reaching into the
+    /// rotated file when the live one is shorter.
+    pub fn tail(&self, lines: usize) -> Vec<String> {
+        let mut tail = read_lines(&self.path);
+        if tail.len() < lines {
+            let mut older = read_lines(&self.rotated_path());
+            older.append(&mut tail);
+            tail = older;
+        }
+
+        let skip = tail.len().saturating_sub(lines);
+        tail.split_off(skip)
+    }
+
+    /// `tail` formatted for appending to an error message.
+    pub fn tail_for_error(&self, lines: usize) -> String {
+        let tail = self.tail(lines);
+        if tail.is_empty() {
+            String::new()
+        } else {
+            format!("\n\nLast llama-server output:\n{}", tail.join("\n"))
+        }
+    }
+}
+
+fn read_lines(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .map(|content| content.lines().map(String::from).collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+
+/// What we could find out about the machine the engine will run on. Fields
+/// are `None` when the platform does not expose them to us.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HardwareInfo {
+    pub logical_cores: u32,
+    pub physical_cores: u32,
+    pub total_memory: Option<u64>,
+    pub available_memory: Option<u64>,
+}
+
+impl HardwareInfo {
+    pub fn detect() -> Self {
+        let logical_cores = std::thread::available_parallelism()
+            .map(|n| n.get() as u32)
+            .unwrap_or(1);
+
+        let physical_cores = detect_physical_cores()
+            .filter(|&cores| cores > 0 && cores <= logical_cores)
+            .unwrap_or(logical_cores);
+
+        let (total_memory, available_memory) = detect_memory();
+
+        Self {
+            logical_cores,
+            physical_cores,
+            total_memory,
+            available_memory,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn detect_physical_cores() -> Option<u32> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+
+    // Each physical core shows up once per hardware thread with the same
+    // (physical id, core id) pair
+    let mut cores = std::collections::HashSet::new();
+    let mut physical_id = None;
+    for line in cpuinfo.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key.trim() {
+            "physical id" => physical_id = Some(value.trim().to_string()),
+            "core id" => {
+                cores.insert((physical_id.clone(), value.trim().to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    if cores.is_empty() {
+        None
+    } else {
+        Some(cores.len() as u32)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn detect_physical_cores() -> Option<u32> {
+    sysctl("hw.physicalcpu")?.parse().ok()
+}
+
+#[cfg(windows)]
+fn detect_physical_cores() -> Option<u32> {
+    windows::physical_cores()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn detect_physical_cores() -> Option<u32> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn detect_memory() -> (Option<u64>, Option<u64>) {
+    let Ok(meminfo) = std::fs::read_to_string("/proc/meminfo") else {
+        return (None, None);
+    };
+
+    let read_kb = |name: &str| {
+        meminfo
+            .lines()
+            .find(|line| line.starts_with(name))
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|kb| kb.parse::<u64>().ok())
+            .map(|kb| kb * 1024)
+    };
+
+    (read_kb("MemTotal:"), read_kb("MemAvailable:"))
+}
+
+#[cfg(target_os = "macos")]
+fn detect_memory() -> (Option<u64>, Option<u64>) {
+    let total = sysctl("hw.memsize").and_then(|v| v.parse().ok());
+    (total, None)
+}
+
+#[cfg(windows)]
+fn detect_memory() -> (Option<u64>, Option<u64>) {
+    match windows::memory_status() {
+        Some((total, available)) => (Some(total), Some(available)),
+        None => (None, None),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn detect_memory() -> (Option<u64>, Option<u64>) {
+    (None, None)
+}
+
+#[cfg(target_os = "macos")]
+fn sysctl(name: &str) -> Option<String> {
+    let output = std::process::Command::new("sysctl")
+        .arg("-n")
+        .arg(name)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The two kernel32 calls we need, declared by hand rather than pulling in
+/// a Windows API crate.
+#[cfg(windows)]
+mod windows {
+    // SYSTEM_LOGICAL_PROCESSOR_INFORMATION; the union after `relationship`
+    // is 16 bytes and 8-byte aligned on both 32- and 64-bit Windows
+    #[repr(C)]
+    struct LogicalProcessorInformation {
+        processor_mask: usize,
+        relationship: u32,
+        details: [u64; 2],
+    }
+
+    // LOGICAL_PROCESSOR_RELATIONSHIP::RelationProcessorCore
+    const RELATION_PROCESSOR_CORE: u32 = 0;
+
+    // MEMORYSTATUSEX
+    #[repr(C)]
+    #[derive(Default)]
+    struct MemoryStatus {
+        length: u32,
+        memory_load: u32,
+        total_phys: u64,
+        avail_phys: u64,
+        total_page_file: u64,
+        avail_page_file: u64,
+        total_virtual: u64,
+        avail_virtual: u64,
+        avail_extended_virtual: u64,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetLogicalProcessorInformation(
+            buffer: *mut LogicalProcessorInformation,
+            returned_length: *mut u32,
+        ) -> i32;
+        fn GlobalMemoryStatusEx(buffer: *mut MemoryStatus) -> i32;
+    }
+
+    /// One entry per physical core, however many hardware threads it runs.
+    pub fn physical_cores() -> Option<u32> {
+        let entry_size = std::mem::size_of::<LogicalProcessorInformation>();
+
+        // The first call only reports the buffer size needed
+        let mut length = 0u32;
+        unsafe { GetLogicalProcessorInformation(std::ptr::null_mut(), &mut length) };
+        if length == 0 {
+            return None;
+        }
+
+        let mut entries: Vec<LogicalProcessorInformation> =
+            Vec::with_capacity(length as usize / entry_size);
+        if unsafe { GetLogicalProcessorInformation(entries.as_mut_ptr(), &mut length) } == 0 {
+            return None;
+        }
+        // SAFETY: the call filled `length` bytes, within the capacity
+        unsafe { entries.set_len(length as usize / entry_size) };
+
+        let cores = entries
+            .iter()
+            .filter(|entry| entry.relationship == RELATION_PROCESSOR_CORE)
+            .count();
+        (cores > 0).then_some(cores as u32)
+    }
+
+    /// Total and available physical memory in bytes.
+    pub fn memory_status() -> Option<(u64, u64)> {
+        let mut status = MemoryStatus {
+            length: std::mem::size_of::<MemoryStatus>() as u32,
+            ..Default::default()
+        };
+        if unsafe { GlobalMemoryStatusEx(&mut status) } == 0 {
+            return None;
+        }
+        Some((status.total_phys, status.avail_phys))
+    }
+}
@@ -0,0 +1,721 @@
+use crate::text::tokenize;
+use crate::tools::{Tool, ToolOutput, ToolSpec};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// File in the app data directory holding an imported dataset. Without it
+/// the dataset bundled with the app is used.
+pub const DATASET_FILE_NAME: &str = "drug_interactions.json";
+
+const BUNDLED_DATASET: &str = include_str!("../resources/drug_interactions.json");
+
+// Columns of an imported CSV file; a drug cell may list aliases after the
+// name, separated by "|"
+const CSV_COLUMNS: [&str; 5] = ["drug_a", "drug_b", "severity", "effect", "management"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractionSeverity {
+    /// Must not be given together
+    Contraindicated,
+    /// Avoid, or only with close monitoring
+    Major,
+    /// May need a dose change or monitoring
+    Moderate,
+    Minor,
+}
+
+/// A drug the checker recognizes. Interactions can name the drug itself or
+/// one of its classes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrugEntry {
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub classes: Vec<String>,
+}
+
+/// An interaction between two drugs or drug classes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InteractionRule {
+    pub a: String,
+    pub b: String,
+    pub severity: InteractionSeverity,
+    pub effect: String,
+    pub management: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InteractionDataset {
+    pub version: String,
+    pub drugs: Vec<DrugEntry>,
+    pub interactions: Vec<InteractionRule>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DatasetSource {
+    #[default]
+    Bundled,
+    Imported,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatasetInfo {
+    pub version: String,
+    pub source: DatasetSource,
+    pub drug_count: usize,
+    pub interaction_count: usize,
+}
+
+/// An interaction found between two of the checked drugs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InteractionFinding {
+    pub drug_a: String,
+    pub drug_b: String,
+    pub severity: InteractionSeverity,
+    pub effect: String,
+    pub management: String,
+}
+
+/// The result of checking a list of drugs against the dataset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InteractionReport {
+    pub dataset_version: String,
+    #[serde(default)]
+    pub dataset_source: DatasetSource,
+    /// The checked drugs by their dataset names
+    pub drugs: Vec<String>,
+    /// Names the dataset doesn't know, so nothing can be said about them
+    pub unrecognized: Vec<String>,
+    /// Most severe first
+    pub interactions: Vec<InteractionFinding>,
+}
+
+impl InteractionSeverity {
+    fn label(self) -> &'static str {
+        match self {
+            InteractionSeverity::Contraindicated => "contraindicated",
+            InteractionSeverity::Major => "major",
+            InteractionSeverity::Moderate => "moderate",
+            InteractionSeverity::Minor => "minor",
+        }
+    }
+}
+
+impl DatasetSource {
+    fn label(self) -> &'static str {
+        match self {
+            DatasetSource::Bundled => "bundled",
+            DatasetSource::Imported => "imported",
+        }
+    }
+}
+
+impl InteractionReport {
+    /// The findings on one line, e.g. for the model.
+    pub fn summary(&self) -> String {
+        let dataset = format!(
+            "the {} interaction dataset, version {}",
+            self.dataset_source.label(),
+            self.dataset_version
+        );
+        let mut summary = if self.interactions.is_empty() {
+            format!(
+                "No interactions between {} found in {}, which is not exhaustive.",
+                self.drugs.join(", "),
+                dataset
+            )
+        } else {
+            let findings: Vec<String> = self
+                .interactions
+                .iter()
+                .map(|finding| {
+                    format!(
+                        "{} + {} ({}): {} {}",
+                        finding.drug_a,
+                        finding.drug_b,
+                        finding.severity.label(),
+                        finding.effect,
+                        finding.management
+                    )
+                })
+                .collect();
+            format!("From {}: {}", dataset, findings.join(" "))
+        };
+        if !self.unrecognized.is_empty() {
+            summary.push_str(&format!(
+                " Not in the dataset, so unchecked: {}.",
+                self.unrecognized.join(", ")
+            ));
+        }
+        summary
+    }
+}
+
+impl InteractionDataset {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let dataset: InteractionDataset = serde_json::from_str(json)
+            .map_err(|e| anyhow!("Invalid drug interaction dataset: {}", e))?;
+        dataset.validate()?;
+        Ok(dataset)
+    }
+
+    /// Reads one interaction per row under a `drug_a,drug_b,severity,effect,
+    /// management` header. Drugs are those named in the rows; classes
+    /// can't be expressed.
+    pub fn from_csv(csv: &str, version: &str) -> Result<Self> {
+        let mut rows = csv.lines().filter(|line| !line.trim().is_empty());
+        let header = parse_csv_line(rows.next().ok_or_else(|| anyhow!("CSV file is empty"))?)?;
+        let header: Vec<String> = header
+            .iter()
+            .map(|cell| cell.trim().to_lowercase())
+            .collect();
+        if header != CSV_COLUMNS {
+            return Err(anyhow!("CSV header must be: {}", CSV_COLUMNS.join(",")));
+        }
+
+        let mut drugs: Vec<DrugEntry> = Vec::new();
+        let mut interactions = Vec::new();
+        for (line, row) in rows.enumerate() {
+            // Line numbers as seen in an editor, after the header
+            let line = line + 2;
+            let cells = parse_csv_line(row)?;
+            let [drug_a, drug_b, severity, effect, management]: [String; 5] = cells
+                .try_into()
+                .map_err(|_| anyhow!("Line {}: expected {} columns", line, CSV_COLUMNS.len()))?;
+
+            let severity =
+                serde_json::from_value(serde_json::Value::String(severity.trim().to_lowercase()))
+                    .map_err(|_| anyhow!("Line {}: unknown severity '{}'", line, severity.trim()))?;
+
+            interactions.push(InteractionRule {
+                a: add_csv_drug(&mut drugs, &drug_a),
+                b: add_csv_drug(&mut drugs, &drug_b),
+                severity,
+                effect: effect.trim().to_string(),
+                management: management.trim().to_string(),
+            });
+        }
+
+        let dataset = Self {
+            version: version.to_string(),
+            drugs,
+            interactions,
+        };
+        dataset.validate()?;
+        Ok(dataset)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.version.trim().is_empty() {
+            return Err(anyhow!("Dataset has no version"));
+        }
+
+        let mut names = HashSet::new();
+        let mut classes = HashSet::new();
+        for drug in &self.drugs {
+            if drug.name.trim().is_empty() {
+                return Err(anyhow!("Drug without a name"));
+            }
+            for name in std::iter::once(&drug.name).chain(&drug.aliases) {
+                if tokenize(name).is_empty() {
+                    return Err(anyhow!("Drug '{}' has a name without words", drug.name));
+                }
+                if !names.insert(tokenize(name)) {
+                    return Err(anyhow!("Drug name used twice: {}", name));
+                }
+            }
+            classes.extend(drug.classes.iter().map(|class| class.to_lowercase()));
+        }
+
+        for rule in &self.interactions {
+            for side in [&rule.a, &rule.b] {
+                let known = self
+                    .drugs
+                    .iter()
+                    .any(|drug| drug.name.eq_ignore_ascii_case(side))
+                    || classes.contains(&side.to_lowercase());
+                if !known {
+                    return Err(anyhow!(
+                        "Interaction {} / {} names an unknown drug or class: {}",
+                        rule.a,
+                        rule.b,
+                        side
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks drug combinations against a fixed dataset, independently of the
+/// model.
+pub struct InteractionChecker {
+    dataset: InteractionDataset,
+    source: DatasetSource,
+    // Tokenized names and aliases with the index of their drug, longest
+    // first so "co-trimoxazole" wins over the "trimethoprim" inside
+    // "trimethoprim-sulfamethoxazole"
+    terms: Vec<(Vec<String>, usize)>,
+}
+
+impl InteractionChecker {
+    pub fn new(dataset: InteractionDataset, source: DatasetSource) -> Result<Self> {
+        dataset.validate()?;
+
+        let mut terms: Vec<(Vec<String>, usize)> = dataset
+            .drugs
+            .iter()
+            .enumerate()
+            .flat_map(|(index, drug)| {
+                std::iter::once(&drug.name)
+                    .chain(&drug.aliases)
+                    .map(move |name| (tokenize(name), index))
+            })
+            .collect();
+        terms.sort_by_key(|(words, _)| std::cmp::Reverse(words.len()));
+
+        Ok(Self {
+            dataset,
+            source,
+            terms,
+        })
+    }
+
+    pub fn bundled() -> Self {
+        let dataset =
+            InteractionDataset::from_json(BUNDLED_DATASET).expect("bundled dataset is valid");
+        Self::new(dataset, DatasetSource::Bundled).expect("bundled dataset is valid")
+    }
+
+    /// Loads the imported dataset at `path`, or the bundled one when nothing
+    /// has been imported.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::bundled());
+        }
+
+        let dataset = InteractionDataset::from_json(&std::fs::read_to_string(path)?)?;
+        Self::new(dataset, DatasetSource::Imported)
+    }
+
+    /// Reads a maintained dataset from a `.json` or `.csv` file and saves it
+    /// at `path`, replacing any earlier import. CSV files carry no version,
+    /// so `version` (or the import date) is used for them.
+    pub fn import(source_path: &Path, path: &Path, version: Option<&str>) -> Result<Self> {
+        let contents = std::fs::read_to_string(source_path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", source_path.display(), e))?;
+
+        let extension = source_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+        let dataset = match extension.as_deref() {
+            Some("json") => InteractionDataset::from_json(&contents)?,
+            Some("csv") => {
+                let version = version
+                    .map(String::from)
+                    .unwrap_or_else(|| format!("imported-{}", Utc::now().format("%Y-%m-%d")));
+                InteractionDataset::from_csv(&contents, &version)?
+            }
+            _ => return Err(anyhow!("Only .json and .csv datasets can be imported")),
+        };
+
+        std::fs::write(path, serde_json::to_string_pretty(&dataset)?)?;
+        Self::new(dataset, DatasetSource::Imported)
+    }
+
+    pub fn info(&self) -> DatasetInfo {
+        DatasetInfo {
+            version: self.dataset.version.clone(),
+            source: self.source,
+            drug_count: self.dataset.drugs.len(),
+            interaction_count: self.dataset.interactions.len(),
+        }
+    }
+
+    /// Dataset names of the drugs mentioned in `text`, in order of first
+    /// mention. Whole words only, case-insensitively.
+    pub fn detect_drugs(&self, text: &str) -> Vec<String> {
+        let words = tokenize(text);
+        let mut covered = vec![false; words.len()];
+        let mut found: Vec<(usize, usize)> = Vec::new();
+
+        for (term, drug) in &self.terms {
+            if term.len() > words.len() {
+                continue;
+            }
+            for start in 0..=words.len() - term.len() {
+                let span = start..start + term.len();
+                if words[span.clone()] != term[..] || covered[span.clone()].contains(&true) {
+                    continue;
+                }
+                covered[span].iter_mut().for_each(|word| *word = true);
+                found.push((start, *drug));
+            }
+        }
+
+        found.sort_unstable();
+        let mut drugs: Vec<String> = Vec::new();
+        for (_, drug) in found {
+            let name = &self.dataset.drugs[drug].name;
+            if !drugs.contains(name) {
+                drugs.push(name.clone());
+            }
+        }
+        drugs
+    }
+
+    /// Checks every pair of `drugs`, given by name or alias.
+    pub fn check(&self, drugs: &[String]) -> InteractionReport {
+        let mut recognized: Vec<usize> = Vec::new();
+        let mut unrecognized = Vec::new();
+        for name in drugs {
+            let words = tokenize(name);
+            match self.terms.iter().find(|(term, _)| *term == words) {
+                Some((_, drug)) if !recognized.contains(drug) => recognized.push(*drug),
+                Some(_) => {}
+                None if words.is_empty() => {}
+                None => unrecognized.push(name.trim().to_string()),
+            }
+        }
+
+        let mut interactions = Vec::new();
+        for (i, &first) in recognized.iter().enumerate() {
+            for &second in &recognized[i + 1..] {
+                let first = &self.dataset.drugs[first];
+                let second = &self.dataset.drugs[second];
+                // A drug rule and a class rule may both cover the pair; the
+                // most severe one is reported
+                let rule = self
+                    .dataset
+                    .interactions
+                    .iter()
+                    .filter(|rule| {
+                        (names_drug(&rule.a, first) && names_drug(&rule.b, second))
+                            || (names_drug(&rule.a, second) && names_drug(&rule.b, first))
+                    })
+                    .min_by_key(|rule| rule.severity);
+                if let Some(rule) = rule {
+                    interactions.push(InteractionFinding {
+                        drug_a: first.name.clone(),
+                        drug_b: second.name.clone(),
+                        severity: rule.severity,
+                        effect: rule.effect.clone(),
+                        management: rule.management.clone(),
+                    });
+                }
+            }
+        }
+        interactions.sort_by_key(|finding| finding.severity);
+
+        InteractionReport {
+            dataset_version: self.dataset.version.clone(),
+            dataset_source: self.source,
+            drugs: recognized
+                .iter()
+                .map(|&drug| self.dataset.drugs[drug].name.clone())
+                .collect(),
+            unrecognized,
+            interactions,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CheckArguments {
+    drugs: Vec<String>,
+}
+
+#[async_trait]
+impl Tool for InteractionChecker {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "check_interactions".to_string(),
+            description: "Known interactions between drugs, from the interaction dataset."
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "drugs": { "type": "array", "items": { "type": "string" } }
+                },
+                "required": ["drugs"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn call(&self, arguments: serde_json::Value) -> Result<ToolOutput> {
+        let arguments: CheckArguments = serde_json::from_value(arguments)
+            .map_err(|e| anyhow!("Invalid arguments for check_interactions: {}", e))?;
+        if arguments.drugs.len() < 2 {
+            return Err(anyhow!("Give at least two drugs to check"));
+        }
+
+        Ok(ToolOutput::Interactions(self.check(&arguments.drugs)))
+    }
+}
+
+/// Whether an interaction side refers to `drug`, by name or class.
+fn names_drug(side: &str, drug: &DrugEntry) -> bool {
+    drug.name.eq_ignore_ascii_case(side)
+        || drug
+            .classes
+            .iter()
+            .any(|class| class.eq_ignore_ascii_case(side))
+}
+
+/// Adds the drug in a CSV cell to `drugs`, merging aliases into an existing
+/// entry, and returns its name. Names are compared as they are matched, so
+/// "Warfarin" in one row and "warfarin", or an alias of it, in another are
+/// the same drug.
+fn add_csv_drug(drugs: &mut Vec<DrugEntry>, cell: &str) -> String {
+    let names: Vec<&str> = cell
+        .split('|')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    let index = match names
+        .iter()
+        .find_map(|name| drugs.iter().position(|drug| has_name(drug, name)))
+    {
+        Some(index) => index,
+        None => {
+            drugs.push(DrugEntry {
+                name: names.first().copied().unwrap_or_default().to_string(),
+                aliases: Vec::new(),
+                classes: Vec::new(),
+            });
+            drugs.len() - 1
+        }
+    };
+
+    for name in names {
+        if !has_name(&drugs[index], name) {
+            drugs[index].aliases.push(name.to_string());
+        }
+    }
+    drugs[index].name.clone()
+}
+
+/// Whether `name` is the drug's name or one of its aliases.
+fn has_name(drug: &DrugEntry, name: &str) -> bool {
+    let words = tokenize(name);
+    std::iter::once(&drug.name)
+        .chain(&drug.aliases)
+        .any(|known| tokenize(known) == words)
+}
+
+/// Splits a CSV line into cells. Cells may be quoted, with `""` for a quote
+/// inside; quoted line breaks aren't supported.
+fn parse_csv_line(line: &str) -> Result<Vec<String>> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if cell.trim().is_empty() => {
+                cell.clear();
+                quoted = true;
+            }
+            (',', false) => cells.push(std::mem::take(&mut cell)),
+            _ => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("Unterminated quote in CSV line: {}", line));
+    }
+    cells.push(cell);
+
+    Ok(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATASET: &str = r#"{
+        "version": "test",
+        "drugs": [
+            { "name": "potassium", "classes": [] },
+            { "name": "potassium chloride", "aliases": ["slow k"], "classes": ["potassium_supplement"] },
+            { "name": "lisinopril", "classes": ["ace_inhibitor"] },
+            { "name": "warfarin", "aliases": ["coumadin"], "classes": [] },
+            { "name": "ibuprofen", "aliases": ["nurofen"], "classes": ["nsaid"] }
+        ],
+        "interactions": [
+            { "a": "ace_inhibitor", "b": "potassium_supplement", "severity": "major", "effect": "Hyperkalaemia.", "management": "Check potassium." },
+            { "a": "nsaid", "b": "warfarin", "severity": "major", "effect": "Bleeding.", "management": "Avoid." },
+            { "a": "warfarin", "b": "ibuprofen", "severity": "moderate", "effect": "INR may rise.", "management": "Monitor INR." }
+        ]
+    }"#;
+
+    fn checker() -> InteractionChecker {
+        let dataset = InteractionDataset::from_json(DATASET).unwrap();
+        InteractionChecker::new(dataset, DatasetSource::Imported).unwrap()
+    }
+
+    fn names(drugs: &[&str]) -> Vec<String> {
+        drugs.iter().map(|drug| drug.to_string()).collect()
+    }
+
+    #[test]
+    fn detects_the_longest_name() {
+        let checker = checker();
+
+        assert_eq!(
+            checker.detect_drugs("Takes potassium chloride daily."),
+            vec!["potassium chloride"]
+        );
+        assert_eq!(
+            checker.detect_drugs("potassium chloride, then more potassium"),
+            vec!["potassium chloride", "potassium"]
+        );
+    }
+
+    #[test]
+    fn recognizes_aliases() {
+        let checker = checker();
+
+        assert_eq!(
+            checker.detect_drugs("On Coumadin and Slow-K"),
+            vec!["warfarin", "potassium chloride"]
+        );
+
+        let report = checker.check(&names(&["COUMADIN", "nurofen", "warfarin"]));
+        assert_eq!(report.drugs, vec!["warfarin", "ibuprofen"]);
+        assert!(report.unrecognized.is_empty());
+    }
+
+    #[test]
+    fn expands_class_rules_to_their_drugs() {
+        let report = checker().check(&names(&["lisinopril", "slow k"]));
+
+        assert_eq!(report.interactions.len(), 1);
+        let finding = &report.interactions[0];
+        assert_eq!(finding.drug_a, "lisinopril");
+        assert_eq!(finding.drug_b, "potassium chloride");
+        assert_eq!(finding.severity, InteractionSeverity::Major);
+
+        // Plain "potassium" is a different drug with no class
+        assert!(checker()
+            .check(&names(&["lisinopril", "potassium"]))
+            .interactions
+            .is_empty());
+    }
+
+    #[test]
+    fn reports_the_most_severe_rule_once_per_pair() {
+        let report = checker().check(&names(&["ibuprofen", "warfarin"]));
+
+        assert_eq!(report.interactions.len(), 1);
+        assert_eq!(report.interactions[0].severity, InteractionSeverity::Major);
+        assert_eq!(report.interactions[0].effect, "Bleeding.");
+    }
+
+    #[test]
+    fn summarizes_empty_results_as_not_exhaustive() {
+        let report = checker().check(&names(&["warfarin", "potassium", "unobtainium"]));
+
+        assert!(report.interactions.is_empty());
+        assert_eq!(report.unrecognized, vec!["unobtainium"]);
+        assert_eq!(
+            report.summary(),
+            "No interactions between warfarin, potassium found in the imported interaction dataset, version test, which is not exhaustive. Not in the dataset, so unchecked: unobtainium."
+        );
+    }
+
+    #[test]
+    fn bundled_dataset_covers_reviewed_pairs() {
+        let checker = InteractionChecker::bundled();
+        for pair in [
+            ["warfarin", "amiodarone"],
+            ["lisinopril", "potassium chloride"],
+            ["spironolactone", "potassium chloride"],
+            ["lisinopril", "spironolactone"],
+        ] {
+            let report = checker.check(&names(&pair));
+            assert_eq!(report.interactions.len(), 1, "{:?}", pair);
+            assert_eq!(report.interactions[0].severity, InteractionSeverity::Major);
+        }
+    }
+
+    #[test]
+    fn merges_csv_drugs_by_matched_name() {
+        let csv = "drug_a,drug_b,severity,effect,management\n\
+                   Warfarin|Coumadin,Ibuprofen,major,Bleeding.,Avoid.\n\
+                   coumadin,Aspirin,Moderate,\"Bleeding, mild.\",\"Watch for \"\"bruising\"\".\"\n";
+        let dataset = InteractionDataset::from_csv(csv, "2026-10-17").unwrap();
+
+        assert_eq!(dataset.version, "2026-10-17");
+        assert_eq!(dataset.drugs.len(), 3);
+        assert_eq!(dataset.drugs[0].name, "Warfarin");
+        assert_eq!(dataset.drugs[0].aliases, vec!["Coumadin"]);
+        assert_eq!(dataset.interactions[1].a, "Warfarin");
+        assert_eq!(
+            dataset.interactions[1].severity,
+            InteractionSeverity::Moderate
+        );
+        assert_eq!(dataset.interactions[1].effect, "Bleeding, mild.");
+        assert_eq!(
+            dataset.interactions[1].management,
+            "Watch for \"bruising\"."
+        );
+
+        let checker = InteractionChecker::new(dataset, DatasetSource::Imported).unwrap();
+        let report = checker.check(&names(&["coumadin", "aspirin"]));
+        assert_eq!(report.interactions.len(), 1);
+        assert_eq!(report.interactions[0].drug_a, "Warfarin");
+    }
+
+    #[test]
+    fn rejects_malformed_csv() {
+        let error = |csv: &str| {
+            InteractionDataset::from_csv(csv, "1")
+                .unwrap_err()
+                .to_string()
+        };
+        let header = "drug_a,drug_b,severity,effect,management\n";
+
+        assert_eq!(error(""), "CSV file is empty");
+        assert!(error("a,b,c\n").starts_with("CSV header must be"));
+        assert_eq!(
+            error(&format!("{}warfarin,aspirin,major,Bleeding.\n", header)),
+            "Line 2: expected 5 columns"
+        );
+        assert_eq!(
+            error(&format!(
+                "{}warfarin,aspirin,major,Bleeding.,Avoid.\nwarfarin,ibuprofen,severe,Bleeding.,Avoid.\n",
+                header
+            )),
+            "Line 3: unknown severity 'severe'"
+        );
+        assert!(error(&format!(
+            "{}warfarin,aspirin,major,\"Bleeding.,Avoid.\n",
+            header
+        ))
+        .starts_with("Unterminated quote"));
+        assert!(
+            error(&format!("{},aspirin,major,Bleeding.,Avoid.\n", header))
+                .starts_with("Drug without a name")
+        );
+    }
+}
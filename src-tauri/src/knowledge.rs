@@ -0,0 +1,701 @@
+use crate::ai_engine::AIEngine;
+use crate::database::Database;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+use uuid::Uuid;
+
+/// Passages retrieved for each question.
+pub const RETRIEVAL_LIMIT: usize = 4;
+
+// Passages less similar to the question than this are left out, so
+// unrelated questions don't drag in guideline text
+const MIN_RELEVANCE: f32 = 0.3;
+
+// Chunks are built from whole paragraphs up to about this many characters
+const CHUNK_TARGET_CHARS: usize = 1200;
+
+// Chunks sent to the embedding server per request
+const EMBEDDING_BATCH_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentKind {
+    Markdown,
+    Text,
+    Pdf,
+}
+
+/// A guideline, formulary or other reference added to the knowledge base.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnowledgeDocument {
+    pub id: String,
+    pub title: String,
+    pub source_path: String,
+    pub kind: DocumentKind,
+    pub chunk_count: usize,
+    /// Engine the chunks were embedded with. Vectors from different models
+    /// can't be compared, so only this engine can search the document.
+    pub embedding_model: String,
+    pub added_at: DateTime<Utc>,
+}
+
+/// A passage of a document with its embedding.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnowledgeChunk {
+    pub id: String,
+    pub document_id: String,
+    pub index: usize,
+    /// Nearest Markdown heading above the passage
+    pub section: Option<String>,
+    /// Page the passage starts on, for PDFs
+    pub page: Option<u32>,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+/// A chunk found for a question, with where it came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetrievedPassage {
+    pub passage_id: String,
+    pub document_id: String,
+    pub title: String,
+    pub section: Option<String>,
+    pub page: Option<u32>,
+    pub text: String,
+    /// Cosine similarity to the question
+    pub score: f32,
+}
+
+/// Where a passage given to the model came from, as returned with a reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceReference {
+    pub passage_id: String,
+    pub document_id: String,
+    pub title: String,
+    pub section: Option<String>,
+    pub page: Option<u32>,
+    pub score: f32,
+}
+
+/// A passage the reply cites with a `[n]` marker, stored with the message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Citation {
+    /// Number of the passage in the prompt, as used in the marker
+    pub marker: usize,
+    pub passage_id: String,
+    pub document_id: String,
+    pub title: String,
+    pub section: Option<String>,
+    pub page: Option<u32>,
+}
+
+/// A reply with its citation markers checked against the passages the model
+/// was given.
+#[derive(Debug, Clone)]
+pub struct CheckedCitations {
+    /// The reply with numbers for passages that weren't supplied removed
+    /// from its markers
+    pub content: String,
+    /// Cited passages in marker order, each once
+    pub citations: Vec<Citation>,
+    /// Numbers in a marker that matched no supplied passage
+    pub unsupported: Vec<usize>,
+}
+
+impl From<&RetrievedPassage> for SourceReference {
+    fn from(passage: &RetrievedPassage) -> Self {
+        Self {
+            passage_id: passage.passage_id.clone(),
+            document_id: passage.document_id.clone(),
+            title: passage.title.clone(),
+            section: passage.section.clone(),
+            page: passage.page,
+            score: passage.score,
+        }
+    }
+}
+
+impl RetrievedPassage {
+    /// "Title, Section, p. 12" as far as known.
+    pub fn location(&self) -> String {
+        let mut location = self.title.clone();
+        if let Some(section) = &self.section {
+            location.push_str(&format!(", {}", section));
+        }
+        if let Some(page) = self.page {
+            location.push_str(&format!(", p. {}", page));
+        }
+        location
+    }
+}
+
+/// Chunk text before it is embedded.
+struct TextChunk {
+    section: Option<String>,
+    page: Option<u32>,
+    text: String,
+}
+
+/// Reads a Markdown, text or PDF file, splits it into passages, embeds them
+/// with `engine` and stores the lot.
+pub async fn ingest_document(
+    engine: &AIEngine,
+    database: &Database,
+    path: &Path,
+) -> Result<KnowledgeDocument> {
+    let (kind, text) = extract_text(path)?;
+    let chunks = chunk_text(kind, &text);
+    if chunks.is_empty() {
+        return Err(anyhow!("No text found in {}", path.display()));
+    }
+
+    let mut embeddings = Vec::with_capacity(chunks.len());
+    for batch in chunks.chunks(EMBEDDING_BATCH_SIZE) {
+        let texts: Vec<String> = batch.iter().map(|chunk| chunk.text.clone()).collect();
+        embeddings.extend(engine.embed(&texts).await?);
+    }
+
+    let document_id = Uuid::new_v4().to_string();
+    let document = KnowledgeDocument {
+        id: document_id.clone(),
+        title: document_title(kind, &text, path),
+        source_path: path.display().to_string(),
+        kind,
+        chunk_count: chunks.len(),
+        embedding_model: engine.model(),
+        added_at: Utc::now(),
+    };
+
+    let chunks: Vec<KnowledgeChunk> = chunks
+        .into_iter()
+        .zip(embeddings)
+        .enumerate()
+        .map(|(index, (chunk, embedding))| KnowledgeChunk {
+            id: format!("{}:{}", document_id, index),
+            document_id: document_id.clone(),
+            index,
+            section: chunk.section,
+            page: chunk.page,
+            text: chunk.text,
+            embedding,
+        })
+        .collect();
+
+    database.save_knowledge_document(&document, &chunks)?;
+
+    Ok(document)
+}
+
+/// The passages most similar to `query`, best first. Only documents embedded
+/// with `engine` are searched; with none of those, the embedding server
+/// isn't asked at all. A linear scan, which is plenty for a shelf of
+/// guidelines.
+pub async fn search(
+    engine: &AIEngine,
+    database: &Database,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<RetrievedPassage>> {
+    let documents: Vec<KnowledgeDocument> = database
+        .get_knowledge_documents()?
+        .into_iter()
+        .filter(|document| document.embedding_model == engine.model())
+        .collect();
+    if documents.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let query_embedding = engine
+        .embed(&[query.to_string()])
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow!("No embedding returned for the question"))?;
+
+    let mut passages = Vec::new();
+    for document in &documents {
+        for chunk in database.get_knowledge_chunks(&document.id)? {
+            let score = cosine_similarity(&query_embedding, &chunk.embedding);
+            if score < MIN_RELEVANCE {
+                continue;
+            }
+            passages.push(RetrievedPassage {
+                passage_id: chunk.id,
+                document_id: document.id.clone(),
+                title: document.title.clone(),
+                section: chunk.section,
+                page: chunk.page,
+                text: chunk.text,
+                score,
+            });
+        }
+    }
+
+    passages.sort_by(|a, b| b.score.total_cmp(&a.score));
+    passages.truncate(limit);
+
+    Ok(passages)
+}
+
+/// One passage as it appears in the prompt, numbered from 1.
+fn format_passage(number: usize, passage: &RetrievedPassage) -> String {
+    format!("[{}] {}\n{}", number, passage.location(), passage.text)
+}
+
+/// The user's turn with the passages given to the model above the question.
+pub fn with_passages(user_message: &str, passages: &[RetrievedPassage]) -> String {
+    if passages.is_empty() {
+        return user_message.to_string();
+    }
+
+    let passages: Vec<String> = passages
+        .iter()
+        .enumerate()
+        .map(|(i, passage)| format_passage(i + 1, passage))
+        .collect();
+
+    format!(
+        "Reference passages from local clinical guidelines:\n\n{}\n\nUse these passages where they are relevant and say so when they don't cover the question. Cite a passage right after each statement based on it, by its number in square brackets such as [1] or [1, 3]; don't cite anything else.\n\nQuestion: {}",
+        passages.join("\n\n"),
+        user_message
+    )
+}
+
+/// Checks the `[n]` and `[n, m]` markers in a reply. `sources` are the
+/// passages in the order they were numbered in the prompt. Only brackets
+/// naming at least one of them are markers; their other numbers are dropped
+/// rather than left pointing at nothing. Other bracketed text, such as
+/// "[2019]", is left alone.
+pub fn check_citations(content: &str, sources: &[SourceReference]) -> CheckedCitations {
+    if sources.is_empty() {
+        return CheckedCitations {
+            content: content.to_string(),
+            citations: Vec::new(),
+            unsupported: Vec::new(),
+        };
+    }
+
+    let mut checked = String::with_capacity(content.len());
+    let mut cited = Vec::new();
+    let mut unsupported = Vec::new();
+
+    let mut rest = content;
+    while let Some(open) = rest.find('[') {
+        checked.push_str(&rest[..open]);
+        let after_open = &rest[open + 1..];
+
+        let marker = after_open
+            .find(']')
+            .and_then(|close| Some((close, parse_marker(&after_open[..close])?)));
+        let Some((close, numbers)) = marker else {
+            checked.push('[');
+            rest = after_open;
+            continue;
+        };
+
+        let (supported, missing): (Vec<usize>, Vec<usize>) = numbers
+            .into_iter()
+            .partition(|number| (1..=sources.len()).contains(number));
+        if supported.is_empty() {
+            checked.push('[');
+            rest = after_open;
+            continue;
+        }
+
+        let numbers: Vec<String> = supported.iter().map(|n| n.to_string()).collect();
+        checked.push_str(&format!("[{}]", numbers.join(", ")));
+        cited.extend(supported);
+        unsupported.extend(missing);
+        rest = &after_open[close + 1..];
+    }
+    checked.push_str(rest);
+
+    cited.sort_unstable();
+    cited.dedup();
+    unsupported.sort_unstable();
+    unsupported.dedup();
+
+    let citations = cited
+        .into_iter()
+        .map(|marker| {
+            let source = &sources[marker - 1];
+            Citation {
+                marker,
+                passage_id: source.passage_id.clone(),
+                document_id: source.document_id.clone(),
+                title: source.title.clone(),
+                section: source.section.clone(),
+                page: source.page,
+            }
+        })
+        .collect();
+
+    CheckedCitations {
+        content: checked,
+        citations,
+        unsupported,
+    }
+}
+
+/// The numbers in a marker's brackets, or `None` if it isn't a marker.
+fn parse_marker(inner: &str) -> Option<Vec<usize>> {
+    inner
+        .split(',')
+        .map(|number| number.trim().parse::<usize>().ok())
+        .collect()
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+fn extract_text(path: &Path) -> Result<(DocumentKind, String)> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    match extension.as_deref() {
+        Some("md") | Some("markdown") => Ok((DocumentKind::Markdown, read_text_file(path)?)),
+        Some("txt") => Ok((DocumentKind::Text, read_text_file(path)?)),
+        Some("pdf") => Ok((DocumentKind::Pdf, extract_pdf_text(path)?)),
+        _ => Err(anyhow!(
+            "Unsupported document type: {} (use Markdown, text or PDF)",
+            path.display()
+        )),
+    }
+}
+
+fn read_text_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))
+}
+
+/// Extracts text with poppler's `pdftotext`, which separates pages with form
+/// feeds.
+fn extract_pdf_text(path: &Path) -> Result<String> {
+    let pdftotext = which::which("pdftotext").map_err(|_| {
+        anyhow!("pdftotext not found; install poppler-utils or convert the PDF to Markdown")
+    })?;
+
+    let output = Command::new(pdftotext)
+        .arg("-layout")
+        .arg(path)
+        .arg("-")
+        .output()
+        .map_err(|e| anyhow!("Failed to run pdftotext: {}", e))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "pdftotext failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The first top-level Markdown heading, otherwise the file name.
+fn document_title(kind: DocumentKind, text: &str, path: &Path) -> String {
+    if kind == DocumentKind::Markdown {
+        if let Some(title) = text.lines().find_map(|line| line.strip_prefix("# ")) {
+            return title.trim().to_string();
+        }
+    }
+
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Untitled".to_string())
+}
+
+/// Splits a document into passages of whole paragraphs. A Markdown heading
+/// always starts a new passage and names the section of those below it;
+/// PDF pages are counted from the form feeds between them.
+fn chunk_text(kind: DocumentKind, text: &str) -> Vec<TextChunk> {
+    let mut chunker = Chunker {
+        chunks: Vec::new(),
+        current: String::new(),
+        current_page: None,
+        section: None,
+        page: (kind == DocumentKind::Pdf).then_some(1),
+    };
+    let mut paragraph = String::new();
+
+    for line in text.lines() {
+        // pdftotext puts a form feed at the start of each new page
+        let mut line = line;
+        while let Some(rest) = line.strip_prefix('\u{c}') {
+            chunker.add_paragraph(&paragraph);
+            paragraph.clear();
+            chunker.page = chunker.page.map(|page| page + 1);
+            line = rest;
+        }
+
+        let heading = if kind == DocumentKind::Markdown && line.trim_start().starts_with('#') {
+            Some(line.trim_start().trim_start_matches('#').trim())
+        } else {
+            None
+        };
+
+        if let Some(heading) = heading {
+            chunker.add_paragraph(&paragraph);
+            paragraph.clear();
+            chunker.finish_chunk();
+            chunker.section = Some(heading.to_string()).filter(|heading| !heading.is_empty());
+        } else if line.trim().is_empty() {
+            chunker.add_paragraph(&paragraph);
+            paragraph.clear();
+        } else {
+            if !paragraph.is_empty() {
+                paragraph.push('\n');
+            }
+            paragraph.push_str(line.trim_end());
+        }
+    }
+
+    chunker.add_paragraph(&paragraph);
+    chunker.finish_chunk();
+    chunker.chunks
+}
+
+/// Collects paragraphs into chunks of about `CHUNK_TARGET_CHARS`.
+struct Chunker {
+    chunks: Vec<TextChunk>,
+    current: String,
+    // Page the chunk being built started on
+    current_page: Option<u32>,
+    section: Option<String>,
+    page: Option<u32>,
+}
+
+impl Chunker {
+    fn add_paragraph(&mut self, paragraph: &str) {
+        let text = paragraph.trim();
+        if text.is_empty() {
+            return;
+        }
+
+        for piece in split_long(text) {
+            // Pieces are joined with a blank line
+            if !self.current.is_empty() && self.current.len() + 2 + piece.len() > CHUNK_TARGET_CHARS
+            {
+                self.finish_chunk();
+            }
+
+            if self.current.is_empty() {
+                self.current_page = self.page;
+            } else {
+                self.current.push_str("\n\n");
+            }
+            self.current.push_str(piece);
+        }
+    }
+
+    fn finish_chunk(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+
+        self.chunks.push(TextChunk {
+            section: self.section.clone(),
+            page: self.current_page,
+            text: std::mem::take(&mut self.current),
+        });
+    }
+}
+
+/// Breaks a paragraph longer than a chunk into chunk-sized pieces, between
+/// words where possible.
+fn split_long(text: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+
+    while rest.len() > CHUNK_TARGET_CHARS {
+        let mut cut = CHUNK_TARGET_CHARS;
+        while !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if let Some(space) = rest[..cut]
+            .rfind(char::is_whitespace)
+            .filter(|&space| space > 0)
+        {
+            cut = space;
+        }
+
+        pieces.push(rest[..cut].trim_end());
+        rest = rest[cut..].trim_start();
+    }
+
+    if !rest.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(count: usize) -> Vec<SourceReference> {
+        (1..=count)
+            .map(|n| SourceReference {
+                passage_id: format!("doc:{}", n),
+                document_id: "doc".to_string(),
+                title: "Guideline".to_string(),
+                section: None,
+                page: Some(n as u32),
+                score: 0.9,
+            })
+            .collect()
+    }
+
+    fn markers(checked: &CheckedCitations) -> Vec<usize> {
+        checked
+            .citations
+            .iter()
+            .map(|citation| citation.marker)
+            .collect()
+    }
+
+    #[test]
+    fn drops_numbers_without_a_passage_from_markers() {
+        let checked = check_citations(
+            "Give fluids [1]. Avoid NSAIDs [2]. Recheck [1, 3].",
+            &sources(2),
+        );
+
+        assert_eq!(
+            checked.content,
+            "Give fluids [1]. Avoid NSAIDs [2]. Recheck [1]."
+        );
+        assert_eq!(markers(&checked), vec![1, 2]);
+        assert_eq!(checked.unsupported, vec![3]);
+    }
+
+    #[test]
+    fn leaves_numbers_outside_the_sources_as_text() {
+        let content = "Per the 2019 update [2019], see table [3].";
+        let checked = check_citations(content, &sources(2));
+
+        assert_eq!(checked.content, content);
+        assert!(checked.citations.is_empty());
+        assert!(checked.unsupported.is_empty());
+    }
+
+    #[test]
+    fn leaves_content_alone_without_sources() {
+        let content = "Give fluids [1], as in [2, 3].";
+        let checked = check_citations(content, &[]);
+
+        assert_eq!(checked.content, content);
+        assert!(checked.citations.is_empty());
+        assert!(checked.unsupported.is_empty());
+    }
+
+    #[test]
+    fn cites_each_passage_once_in_marker_order() {
+        let checked = check_citations("See [2] and [1], then [2, 1].", &sources(2));
+
+        assert_eq!(checked.content, "See [2] and [1], then [2, 1].");
+        assert_eq!(markers(&checked), vec![1, 2]);
+        assert_eq!(checked.citations[1].passage_id, "doc:2");
+    }
+
+    #[test]
+    fn leaves_unclosed_brackets_alone() {
+        let content = "Dose per table [see appendix and [1";
+        let checked = check_citations(content, &sources(1));
+
+        assert_eq!(checked.content, content);
+        assert!(checked.citations.is_empty());
+        assert!(checked.unsupported.is_empty());
+    }
+
+    #[test]
+    fn finds_markers_inside_other_brackets() {
+        let checked = check_citations("Range [[1]] and [a [2]] and [1, x]", &sources(2));
+
+        assert_eq!(checked.content, "Range [[1]] and [a [2]] and [1, x]");
+        assert_eq!(markers(&checked), vec![1, 2]);
+    }
+
+    #[test]
+    fn treats_empty_and_zero_markers_as_text() {
+        let content = "Empty [] and zero [0].";
+        let checked = check_citations(content, &sources(1));
+
+        assert_eq!(checked.content, content);
+        assert!(checked.unsupported.is_empty());
+    }
+
+    #[test]
+    fn counts_pdf_pages_from_form_feeds() {
+        let paragraph = "word ".repeat(160);
+        let text = format!("{p}\n\u{c}{p}\n\u{c}\u{c}{p}", p = paragraph.trim_end());
+
+        let pages: Vec<Option<u32>> = chunk_text(DocumentKind::Pdf, &text)
+            .iter()
+            .map(|chunk| chunk.page)
+            .collect();
+
+        // The empty third page is skipped over
+        assert_eq!(pages, vec![Some(1), Some(2), Some(4)]);
+    }
+
+    #[test]
+    fn pages_start_where_the_chunk_starts() {
+        let chunks = chunk_text(DocumentKind::Pdf, "first page\n\u{c}second page");
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].page, Some(1));
+        assert_eq!(chunks[0].text, "first page\n\nsecond page");
+    }
+
+    #[test]
+    fn markdown_headings_start_sections() {
+        let chunks = chunk_text(
+            DocumentKind::Markdown,
+            "# Malaria\nIntro text.\n\n## Dosing\nLine one\nline two\n\n##\nNo section.",
+        );
+
+        let sections: Vec<(Option<&str>, &str)> = chunks
+            .iter()
+            .map(|chunk| (chunk.section.as_deref(), chunk.text.as_str()))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                (Some("Malaria"), "Intro text."),
+                (Some("Dosing"), "Line one\nline two"),
+                (None, "No section."),
+            ]
+        );
+        assert!(chunks.iter().all(|chunk| chunk.page.is_none()));
+    }
+
+    #[test]
+    fn splits_long_paragraphs_between_words() {
+        let text = "ab ".repeat(1000);
+        let chunks = chunk_text(DocumentKind::Text, &text);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.text.len() <= CHUNK_TARGET_CHARS));
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.text.starts_with("ab") && chunk.text.ends_with("ab")));
+    }
+}
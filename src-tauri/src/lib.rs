@@ -1,19 +1,75 @@
 mod ai_engine;
+mod backend;
+mod calculators;
 mod database;
+mod differential;
+mod engine_log;
+mod hardware;
+mod interactions;
+mod knowledge;
 mod model_manager;
+mod postprocess;
+mod profiles;
+mod prompt_template;
+mod red_flags;
+mod settings;
+mod soap_note;
+mod summarizer;
+mod text;
+mod tools;
 
-use ai_engine::{AIEngine, ChatMessage, ChatRequest, ChatResponse};
+use ai_engine::{
+    AIEngine, ChatMessage, ChatRequest, ChatResponse, ChatStreamChunk, EditMessageRequest,
+    EngineInfo, RedFlagEvent, RegenerateRequest,
+};
 use anyhow::Result;
-use database::{Conversation, Database};
+use backend::{
+    EngineStatusEvent, InferenceBackend, LlamaServerBackend, MockBackend, OpenAiCompatBackend,
+    StatusReporter,
+};
+use calculators::{CalculationResult, CalculatorSpec};
+use chrono::Utc;
+use database::{Conversation, ConversationMessage, ConversationSummary, Database};
+use differential::DifferentialRecord;
+use engine_log::EngineLog;
+use hardware::HardwareInfo;
+use interactions::{DatasetInfo, InteractionChecker, InteractionReport};
+use knowledge::{KnowledgeDocument, RetrievedPassage};
 use model_manager::{ModelInfo, ModelManager};
+use profiles::PromptProfile;
+use red_flags::{AlertSource, RedFlagDetector, RedFlagRule};
+use settings::{BackendConfig, EngineConfig, GenerationSettings, ReplySettings};
+use soap_note::RenderedSoapNote;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use tauri::{AppHandle, Manager};
+use tauri::{AppHandle, Emitter, Manager};
+use tools::{ToolOutput, ToolRegistry, ToolSpec};
+use uuid::Uuid;
+
+// How many models may be loaded into llama-server instances at once
+const MAX_LOADED_ENGINES: usize = 2;
+
+// Lines returned by get_engine_log when the caller doesn't ask for a number
+const DEFAULT_ENGINE_LOG_LINES: usize = 200;
 
 // Application state
 pub struct AppState {
-    pub ai_engine: Arc<Mutex<Option<AIEngine>>>,
+    // Loaded engines keyed by engine id, plus the one used when a request
+    // does not name an engine
+    pub ai_engines: Arc<Mutex<HashMap<String, AIEngine>>>,
+    pub active_engine: Arc<Mutex<Option<String>>>,
+    // Engine ids being started; they count towards MAX_LOADED_ENGINES. Lock
+    // after `ai_engines` when both are needed.
+    pub starting_engines: Arc<Mutex<HashSet<String>>>,
+    // Serves knowledge base embeddings; the active engine does when unset
+    pub embedding_engine: Arc<Mutex<Option<AIEngine>>>,
     pub database: Arc<Mutex<Option<Database>>>,
     pub model_manager: Arc<Mutex<Option<Arc<ModelManager>>>>,
+    // Built-in rules until the rules file has been read
+    pub red_flags: Arc<Mutex<Arc<RedFlagDetector>>>,
+    // Bundled dataset until an imported one has been read
+    pub interactions: Arc<Mutex<Arc<InteractionChecker>>>,
 }
 
 #[tauri::command]
@@ -40,6 +96,19 @@ async fn initialize_app(app_handle: AppHandle) -> Result<String, String> {
     *state.database.lock().unwrap() = Some(database);
     *state.model_manager.lock().unwrap() = Some(Arc::new(model_manager));
 
+    // A broken rules file must not stop the app; the built-in rules stay
+    match RedFlagDetector::load(&app_data_dir.join(red_flags::RULES_FILE_NAME)) {
+        Ok(detector) => *state.red_flags.lock().unwrap() = Arc::new(detector),
+        Err(e) => println!("Failed to load red flag rules, using built-in rules: {}", e),
+    }
+    match InteractionChecker::load(&app_data_dir.join(interactions::DATASET_FILE_NAME)) {
+        Ok(checker) => *state.interactions.lock().unwrap() = Arc::new(checker),
+        Err(e) => println!(
+            "Failed to load drug interaction dataset, using bundled dataset: {}",
+            e
+        ),
+    }
+
     Ok("Application initialized successfully".to_string())
 }
 
@@ -80,18 +149,174 @@ async fn download_model(app_handle: AppHandle, model: ModelInfo) -> Result<Strin
     }
 }
 
+/// Starts an engine for a model and makes it the active one. The engine is
+/// addressed by `engine_id`, which defaults to the model filename;
+/// initializing an id that is already loaded restarts that engine.
 #[tauri::command]
 async fn initialize_ai_engine(
     app_handle: AppHandle,
     model_filename: String,
+    engine_id: Option<String>,
 ) -> Result<String, String> {
     let state = app_handle.state::<AppState>();
+    let engine_id = engine_id.unwrap_or_else(|| model_filename.clone());
+
+    // Reserve the id, and room for a new engine, before anything is awaited
+    // so concurrent calls can't both get past the limit. An engine with the
+    // same id is replaced.
+    let previous_engine = {
+        let mut engines = state.ai_engines.lock().unwrap();
+        let mut starting_engines = state.starting_engines.lock().unwrap();
+        if starting_engines.contains(&engine_id) {
+            return Err(format!("Engine is already starting: {}", engine_id));
+        }
+        let previous_engine = engines.remove(&engine_id);
+        if previous_engine.is_none() && engines.len() + starting_engines.len() >= MAX_LOADED_ENGINES
+        {
+            return Err(format!(
+                "At most {} models can be loaded at once; unload one first",
+                MAX_LOADED_ENGINES
+            ));
+        }
+        starting_engines.insert(engine_id.clone());
+        previous_engine
+    };
+
+    let started = async {
+        if let Some(previous_engine) = previous_engine {
+            if let Err(e) = previous_engine.save_cache().await {
+                println!("Failed to save prompt cache: {}", e);
+            }
+            previous_engine
+                .shutdown()
+                .map_err(|e| format!("Failed to stop previous engine: {}", e))?;
+        }
+
+        let engine_config = get_database(&state)?
+            .get_engine_config()
+            .map_err(|e| format!("Failed to load engine configuration: {}", e))?;
+
+        // Forward status changes (crashes, restarts) to the frontend
+        let status_app_handle = app_handle.clone();
+        let status = StatusReporter::new(
+            &engine_id,
+            Some(Arc::new(move |event: EngineStatusEvent| {
+                let _ = status_app_handle.emit("engine-status", event);
+            })),
+        );
+
+        let backend = create_backend(
+            &app_handle,
+            &model_filename,
+            &engine_id,
+            &engine_config,
+            status.clone(),
+            false,
+        )?;
+        let ai_engine = AIEngine::new(engine_id.clone(), backend, status);
 
-    // Get model path without holding the lock
-    let model_path = {
+        ai_engine
+            .initialize()
+            .await
+            .map_err(|e| format!("Failed to initialize AI engine: {}", e))?;
+
+        Ok::<_, String>(ai_engine)
+    }
+    .await;
+
+    match started {
+        Ok(ai_engine) => {
+            // Stored before the reservation is released, so the engine is
+            // never uncounted
+            state
+                .ai_engines
+                .lock()
+                .unwrap()
+                .insert(engine_id.clone(), ai_engine);
+            state.starting_engines.lock().unwrap().remove(&engine_id);
+            *state.active_engine.lock().unwrap() = Some(engine_id);
+
+            Ok("AI engine initialized successfully".to_string())
+        }
+        Err(e) => {
+            state.starting_engines.lock().unwrap().remove(&engine_id);
+
+            // A replaced engine is gone; don't leave it selected
+            let fallback = state.ai_engines.lock().unwrap().keys().next().cloned();
+            let mut active_engine = state.active_engine.lock().unwrap();
+            if active_engine.as_deref() == Some(engine_id.as_str()) {
+                *active_engine = fallback;
+            }
+
+            Err(e)
+        }
+    }
+}
+
+/// Sets up the configured kind of backend for `model_filename`. An embedding
+/// backend serves the knowledge base instead of chat.
+fn create_backend(
+    app_handle: &AppHandle,
+    model_filename: &str,
+    engine_id: &str,
+    engine_config: &EngineConfig,
+    status: StatusReporter,
+    embeddings: bool,
+) -> Result<Arc<dyn InferenceBackend>, String> {
+    Ok(match &engine_config.backend {
+        BackendConfig::Managed => Arc::new(create_managed_backend(
+            app_handle,
+            model_filename,
+            engine_id,
+            engine_config,
+            status,
+            embeddings,
+        )?),
+        BackendConfig::OpenAiCompatible {
+            base_url,
+            model,
+            api_key,
+        } => {
+            // The configured model is the chat model; the embedding model is
+            // always the one asked for
+            let model = match model {
+                Some(model) if !embeddings => model.as_str(),
+                _ => model_filename,
+            };
+            Arc::new(OpenAiCompatBackend::new(
+                base_url,
+                model,
+                api_key.clone(),
+                engine_config
+                    .context_size
+                    .unwrap_or(backend::DEFAULT_CONTEXT_SIZE),
+                status,
+            ))
+        }
+        BackendConfig::Mock => Arc::new(MockBackend::new(status)),
+    })
+}
+
+/// Sets up a llama-server for `model_filename` with launch options worked out
+/// from the saved overrides and this machine.
+fn create_managed_backend(
+    app_handle: &AppHandle,
+    model_filename: &str,
+    engine_id: &str,
+    engine_config: &EngineConfig,
+    status: StatusReporter,
+    embeddings: bool,
+) -> Result<LlamaServerBackend, String> {
+    let state = app_handle.state::<AppState>();
+
+    // Get model path and prompt format without holding the lock
+    let (model_path, chat_template) = {
         let model_manager_guard = state.model_manager.lock().unwrap();
         if let Some(model_manager) = model_manager_guard.as_ref() {
-            model_manager.get_model_path(&model_filename)
+            (
+                model_manager.get_model_path(model_filename),
+                model_manager.resolve_chat_template(model_filename),
+            )
         } else {
             return Err("Model manager not initialized".to_string());
         }
@@ -101,121 +326,706 @@ async fn initialize_ai_engine(
         return Err(format!("Model file not found: {}", model_path.display()));
     }
 
-    let ai_engine = AIEngine::new(model_path);
+    let model_size = std::fs::metadata(&model_path)
+        .map_err(|e| format!("Failed to read model file: {}", e))?
+        .len();
+    let launch_options = engine_config
+        .resolve(&HardwareInfo::detect(), model_size)
+        .map_err(|e| format!("Invalid engine configuration: {}", e))?;
+
+    let engine_log = EngineLog::new(&get_logs_dir(app_handle)?, engine_id)
+        .map_err(|e| format!("Failed to create engine log: {}", e))?;
+
+    let mut backend = LlamaServerBackend::new(model_path, chat_template, launch_options, status)
+        .with_log(engine_log);
+    if embeddings {
+        return Ok(backend.for_embeddings());
+    }
+
+    // Saved caches only fit the model they were made with. Without
+    // persistence the directory still holds a conversation's cache while
+    // background work borrows its slot.
+    let slot_cache_dir = get_prompt_cache_dir(app_handle)?.join(model_filename);
+    std::fs::create_dir_all(&slot_cache_dir)
+        .map_err(|e| format!("Failed to create prompt cache directory: {}", e))?;
+    if engine_config.persist_prompt_cache {
+        backend = backend.with_slot_cache(slot_cache_dir);
+    } else {
+        backend = backend.with_slot_scratch(slot_cache_dir);
+    }
 
+    Ok(backend)
+}
+
+#[tauri::command]
+async fn list_engines(app_handle: AppHandle) -> Result<Vec<EngineInfo>, String> {
+    let state = app_handle.state::<AppState>();
+    let ai_engines = state.ai_engines.lock().unwrap();
+
+    let mut engines: Vec<EngineInfo> = ai_engines.values().map(|engine| engine.info()).collect();
+    engines.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(engines)
+}
+
+#[tauri::command]
+async fn set_active_engine(app_handle: AppHandle, engine_id: String) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+
+    if !state.ai_engines.lock().unwrap().contains_key(&engine_id) {
+        return Err(format!("Engine not loaded: {}", engine_id));
+    }
+
+    *state.active_engine.lock().unwrap() = Some(engine_id);
+    Ok("Active engine changed".to_string())
+}
+
+/// Returns the last lines of an engine's llama-server output (the active
+/// engine by default). Works for engines that failed to start, too.
+#[tauri::command]
+async fn get_engine_log(
+    app_handle: AppHandle,
+    engine_id: Option<String>,
+    lines: Option<usize>,
+) -> Result<Vec<String>, String> {
+    let state = app_handle.state::<AppState>();
+    let engine_id = match engine_id {
+        Some(engine_id) => engine_id,
+        None => state
+            .active_engine
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| "AI engine not initialized".to_string())?,
+    };
+
+    let engine_log = EngineLog::new(&get_logs_dir(&app_handle)?, &engine_id)
+        .map_err(|e| format!("Failed to open engine log: {}", e))?;
+
+    Ok(engine_log.tail(lines.unwrap_or(DEFAULT_ENGINE_LOG_LINES)))
+}
+
+/// Stops an engine's llama-server and frees its port and memory.
+#[tauri::command]
+async fn unload_engine(app_handle: AppHandle, engine_id: String) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+
+    let ai_engine = state
+        .ai_engines
+        .lock()
+        .unwrap()
+        .remove(&engine_id)
+        .ok_or_else(|| format!("Engine not loaded: {}", engine_id))?;
+
+    {
+        let mut active_engine = state.active_engine.lock().unwrap();
+        if active_engine.as_deref() == Some(engine_id.as_str()) {
+            *active_engine = None;
+        }
+    }
+
+    if let Err(e) = ai_engine.save_cache().await {
+        println!("Failed to save prompt cache: {}", e);
+    }
     ai_engine
+        .shutdown()
+        .map_err(|e| format!("Failed to stop engine: {}", e))?;
+    Ok("Engine unloaded".to_string())
+}
+
+/// Starts the engine that embeds knowledge base documents and questions,
+/// replacing any previous one. It doesn't count towards the chat engine
+/// limit and is never used for chat.
+#[tauri::command]
+async fn initialize_embedding_engine(
+    app_handle: AppHandle,
+    model_filename: String,
+) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+
+    let engine_config = get_database(&state)?
+        .get_engine_config()
+        .map_err(|e| format!("Failed to load engine configuration: {}", e))?;
+
+    let status_app_handle = app_handle.clone();
+    let status = StatusReporter::new(
+        &model_filename,
+        Some(Arc::new(move |event: EngineStatusEvent| {
+            let _ = status_app_handle.emit("engine-status", event);
+        })),
+    );
+
+    let backend = create_backend(
+        &app_handle,
+        &model_filename,
+        &model_filename,
+        &engine_config,
+        status.clone(),
+        true,
+    )?;
+    let embedding_engine = AIEngine::new(model_filename, backend, status);
+
+    embedding_engine
         .initialize()
         .await
-        .map_err(|e| format!("Failed to initialize AI engine: {}", e))?;
+        .map_err(|e| format!("Failed to initialize embedding engine: {}", e))?;
+
+    // The previous engine keeps serving until the new one is up
+    let previous_engine = state
+        .embedding_engine
+        .lock()
+        .unwrap()
+        .replace(embedding_engine);
+    if let Some(previous_engine) = previous_engine {
+        previous_engine
+            .shutdown()
+            .map_err(|e| format!("Failed to stop previous embedding engine: {}", e))?;
+    }
 
-    // Now store the initialized engine
-    *state.ai_engine.lock().unwrap() = Some(ai_engine);
+    Ok("Embedding engine initialized successfully".to_string())
+}
 
-    Ok("AI engine initialized successfully".to_string())
+#[tauri::command]
+async fn unload_embedding_engine(app_handle: AppHandle) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+
+    let embedding_engine = state
+        .embedding_engine
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| "Embedding engine not loaded".to_string())?;
+
+    embedding_engine
+        .shutdown()
+        .map_err(|e| format!("Failed to stop embedding engine: {}", e))?;
+    Ok("Embedding engine unloaded".to_string())
 }
 
 #[tauri::command]
 async fn send_chat_message(
     app_handle: AppHandle,
     request: ChatRequest,
+) -> Result<ChatResponse, String> {
+    process_chat_message(&app_handle, request, false).await
+}
+
+/// Streaming variant of `send_chat_message`. Every generated token is emitted
+/// to the frontend as a `chat-token` event carrying a `ChatStreamChunk`; the
+/// assistant message is only persisted once the stream has completed, after
+/// which a `chat-complete` event with the final `ChatResponse` is emitted.
+#[tauri::command]
+async fn stream_chat_message(
+    app_handle: AppHandle,
+    request: ChatRequest,
+) -> Result<ChatResponse, String> {
+    let response = process_chat_message(&app_handle, request, true).await?;
+
+    let _ = app_handle.emit("chat-complete", response.clone());
+
+    Ok(response)
+}
+
+async fn process_chat_message(
+    app_handle: &AppHandle,
+    request: ChatRequest,
+    stream: bool,
 ) -> Result<ChatResponse, String> {
     let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+    let ai_engine = get_ai_engine(&state, request.engine_id.as_deref())?;
+
+    if let Some(settings) = &request.generation_settings {
+        settings
+            .validate()
+            .map_err(|e| format!("Invalid generation settings: {}", e))?;
+    }
 
     // Get conversation ID or create new one
     let conversation_id = if let Some(id) = request.conversation_id {
         id
     } else {
-        let database = {
-            let db_guard = state.database.lock().unwrap();
-            db_guard.as_ref().cloned()
+        // Counted in chars, not bytes, so accented text and "µg" can't be
+        // cut in the middle of a character
+        let title = if request.message.chars().count() > 50 {
+            format!(
+                "{}...",
+                request.message.chars().take(47).collect::<String>()
+            )
+        } else {
+            request.message.clone()
         };
+        database
+            .create_conversation(&title)
+            .map_err(|e| format!("Failed to create conversation: {}", e))?
+    };
 
-        if let Some(database) = database {
-            let title = if request.message.len() > 50 {
-                format!("{}...", &request.message[..47])
-            } else {
-                request.message.clone()
-            };
-            database
-                .create_conversation(&title)
-                .map_err(|e| format!("Failed to create conversation: {}", e))?
-        } else {
-            return Err("Database not initialized".to_string());
-        }
+    if let Some(profile_id) = &request.profile_id {
+        database
+            .set_conversation_profile(&conversation_id, Some(profile_id))
+            .map_err(|e| format!("Failed to select prompt profile: {}", e))?;
+    }
+
+    // Get conversation history before the new message joins it; the engine
+    // adds that message to the prompt itself
+    let conversation_history = get_active_history(&database, &conversation_id)?;
+
+    // Store user message, continuing the active branch
+    let parent_id = conversation_history
+        .last()
+        .map(|message| message.id.as_str());
+    let user_message_id = database
+        .add_message(&conversation_id, "user", &request.message, parent_id)
+        .map_err(|e| format!("Failed to store user message: {}", e))?;
+    let user_message = database
+        .get_message(&conversation_id, &user_message_id)
+        .map_err(|e| format!("Failed to load user message: {}", e))?
+        .ok_or_else(|| "User message was not stored".to_string())?;
+
+    let generation_settings = match request.generation_settings {
+        Some(settings) => settings,
+        None => database
+            .resolve_generation_settings(&conversation_id)
+            .map_err(|e| format!("Failed to load generation settings: {}", e))?,
     };
 
-    // Store user message
-    let _user_message_id = {
-        let database = {
-            let db_guard = state.database.lock().unwrap();
-            db_guard.as_ref().cloned()
-        };
+    generate_reply(
+        app_handle,
+        ai_engine,
+        &conversation_id,
+        &user_message,
+        conversation_history,
+        generation_settings,
+        stream,
+    )
+    .await
+}
 
-        if let Some(database) = database {
-            database
-                .add_message(&conversation_id, "user", &request.message)
-                .map_err(|e| format!("Failed to store user message: {}", e))?
-        } else {
-            return Err("Database not initialized".to_string());
-        }
+/// Generates another answer to the question behind an assistant message.
+/// The new answer is stored as an alternative next to the old one and
+/// becomes the active one; tokens are streamed like `stream_chat_message`.
+#[tauri::command]
+async fn regenerate_message(
+    app_handle: AppHandle,
+    request: RegenerateRequest,
+) -> Result<ChatResponse, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+    let ai_engine = get_ai_engine(&state, request.engine_id.as_deref())?;
+
+    if let Some(settings) = &request.generation_settings {
+        settings
+            .validate()
+            .map_err(|e| format!("Invalid generation settings: {}", e))?;
+    }
+
+    let conversation_id = request.conversation_id;
+    let message = database
+        .get_message(&conversation_id, &request.message_id)
+        .map_err(|e| format!("Failed to load message: {}", e))?
+        .ok_or_else(|| "Message not found".to_string())?;
+    if message.role != "assistant" {
+        return Err("Only assistant messages can be regenerated".to_string());
+    }
+
+    // The question being answered is the message's parent
+    let active_messages = get_active_history(&database, &conversation_id)?;
+    let user_position = active_messages
+        .iter()
+        .position(|candidate| Some(&candidate.id) == message.parent_id.as_ref())
+        .ok_or_else(|| "Only messages on the current branch can be regenerated".to_string())?;
+    let user_message = active_messages[user_position].clone();
+
+    let generation_settings = match request.generation_settings {
+        Some(settings) => settings,
+        None => database
+            .resolve_generation_settings(&conversation_id)
+            .map_err(|e| format!("Failed to load generation settings: {}", e))?,
     };
 
-    // Get conversation history
-    let conversation_history = {
-        let database = {
-            let db_guard = state.database.lock().unwrap();
-            db_guard.as_ref().cloned()
-        };
+    let history = active_messages[..user_position].to_vec();
+    let response = generate_reply(
+        &app_handle,
+        ai_engine,
+        &conversation_id,
+        &user_message,
+        history,
+        generation_settings,
+        true,
+    )
+    .await?;
 
-        if let Some(database) = database {
-            database
-                .get_conversation_messages(&conversation_id)
-                .map_err(|e| format!("Failed to get conversation history: {}", e))?
-        } else {
-            return Err("Database not initialized".to_string());
-        }
+    let _ = app_handle.emit("chat-complete", response.clone());
+
+    Ok(response)
+}
+
+/// Corrects an earlier question by branching the conversation: the edited
+/// text is stored as a sibling of the original message, answered, and
+/// becomes the active branch. The original branch is kept and can be
+/// switched back to with `set_active_alternative`. Tokens are streamed like
+/// `stream_chat_message`.
+#[tauri::command]
+async fn edit_message(
+    app_handle: AppHandle,
+    request: EditMessageRequest,
+) -> Result<ChatResponse, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+    let ai_engine = get_ai_engine(&state, request.engine_id.as_deref())?;
+
+    if let Some(settings) = &request.generation_settings {
+        settings
+            .validate()
+            .map_err(|e| format!("Invalid generation settings: {}", e))?;
+    }
+    if request.content.trim().is_empty() {
+        return Err("Message must not be empty".to_string());
+    }
+
+    let conversation_id = request.conversation_id;
+    let original = database
+        .get_message(&conversation_id, &request.message_id)
+        .map_err(|e| format!("Failed to load message: {}", e))?
+        .ok_or_else(|| "Message not found".to_string())?;
+    if original.role != "user" {
+        return Err("Only user messages can be edited".to_string());
+    }
+
+    // Everything before the original message stays as it is
+    let active_messages = get_active_history(&database, &conversation_id)?;
+    let position = active_messages
+        .iter()
+        .position(|candidate| candidate.id == original.id)
+        .ok_or_else(|| "Only messages on the current branch can be edited".to_string())?;
+    let history = active_messages[..position].to_vec();
+
+    let user_message_id = database
+        .add_message(
+            &conversation_id,
+            "user",
+            &request.content,
+            original.parent_id.as_deref(),
+        )
+        .map_err(|e| format!("Failed to store edited message: {}", e))?;
+    let user_message = database
+        .get_message(&conversation_id, &user_message_id)
+        .map_err(|e| format!("Failed to load edited message: {}", e))?
+        .ok_or_else(|| "Edited message was not stored".to_string())?;
+
+    let generation_settings = match request.generation_settings {
+        Some(settings) => settings,
+        None => database
+            .resolve_generation_settings(&conversation_id)
+            .map_err(|e| format!("Failed to load generation settings: {}", e))?,
     };
 
+    let response = generate_reply(
+        &app_handle,
+        ai_engine,
+        &conversation_id,
+        &user_message,
+        history,
+        generation_settings,
+        true,
+    )
+    .await?;
+
+    let _ = app_handle.emit("chat-complete", response.clone());
+
+    Ok(response)
+}
+
+/// Generates the assistant's answer to `user_message` and stores it as its
+/// child, making it the active alternative. `history` is the conversation
+/// before `user_message`.
+async fn generate_reply(
+    app_handle: &AppHandle,
+    ai_engine: AIEngine,
+    conversation_id: &str,
+    user_message: &ChatMessage,
+    history: Vec<ChatMessage>,
+    generation_settings: GenerationSettings,
+    stream: bool,
+) -> Result<ChatResponse, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+
+    let profile = database
+        .resolve_prompt_profile(conversation_id)
+        .map_err(|e| format!("Failed to load prompt profile: {}", e))?;
+    let conversation_summary = database
+        .get_conversation_summary(conversation_id)
+        .map_err(|e| format!("Failed to get conversation summary: {}", e))?;
+    let mut conversation_context =
+        summarizer::conversation_context(&profile.system_prompt, history, conversation_summary);
+
+    // Without a usable knowledge base the question is answered as before
+    if let Ok(embedder) = get_embedding_engine(&state) {
+        match knowledge::search(
+            &embedder,
+            &database,
+            &user_message.content,
+            knowledge::RETRIEVAL_LIMIT,
+        )
+        .await
+        {
+            Ok(passages) => conversation_context.passages = passages,
+            Err(e) => println!("Failed to search knowledge base: {}", e),
+        }
+    }
+
+    // Red flags in the question are reported straight away, not only once
+    // the reply is done
+    let red_flags = state.red_flags.lock().unwrap().clone();
+    let mut alerts = Vec::new();
+    red_flags.scan(&user_message.content, AlertSource::UserMessage, &mut alerts);
+    if !alerts.is_empty() {
+        let _ = app_handle.emit(
+            "red-flag-alerts",
+            RedFlagEvent {
+                conversation_id: conversation_id.to_string(),
+                message_id: user_message.id.clone(),
+                alerts: alerts.clone(),
+            },
+        );
+    }
+
+    // The assistant message id is handed out up front so the frontend can
+    // attach streamed tokens to it before the message is persisted
+    let assistant_message_id = Uuid::new_v4().to_string();
+    let tools = get_tool_registry(&state);
+
     // Generate AI response
-    let ai_response = {
-        let ai_engine = {
-            let ai_guard = state.ai_engine.lock().unwrap();
-            ai_guard.as_ref().cloned()
-        };
+    let ai_response = if stream {
+        ai_engine
+            .generate_response_stream(
+                conversation_id,
+                &user_message.content,
+                &conversation_context,
+                &tools,
+                &generation_settings,
+                |token| {
+                    let _ = app_handle.emit(
+                        "chat-token",
+                        ChatStreamChunk {
+                            conversation_id: conversation_id.to_string(),
+                            message_id: assistant_message_id.clone(),
+                            content: token.to_string(),
+                        },
+                    );
+                },
+            )
+            .await
+    } else {
+        ai_engine
+            .generate_response(
+                conversation_id,
+                &user_message.content,
+                &conversation_context,
+                &tools,
+                &generation_settings,
+            )
+            .await
+    }
+    .map_err(|e| format!("Failed to generate AI response: {}", e))?;
 
-        if let Some(ai_engine) = ai_engine {
-            ai_engine
-                .generate_response(&request.message, &conversation_history)
-                .await
-                .map_err(|e| format!("Failed to generate AI response: {}", e))?
-        } else {
-            return Err("AI engine not initialized".to_string());
+    let reply_settings = database
+        .get_reply_settings()
+        .map_err(|e| format!("Failed to get reply settings: {}", e))?;
+    let reply = postprocess::process_reply(&ai_response, &reply_settings);
+
+    // Tool calls are stored ahead of the reply, even when it was cancelled,
+    // so the trail behind it stays complete
+    let mut calculations = Vec::new();
+    for tool_call in ai_response.tool_calls {
+        if let Some(ToolOutput::Calculation(calculation)) = &tool_call.output {
+            calculations.push(calculation.clone());
         }
+        let tool_message = ChatMessage {
+            id: Uuid::new_v4().to_string(),
+            role: "tool".to_string(),
+            content: tool_call.describe(),
+            timestamp: Utc::now(),
+            cancelled: false,
+            parent_id: Some(assistant_message_id.clone()),
+            citations: Vec::new(),
+            tool_call: Some(tool_call),
+        };
+        database
+            .insert_message(conversation_id, &tool_message)
+            .map_err(|e| format!("Failed to store tool call: {}", e))?;
+    }
+
+    // Store AI response, including the partial text of a cancelled generation
+    let assistant_message = ChatMessage {
+        id: assistant_message_id,
+        role: "assistant".to_string(),
+        content: reply.content,
+        timestamp: Utc::now(),
+        cancelled: ai_response.cancelled,
+        parent_id: Some(user_message.id.clone()),
+        citations: reply.citations,
+        tool_call: None,
     };
 
-    // Store AI response
-    let assistant_message_id = {
-        let database = {
-            let db_guard = state.database.lock().unwrap();
-            db_guard.as_ref().cloned()
-        };
+    database
+        .insert_message(conversation_id, &assistant_message)
+        .map_err(|e| format!("Failed to store AI response: {}", e))?;
+    database
+        .set_active_message(conversation_id, &assistant_message.id)
+        .map_err(|e| format!("Failed to select AI response: {}", e))?;
 
-        if let Some(database) = database {
-            database
-                .add_message(&conversation_id, "assistant", &ai_response)
-                .map_err(|e| format!("Failed to store AI response: {}", e))?
-        } else {
-            return Err("Database not initialized".to_string());
+    red_flags.scan(&assistant_message.content, AlertSource::Reply, &mut alerts);
+
+    let interaction_checker = state.interactions.lock().unwrap().clone();
+    let mut drugs = interaction_checker.detect_drugs(&user_message.content);
+    for drug in interaction_checker.detect_drugs(&assistant_message.content) {
+        if !drugs.contains(&drug) {
+            drugs.push(drug);
         }
-    };
+    }
+    let interactions = (drugs.len() >= 2).then(|| interaction_checker.check(&drugs));
+
+    // Fold older turns into the summary without holding up the reply
+    let summary_conversation_id = conversation_id.to_string();
+    tokio::spawn(async move {
+        if let Err(e) =
+            summarizer::update_summary(&ai_engine, &database, &summary_conversation_id).await
+        {
+            println!("Failed to update conversation summary: {}", e);
+        }
+    });
 
     Ok(ChatResponse {
-        message: ai_response,
-        conversation_id,
-        message_id: assistant_message_id,
+        message: assistant_message.content,
+        conversation_id: conversation_id.to_string(),
+        message_id: assistant_message.id,
+        cancelled: assistant_message.cancelled,
+        alerts,
+        interactions,
+        calculations,
+        warnings: reply.warnings,
+        sources: ai_response.sources,
+        citations: assistant_message.citations,
     })
 }
 
+/// The active path of a conversation as plain messages, oldest first.
+fn get_active_history(
+    database: &Database,
+    conversation_id: &str,
+) -> Result<Vec<ChatMessage>, String> {
+    Ok(database
+        .get_active_messages(conversation_id)
+        .map_err(|e| format!("Failed to get conversation history: {}", e))?
+        .into_iter()
+        .map(|active| active.message)
+        .collect())
+}
+
+/// The tools the model may call: every calculator and the interaction
+/// checker as currently loaded.
+fn get_tool_registry(state: &AppState) -> ToolRegistry {
+    let mut tools = ToolRegistry::default();
+    for calculator in calculators::calculators() {
+        tools.register(Arc::new(calculator));
+    }
+    tools.register(state.interactions.lock().unwrap().clone());
+    tools
+}
+
+fn get_database(state: &AppState) -> Result<Database, String> {
+    let db_guard = state.database.lock().unwrap();
+    db_guard
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| "Database not initialized".to_string())
+}
+
+fn get_logs_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+    Ok(app_data_dir.join("logs"))
+}
+
+/// Where llama-server saves prompt caches, one directory per model.
+fn get_prompt_cache_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+    Ok(app_data_dir.join("prompt_cache"))
+}
+
+/// Looks up an engine by id, or the active engine when no id is given.
+fn get_ai_engine(state: &AppState, engine_id: Option<&str>) -> Result<AIEngine, String> {
+    let engine_id = match engine_id {
+        Some(engine_id) => engine_id.to_string(),
+        None => state
+            .active_engine
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| "AI engine not initialized".to_string())?,
+    };
+
+    let ai_engines = state.ai_engines.lock().unwrap();
+    ai_engines
+        .get(&engine_id)
+        .cloned()
+        .ok_or_else(|| format!("Engine not loaded: {}", engine_id))
+}
+
+/// The embedding engine if one is loaded, otherwise the active engine.
+fn get_embedding_engine(state: &AppState) -> Result<AIEngine, String> {
+    let embedding_engine = state.embedding_engine.lock().unwrap().clone();
+    if let Some(embedding_engine) = embedding_engine {
+        return Ok(embedding_engine);
+    }
+
+    // A server we don't run may embed with the chat model; a llama-server
+    // started for chat can't
+    let ai_engine = get_ai_engine(state, None)?;
+    if !ai_engine.provides_embeddings() {
+        return Err(
+            "No embedding engine loaded; load an embedding model for the knowledge base"
+                .to_string(),
+        );
+    }
+    Ok(ai_engine)
+}
+
+/// Stops the reply currently being generated for a conversation. The partial
+/// answer is stored by the pending `send_chat_message`/`stream_chat_message`
+/// call with its `cancelled` flag set.
+#[tauri::command]
+async fn cancel_generation(
+    app_handle: AppHandle,
+    conversation_id: String,
+) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+    let ai_engines: Vec<AIEngine> = state.ai_engines.lock().unwrap().values().cloned().collect();
+
+    if ai_engines.is_empty() {
+        return Err("AI engine not initialized".to_string());
+    }
+
+    // The conversation may be running on any of the loaded engines
+    let mut cancelled = false;
+    for ai_engine in &ai_engines {
+        cancelled |= ai_engine.cancel_generation(&conversation_id);
+    }
+
+    if cancelled {
+        Ok("Generation cancelled".to_string())
+    } else {
+        Err("No generation in progress for this conversation".to_string())
+    }
+}
+
 #[tauri::command]
 async fn get_conversations(app_handle: AppHandle) -> Result<Vec<Conversation>, String> {
     let state = app_handle.state::<AppState>();
@@ -233,11 +1043,13 @@ async fn get_conversations(app_handle: AppHandle) -> Result<Vec<Conversation>, S
     }
 }
 
+/// The conversation's active branch, with how many siblings (alternative
+/// answers or edited questions) each message has.
 #[tauri::command]
 async fn get_conversation_messages(
     app_handle: AppHandle,
     conversation_id: String,
-) -> Result<Vec<ChatMessage>, String> {
+) -> Result<Vec<ConversationMessage>, String> {
     let state = app_handle.state::<AppState>();
     let database = {
         let db_guard = state.database.lock().unwrap();
@@ -246,13 +1058,266 @@ async fn get_conversation_messages(
 
     if let Some(database) = database {
         database
-            .get_conversation_messages(&conversation_id)
+            .get_active_messages(&conversation_id)
             .map_err(|e| format!("Failed to get conversation messages: {}", e))
     } else {
         Err("Database not initialized".to_string())
     }
 }
 
+/// A message together with its siblings, oldest first.
+#[tauri::command]
+async fn get_message_alternatives(
+    app_handle: AppHandle,
+    conversation_id: String,
+    message_id: String,
+) -> Result<Vec<ChatMessage>, String> {
+    let state = app_handle.state::<AppState>();
+    get_database(&state)?
+        .get_message_siblings(&conversation_id, &message_id)
+        .map_err(|e| format!("Failed to get message alternatives: {}", e))
+}
+
+/// Switches to the branch through `message_id`, shown instead of its
+/// siblings and used as context for what follows.
+#[tauri::command]
+async fn set_active_alternative(
+    app_handle: AppHandle,
+    conversation_id: String,
+    message_id: String,
+) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+    get_database(&state)?
+        .set_active_message(&conversation_id, &message_id)
+        .map_err(|e| format!("Failed to select alternative: {}", e))?;
+    Ok("Alternative selected".to_string())
+}
+
+#[tauri::command]
+async fn get_conversation_summary(
+    app_handle: AppHandle,
+    conversation_id: String,
+) -> Result<Option<ConversationSummary>, String> {
+    let state = app_handle.state::<AppState>();
+    get_database(&state)?
+        .get_conversation_summary(&conversation_id)
+        .map_err(|e| format!("Failed to get conversation summary: {}", e))
+}
+
+/// Writes a SOAP note from the conversation's active branch and stores it
+/// with the conversation.
+#[tauri::command]
+async fn generate_soap_note(
+    app_handle: AppHandle,
+    conversation_id: String,
+    engine_id: Option<String>,
+) -> Result<RenderedSoapNote, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+    let ai_engine = get_ai_engine(&state, engine_id.as_deref())?;
+
+    soap_note::generate_soap_note(&ai_engine, &database, &conversation_id)
+        .await
+        .map(RenderedSoapNote::from)
+        .map_err(|e| format!("Failed to generate SOAP note: {}", e))
+}
+
+#[tauri::command]
+async fn get_soap_notes(
+    app_handle: AppHandle,
+    conversation_id: String,
+) -> Result<Vec<RenderedSoapNote>, String> {
+    let state = app_handle.state::<AppState>();
+    let notes = get_database(&state)?
+        .get_soap_notes(&conversation_id)
+        .map_err(|e| format!("Failed to get SOAP notes: {}", e))?;
+
+    Ok(notes.into_iter().map(RenderedSoapNote::from).collect())
+}
+
+/// Builds a structured differential diagnosis from the conversation's active
+/// branch and stores it with the conversation.
+#[tauri::command]
+async fn generate_differential(
+    app_handle: AppHandle,
+    conversation_id: String,
+    engine_id: Option<String>,
+) -> Result<DifferentialRecord, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+    let ai_engine = get_ai_engine(&state, engine_id.as_deref())?;
+
+    differential::generate_differential(&ai_engine, &database, &conversation_id)
+        .await
+        .map_err(|e| format!("Failed to generate differential: {}", e))
+}
+
+#[tauri::command]
+async fn get_differentials(
+    app_handle: AppHandle,
+    conversation_id: String,
+) -> Result<Vec<DifferentialRecord>, String> {
+    let state = app_handle.state::<AppState>();
+    get_database(&state)?
+        .get_differentials(&conversation_id)
+        .map_err(|e| format!("Failed to get differentials: {}", e))
+}
+
+#[tauri::command]
+async fn get_red_flag_rules(app_handle: AppHandle) -> Result<Vec<RedFlagRule>, String> {
+    let state = app_handle.state::<AppState>();
+    let red_flags = state.red_flags.lock().unwrap().clone();
+    Ok(red_flags.rules().to_vec())
+}
+
+/// Rereads the rules file after it was edited. Returns how many rules are in
+/// effect; on error the previous rules stay.
+#[tauri::command]
+async fn reload_red_flag_rules(app_handle: AppHandle) -> Result<usize, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+    let detector = RedFlagDetector::load(&app_data_dir.join(red_flags::RULES_FILE_NAME))
+        .map_err(|e| format!("Failed to load red flag rules: {}", e))?;
+    let rule_count = detector.rules().len();
+
+    let state = app_handle.state::<AppState>();
+    *state.red_flags.lock().unwrap() = Arc::new(detector);
+
+    Ok(rule_count)
+}
+
+/// The tools offered to the model, with the schemas of their arguments.
+#[tauri::command]
+async fn get_tools(app_handle: AppHandle) -> Result<Vec<ToolSpec>, String> {
+    let state = app_handle.state::<AppState>();
+    Ok(get_tool_registry(&state).specs())
+}
+
+#[tauri::command]
+async fn get_calculators() -> Result<Vec<CalculatorSpec>, String> {
+    Ok(calculators::calculators())
+}
+
+/// Runs a clinical calculator directly, with the same unit checks as when
+/// the model calls it.
+#[tauri::command]
+async fn run_calculator(
+    calculator: String,
+    arguments: serde_json::Value,
+) -> Result<CalculationResult, String> {
+    calculators::calculate(&calculator, arguments)
+        .map_err(|e| format!("Failed to run calculator: {}", e))
+}
+
+/// Looks up interactions between the given drugs in the local dataset.
+#[tauri::command]
+async fn check_interactions(
+    app_handle: AppHandle,
+    drugs: Vec<String>,
+) -> Result<InteractionReport, String> {
+    let state = app_handle.state::<AppState>();
+    let interaction_checker = state.interactions.lock().unwrap().clone();
+    Ok(interaction_checker.check(&drugs))
+}
+
+#[tauri::command]
+async fn get_interaction_dataset_info(app_handle: AppHandle) -> Result<DatasetInfo, String> {
+    let state = app_handle.state::<AppState>();
+    let interaction_checker = state.interactions.lock().unwrap().clone();
+    Ok(interaction_checker.info())
+}
+
+/// Replaces the interaction dataset with a maintained `.json` or `.csv`
+/// file. On error the current dataset stays.
+#[tauri::command]
+async fn import_interaction_dataset(
+    app_handle: AppHandle,
+    path: String,
+    version: Option<String>,
+) -> Result<DatasetInfo, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+    let checker = InteractionChecker::import(
+        &PathBuf::from(path),
+        &app_data_dir.join(interactions::DATASET_FILE_NAME),
+        version.as_deref(),
+    )
+    .map_err(|e| format!("Failed to import interaction dataset: {}", e))?;
+    let info = checker.info();
+
+    let state = app_handle.state::<AppState>();
+    *state.interactions.lock().unwrap() = Arc::new(checker);
+
+    Ok(info)
+}
+
+/// Adds a Markdown, text or PDF file to the knowledge base. Its passages are
+/// only searched while the engine that embedded them is loaded.
+#[tauri::command]
+async fn add_knowledge_document(
+    app_handle: AppHandle,
+    path: String,
+) -> Result<KnowledgeDocument, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+    let embedder = get_embedding_engine(&state)?;
+
+    knowledge::ingest_document(&embedder, &database, &PathBuf::from(path))
+        .await
+        .map_err(|e| format!("Failed to add document: {}", e))
+}
+
+#[tauri::command]
+async fn get_knowledge_documents(app_handle: AppHandle) -> Result<Vec<KnowledgeDocument>, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+
+    database
+        .get_knowledge_documents()
+        .map_err(|e| format!("Failed to get knowledge base documents: {}", e))
+}
+
+#[tauri::command]
+async fn delete_knowledge_document(
+    app_handle: AppHandle,
+    document_id: String,
+) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+
+    database
+        .delete_knowledge_document(&document_id)
+        .map_err(|e| format!("Failed to delete document: {}", e))?;
+
+    Ok("Document deleted successfully".to_string())
+}
+
+/// Runs a knowledge base search the way chat does, for checking what a
+/// question would pull in.
+#[tauri::command]
+async fn search_knowledge_base(
+    app_handle: AppHandle,
+    query: String,
+    limit: Option<usize>,
+) -> Result<Vec<RetrievedPassage>, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+    let embedder = get_embedding_engine(&state)?;
+
+    knowledge::search(
+        &embedder,
+        &database,
+        &query,
+        limit.unwrap_or(knowledge::RETRIEVAL_LIMIT),
+    )
+    .await
+    .map_err(|e| format!("Failed to search knowledge base: {}", e))
+}
+
 #[tauri::command]
 async fn delete_conversation(
     app_handle: AppHandle,
@@ -268,12 +1333,227 @@ async fn delete_conversation(
         database
             .delete_conversation(&conversation_id)
             .map_err(|e| format!("Failed to delete conversation: {}", e))?;
+        backend::remove_prompt_cache(&get_prompt_cache_dir(&app_handle)?, &conversation_id)
+            .map_err(|e| format!("Failed to delete conversation's prompt cache: {}", e))?;
         Ok("Conversation deleted successfully".to_string())
     } else {
         Err("Database not initialized".to_string())
     }
 }
 
+#[tauri::command]
+async fn get_prompt_profiles(app_handle: AppHandle) -> Result<Vec<PromptProfile>, String> {
+    let state = app_handle.state::<AppState>();
+    get_database(&state)?
+        .get_prompt_profiles()
+        .map_err(|e| format!("Failed to load prompt profiles: {}", e))
+}
+
+/// Creates or updates a profile. Editing a built-in profile stores an
+/// override of it.
+#[tauri::command]
+async fn save_prompt_profile(
+    app_handle: AppHandle,
+    profile: PromptProfile,
+) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+    get_database(&state)?
+        .save_prompt_profile(&profile)
+        .map_err(|e| format!("Failed to save prompt profile: {}", e))?;
+    Ok("Prompt profile saved".to_string())
+}
+
+/// Deletes a custom profile or resets a built-in one to its original text.
+#[tauri::command]
+async fn delete_prompt_profile(
+    app_handle: AppHandle,
+    profile_id: String,
+) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+    get_database(&state)?
+        .delete_prompt_profile(&profile_id)
+        .map_err(|e| format!("Failed to delete prompt profile: {}", e))?;
+    Ok("Prompt profile deleted".to_string())
+}
+
+/// The profile a conversation uses, or the default one when no conversation
+/// is given.
+#[tauri::command]
+async fn get_conversation_profile(
+    app_handle: AppHandle,
+    conversation_id: Option<String>,
+) -> Result<PromptProfile, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+
+    match conversation_id {
+        Some(conversation_id) => database.resolve_prompt_profile(&conversation_id),
+        None => database
+            .get_prompt_profile(profiles::DEFAULT_PROFILE_ID)
+            .and_then(|profile| profile.ok_or_else(|| anyhow::anyhow!("Default profile missing"))),
+    }
+    .map_err(|e| format!("Failed to load prompt profile: {}", e))
+}
+
+/// Selects the profile a conversation uses; `None` goes back to the default.
+#[tauri::command]
+async fn set_conversation_profile(
+    app_handle: AppHandle,
+    conversation_id: String,
+    profile_id: Option<String>,
+) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+    get_database(&state)?
+        .set_conversation_profile(&conversation_id, profile_id.as_deref())
+        .map_err(|e| format!("Failed to select prompt profile: {}", e))?;
+    Ok("Prompt profile selected".to_string())
+}
+
+/// All profiles as JSON, for saving to a file and sharing.
+#[tauri::command]
+async fn export_prompt_profiles(app_handle: AppHandle) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+    let saved = get_database(&state)?
+        .get_prompt_profiles()
+        .map_err(|e| format!("Failed to load prompt profiles: {}", e))?;
+    profiles::export_profiles(&saved).map_err(|e| format!("Failed to export profiles: {}", e))
+}
+
+/// Adds the profiles in an exported JSON file, replacing profiles with the
+/// same id. Returns how many were imported; nothing is imported if any
+/// profile is invalid.
+#[tauri::command]
+async fn import_prompt_profiles(app_handle: AppHandle, json: String) -> Result<usize, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+
+    let imported = profiles::import_profiles(&json)
+        .map_err(|e| format!("Failed to import profiles: {}", e))?;
+    for profile in &imported {
+        database
+            .save_prompt_profile(profile)
+            .map_err(|e| format!("Failed to save prompt profile: {}", e))?;
+    }
+
+    Ok(imported.len())
+}
+
+/// Returns the generation settings in effect for a conversation, or the saved
+/// defaults when no conversation is given.
+#[tauri::command]
+async fn get_generation_settings(
+    app_handle: AppHandle,
+    conversation_id: Option<String>,
+) -> Result<GenerationSettings, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+
+    match conversation_id {
+        Some(conversation_id) => database.resolve_generation_settings(&conversation_id),
+        None => database.get_default_generation_settings(),
+    }
+    .map_err(|e| format!("Failed to load generation settings: {}", e))
+}
+
+#[tauri::command]
+async fn save_default_generation_settings(
+    app_handle: AppHandle,
+    settings: GenerationSettings,
+) -> Result<String, String> {
+    settings
+        .validate()
+        .map_err(|e| format!("Invalid generation settings: {}", e))?;
+
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+
+    database
+        .save_default_generation_settings(&settings)
+        .map_err(|e| format!("Failed to save generation settings: {}", e))?;
+    Ok("Default generation settings saved".to_string())
+}
+
+/// Overrides the generation settings of one conversation. Passing no settings
+/// removes the override so the conversation follows the defaults again.
+#[tauri::command]
+async fn set_conversation_generation_settings(
+    app_handle: AppHandle,
+    conversation_id: String,
+    settings: Option<GenerationSettings>,
+) -> Result<String, String> {
+    if let Some(settings) = &settings {
+        settings
+            .validate()
+            .map_err(|e| format!("Invalid generation settings: {}", e))?;
+    }
+
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+
+    database
+        .set_conversation_generation_settings(&conversation_id, settings.as_ref())
+        .map_err(|e| format!("Failed to save generation settings: {}", e))?;
+    Ok("Conversation generation settings saved".to_string())
+}
+
+#[tauri::command]
+async fn get_hardware_info() -> Result<HardwareInfo, String> {
+    Ok(HardwareInfo::detect())
+}
+
+#[tauri::command]
+async fn get_engine_config(app_handle: AppHandle) -> Result<EngineConfig, String> {
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+
+    database
+        .get_engine_config()
+        .map_err(|e| format!("Failed to load engine configuration: {}", e))
+}
+
+/// Saves llama-server launch overrides. They take effect the next time the AI
+/// engine is initialized.
+#[tauri::command]
+async fn save_engine_config(app_handle: AppHandle, config: EngineConfig) -> Result<String, String> {
+    config
+        .validate()
+        .map_err(|e| format!("Invalid engine configuration: {}", e))?;
+
+    let state = app_handle.state::<AppState>();
+    let database = get_database(&state)?;
+
+    database
+        .save_engine_config(&config)
+        .map_err(|e| format!("Failed to save engine configuration: {}", e))?;
+    Ok("Engine configuration saved".to_string())
+}
+
+#[tauri::command]
+async fn get_reply_settings(app_handle: AppHandle) -> Result<ReplySettings, String> {
+    let state = app_handle.state::<AppState>();
+    get_database(&state)?
+        .get_reply_settings()
+        .map_err(|e| format!("Failed to get reply settings: {}", e))
+}
+
+/// Saves how replies are post-processed, e.g. the disclaimer footer. Applies
+/// to replies generated from now on.
+#[tauri::command]
+async fn save_reply_settings(
+    app_handle: AppHandle,
+    settings: ReplySettings,
+) -> Result<String, String> {
+    settings
+        .validate()
+        .map_err(|e| format!("Invalid reply settings: {}", e))?;
+
+    let state = app_handle.state::<AppState>();
+    get_database(&state)?
+        .save_reply_settings(&settings)
+        .map_err(|e| format!("Failed to save reply settings: {}", e))?;
+    Ok("Reply settings saved".to_string())
+}
+
 #[tauri::command]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
@@ -286,9 +1566,14 @@ pub fn run() {
         .plugin(tauri_plugin_http::init())
         .plugin(tauri_plugin_fs::init())
         .manage(AppState {
-            ai_engine: Arc::new(Mutex::new(None)),
+            ai_engines: Arc::new(Mutex::new(HashMap::new())),
+            active_engine: Arc::new(Mutex::new(None)),
+            starting_engines: Arc::new(Mutex::new(HashSet::new())),
+            embedding_engine: Arc::new(Mutex::new(None)),
             database: Arc::new(Mutex::new(None)),
             model_manager: Arc::new(Mutex::new(None)),
+            red_flags: Arc::new(Mutex::new(Arc::new(RedFlagDetector::builtin()))),
+            interactions: Arc::new(Mutex::new(Arc::new(InteractionChecker::bundled()))),
         })
         .invoke_handler(tauri::generate_handler![
             greet,
@@ -296,10 +1581,54 @@ pub fn run() {
             get_available_models,
             download_model,
             initialize_ai_engine,
+            list_engines,
+            set_active_engine,
+            unload_engine,
+            initialize_embedding_engine,
+            unload_embedding_engine,
+            get_engine_log,
             send_chat_message,
+            stream_chat_message,
+            cancel_generation,
             get_conversations,
             get_conversation_messages,
-            delete_conversation
+            get_message_alternatives,
+            set_active_alternative,
+            regenerate_message,
+            edit_message,
+            get_conversation_summary,
+            generate_soap_note,
+            get_soap_notes,
+            generate_differential,
+            get_differentials,
+            get_red_flag_rules,
+            reload_red_flag_rules,
+            get_tools,
+            get_calculators,
+            run_calculator,
+            check_interactions,
+            get_interaction_dataset_info,
+            import_interaction_dataset,
+            add_knowledge_document,
+            get_knowledge_documents,
+            delete_knowledge_document,
+            search_knowledge_base,
+            delete_conversation,
+            get_prompt_profiles,
+            save_prompt_profile,
+            delete_prompt_profile,
+            get_conversation_profile,
+            set_conversation_profile,
+            export_prompt_profiles,
+            import_prompt_profiles,
+            get_generation_settings,
+            save_default_generation_settings,
+            set_conversation_generation_settings,
+            get_hardware_info,
+            get_engine_config,
+            save_engine_config,
+            get_reply_settings,
+            save_reply_settings
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -1,3 +1,4 @@
+use crate::prompt_template::{read_gguf_chat_template, ChatTemplate};
 use anyhow::{anyhow, Result};
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
@@ -13,6 +14,13 @@ pub struct ModelInfo {
     pub download_url: String,
     pub filename: String,
     pub is_downloaded: bool,
+    /// Prompt format used by the model, e.g. "llama3" or "chatml". When unset
+    /// the format is detected from the GGUF metadata.
+    #[serde(default)]
+    pub chat_template: Option<String>,
+    /// Embeds knowledge base text instead of chatting
+    #[serde(default)]
+    pub embedding: bool,
 }
 
 pub struct ModelManager {
@@ -36,6 +44,8 @@ impl ModelManager {
                 download_url: "https://huggingface.co/bartowski/Llama-3.2-3B-Instruct-GGUF/resolve/main/Llama-3.2-3B-Instruct-Q4_K_M.gguf".to_string(),
                 filename: "llama-3.2-3b-instruct-q4.gguf".to_string(),
                 is_downloaded: self.is_model_downloaded("llama-3.2-3b-instruct-q4.gguf"),
+                chat_template: Some("llama3".to_string()),
+                embedding: false,
             },
             ModelInfo {
                 name: "Llama 3.1 8B Instruct (Q4)".to_string(),
@@ -44,6 +54,8 @@ impl ModelManager {
                 download_url: "https://huggingface.co/bartowski/Meta-Llama-3.1-8B-Instruct-GGUF/resolve/main/Meta-Llama-3.1-8B-Instruct-Q4_K_M.gguf".to_string(),
                 filename: "llama-3.1-8b-instruct-q4.gguf".to_string(),
                 is_downloaded: self.is_model_downloaded("llama-3.1-8b-instruct-q4.gguf"),
+                chat_template: Some("llama3".to_string()),
+                embedding: false,
             },
             ModelInfo {
                 name: "OpenBioLLM 8B (Q4)".to_string(),
@@ -52,6 +64,18 @@ impl ModelManager {
                 download_url: "https://huggingface.co/aaditya/OpenBioLLM-Llama3-8B-GGUF/resolve/main/openbiollm-llama3-8b.Q4_K_M.gguf".to_string(),
                 filename: "openbiollm-llama3-8b-q4.gguf".to_string(),
                 is_downloaded: self.is_model_downloaded("openbiollm-llama3-8b-q4.gguf"),
+                chat_template: Some("llama3".to_string()),
+                embedding: false,
+            },
+            ModelInfo {
+                name: "Nomic Embed Text v1.5 (Q4)".to_string(),
+                size: 84_000_000, // ~84MB
+                description: "Embedding model for searching the knowledge base".to_string(),
+                download_url: "https://huggingface.co/nomic-ai/nomic-embed-text-v1.5-GGUF/resolve/main/nomic-embed-text-v1.5.Q4_K_M.gguf".to_string(),
+                filename: "nomic-embed-text-v1.5-q4.gguf".to_string(),
+                is_downloaded: self.is_model_downloaded("nomic-embed-text-v1.5-q4.gguf"),
+                chat_template: None,
+                embedding: true,
             },
         ]
     }
@@ -64,6 +88,32 @@ impl ModelManager {
         self.models_dir.join(filename)
     }
 
+    /// Picks the chat template for a model: the catalog entry wins, then the
+    /// template embedded in the GGUF, then the plain transcript format.
+    pub fn resolve_chat_template(&self, filename: &str) -> ChatTemplate {
+        let catalog_template = self
+            .get_available_models()
+            .into_iter()
+            .find(|model| model.filename == filename)
+            .and_then(|model| model.chat_template)
+            .and_then(|name| ChatTemplate::from_name(&name));
+
+        if let Some(template) = catalog_template {
+            return template;
+        }
+
+        match read_gguf_chat_template(&self.get_model_path(filename)) {
+            Ok(Some(jinja_template)) => {
+                ChatTemplate::detect(&jinja_template).unwrap_or(ChatTemplate::Plain)
+            }
+            Ok(None) => ChatTemplate::Plain,
+            Err(e) => {
+                println!("Failed to read chat template from {}: {}", filename, e);
+                ChatTemplate::Plain
+            }
+        }
+    }
+
     pub async fn download_model(
         &self,
         model: &ModelInfo,
@@ -0,0 +1,294 @@
+use crate::ai_engine::Generation;
+use crate::backend::FinishReason;
+use crate::knowledge::{self, Citation};
+use crate::settings::ReplySettings;
+use serde::{Deserialize, Serialize};
+
+// Role labels models sometimes repeat at the start of their reply
+const ECHOED_PREFIXES: &[&str] = &["<|assistant|>", "### Assistant:", "Assistant:", "AI:"];
+
+// A line starting with one of these at the start of the reply or after a
+// blank line, the way templates lay out turns, means the model went on to
+// write the next turn itself; everything from there on is dropped
+const TURN_MARKERS: &[&str] = &[
+    "Human:",
+    "User:",
+    "### Human:",
+    "### User:",
+    "### Instruction:",
+    "<|user|>",
+    "<|start_header_id|>user",
+];
+
+// Special tokens that leak into the text when a template doesn't list them
+// as stop strings
+const TEMPLATE_TOKENS: &[&str] = &["<|eot_id|>", "<|im_end|>", "<|end|>", "</s>"];
+
+// Put between a reply and its disclaimer, which is wrapped in `*`
+const FOOTER_SEPARATOR: &str = "\n\n---\n*";
+
+/// Something wrong with a reply that the user should know about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplyWarning {
+    /// The model produced nothing usable
+    Empty,
+    /// Generation hit the token limit, so the reply is cut off
+    Truncated,
+    /// The reply cited passages it wasn't given; those numbers were removed
+    /// from its markers
+    UnsupportedCitation,
+    /// The model wanted more tool calls than allowed, so it answered without
+    /// some results
+    ToolLimitReached,
+}
+
+/// A reply ready to be stored.
+#[derive(Debug, Clone)]
+pub struct ProcessedReply {
+    pub content: String,
+    pub warnings: Vec<ReplyWarning>,
+    pub citations: Vec<Citation>,
+}
+
+/// Cleans up a generated reply and adds the disclaimer. Runs on every reply
+/// before it is stored, cancelled ones included.
+pub fn process_reply(generation: &Generation, settings: &ReplySettings) -> ProcessedReply {
+    let mut content = generation.content.clone();
+    for token in TEMPLATE_TOKENS {
+        content = content.replace(token, "");
+    }
+    let content = cut_at_turn_marker(&content);
+    let content = strip_echoed_prefixes(content).trim();
+    let checked = knowledge::check_citations(content, &generation.sources);
+    let mut content = checked.content.trim().to_string();
+
+    let mut warnings = Vec::new();
+    if content.is_empty() && !generation.cancelled {
+        warnings.push(ReplyWarning::Empty);
+    }
+    if generation.finish_reason == Some(FinishReason::Length) {
+        warnings.push(ReplyWarning::Truncated);
+    }
+    if !checked.unsupported.is_empty() {
+        warnings.push(ReplyWarning::UnsupportedCitation);
+    }
+    if generation.tool_limit_reached {
+        warnings.push(ReplyWarning::ToolLimitReached);
+    }
+
+    let disclaimer = settings.disclaimer.trim();
+    if settings.append_disclaimer && !content.is_empty() && !content.contains(disclaimer) {
+        content.push_str(&format!("{}{}*", FOOTER_SEPARATOR, disclaimer));
+    }
+
+    ProcessedReply {
+        content,
+        warnings,
+        citations: checked.citations,
+    }
+}
+
+/// `content` without the disclaimer footer `process_reply` added, for
+/// sending a stored reply back to the model.
+pub fn without_footer(content: &str) -> &str {
+    match content.rfind(FOOTER_SEPARATOR) {
+        Some(start)
+            if content.ends_with('*')
+                && !content[start + FOOTER_SEPARATOR.len()..].contains('\n') =>
+        {
+            &content[..start]
+        }
+        _ => content,
+    }
+}
+
+/// Drops everything from the first line that starts a new turn. Only lines
+/// at the start of the reply or after a blank line count, so a "User:" line
+/// inside the answer itself stays.
+fn cut_at_turn_marker(content: &str) -> &str {
+    let mut line_start = 0;
+    let mut after_blank_line = true;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if after_blank_line
+            && TURN_MARKERS
+                .iter()
+                .any(|marker| starts_with_ignore_case(trimmed, marker))
+        {
+            return &content[..line_start];
+        }
+        after_blank_line = trimmed.is_empty();
+        line_start += line.len();
+    }
+    content
+}
+
+fn strip_echoed_prefixes(mut content: &str) -> &str {
+    loop {
+        content = content.trim_start();
+        match ECHOED_PREFIXES
+            .iter()
+            .find(|prefix| starts_with_ignore_case(content, prefix))
+        {
+            Some(prefix) => content = &content[prefix.len()..],
+            None => return content,
+        }
+    }
+}
+
+// The markers are ASCII, so a match covers exactly `prefix.len()` bytes
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::knowledge::SourceReference;
+
+    fn generation(content: &str) -> Generation {
+        Generation {
+            content: content.to_string(),
+            cancelled: false,
+            finish_reason: Some(FinishReason::Stop),
+            sources: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_limit_reached: false,
+        }
+    }
+
+    fn no_disclaimer() -> ReplySettings {
+        ReplySettings {
+            append_disclaimer: false,
+            ..ReplySettings::default()
+        }
+    }
+
+    #[test]
+    fn cuts_at_a_turn_marker_after_a_blank_line() {
+        assert_eq!(
+            cut_at_turn_marker("Rest and fluids.\n\nUser: thanks\nAssistant: welcome"),
+            "Rest and fluids.\n\n"
+        );
+        assert_eq!(cut_at_turn_marker("### Human: hi"), "");
+        assert_eq!(cut_at_turn_marker("Rest.\n\n  <|user|> more"), "Rest.\n\n");
+    }
+
+    #[test]
+    fn keeps_turn_markers_inside_the_answer() {
+        let content = "Ask the patient:\nUser: where does it hurt?";
+        assert_eq!(cut_at_turn_marker(content), content);
+
+        let content = "The form has a field labelled User: and a date.";
+        assert_eq!(cut_at_turn_marker(content), content);
+    }
+
+    #[test]
+    fn strips_repeated_role_labels() {
+        assert_eq!(strip_echoed_prefixes("Assistant: Rest."), "Rest.");
+        assert_eq!(
+            strip_echoed_prefixes("  <|assistant|>\nassistant: AI: Rest."),
+            "Rest."
+        );
+        assert_eq!(
+            strip_echoed_prefixes("Rest. Assistant: no"),
+            "Rest. Assistant: no"
+        );
+    }
+
+    #[test]
+    fn cleans_up_the_reply() {
+        let reply = process_reply(
+            &generation("Assistant: Rest and fluids.<|eot_id|>\n\nUser: thanks"),
+            &no_disclaimer(),
+        );
+
+        assert_eq!(reply.content, "Rest and fluids.");
+        assert!(reply.warnings.is_empty());
+    }
+
+    #[test]
+    fn appends_the_disclaimer_once() {
+        let settings = ReplySettings::default();
+        let reply = process_reply(&generation("Rest and fluids."), &settings);
+        assert!(reply.content.starts_with("Rest and fluids.\n\n---\n*"));
+        assert!(reply
+            .content
+            .ends_with(&format!("{}*", settings.disclaimer)));
+
+        let again = process_reply(&generation(&reply.content), &settings);
+        assert_eq!(again.content, reply.content);
+    }
+
+    #[test]
+    fn keeps_the_disclaimer_out_of_prompts() {
+        let reply = process_reply(&generation("Rest and fluids."), &ReplySettings::default());
+        assert_eq!(without_footer(&reply.content), "Rest and fluids.");
+    }
+
+    #[test]
+    fn leaves_replies_without_a_footer_alone() {
+        assert_eq!(without_footer("Rest and fluids."), "Rest and fluids.");
+
+        let content = "Rest.\n\n---\n*Note* below\nand more text";
+        assert_eq!(without_footer(content), content);
+    }
+
+    #[test]
+    fn warns_about_empty_replies() {
+        let reply = process_reply(
+            &generation("Assistant: <|eot_id|>"),
+            &ReplySettings::default(),
+        );
+        assert_eq!(reply.content, "");
+        assert_eq!(reply.warnings, vec![ReplyWarning::Empty]);
+    }
+
+    #[test]
+    fn does_not_warn_about_cancelled_empty_replies() {
+        let mut cancelled = generation("");
+        cancelled.cancelled = true;
+        cancelled.finish_reason = None;
+
+        let reply = process_reply(&cancelled, &no_disclaimer());
+        assert!(reply.warnings.is_empty());
+    }
+
+    #[test]
+    fn warns_about_truncated_replies() {
+        let mut truncated = generation("Rest and");
+        truncated.finish_reason = Some(FinishReason::Length);
+
+        let reply = process_reply(&truncated, &no_disclaimer());
+        assert_eq!(reply.warnings, vec![ReplyWarning::Truncated]);
+    }
+
+    #[test]
+    fn warns_about_unsupported_citations() {
+        let mut cited = generation("Give fluids [1, 2].");
+        cited.sources = vec![SourceReference {
+            passage_id: "doc:1".to_string(),
+            document_id: "doc".to_string(),
+            title: "Guideline".to_string(),
+            section: None,
+            page: None,
+            score: 0.9,
+        }];
+
+        let reply = process_reply(&cited, &no_disclaimer());
+        assert_eq!(reply.content, "Give fluids [1].");
+        assert_eq!(reply.citations.len(), 1);
+        assert_eq!(reply.warnings, vec![ReplyWarning::UnsupportedCitation]);
+    }
+
+    #[test]
+    fn warns_when_the_tool_limit_was_reached() {
+        let mut limited = generation("Rest and fluids.");
+        limited.tool_limit_reached = true;
+
+        let reply = process_reply(&limited, &no_disclaimer());
+        assert_eq!(reply.warnings, vec![ReplyWarning::ToolLimitReached]);
+    }
+}
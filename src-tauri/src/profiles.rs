@@ -0,0 +1,143 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// Profile used by conversations that haven't picked one.
+pub const DEFAULT_PROFILE_ID: &str = "general";
+
+// Version of the import/export file format
+const PROFILE_EXPORT_VERSION: u32 = 1;
+
+/// A named system prompt tailored to a specialty or audience. Conversations
+/// pick one; the built-in set can be edited, extended and shared as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptProfile {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub system_prompt: String,
+    /// Shipped with the app. Edits are stored as overrides; deleting a
+    /// built-in profile restores the original.
+    #[serde(default)]
+    pub builtin: bool,
+}
+
+/// The JSON file written by export and read by import.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ProfileExport {
+    version: u32,
+    profiles: Vec<PromptProfile>,
+}
+
+impl PromptProfile {
+    fn builtin(id: &str, name: &str, description: &str, system_prompt: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            description: description.to_string(),
+            system_prompt: system_prompt.to_string(),
+            builtin: true,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.id.is_empty()
+            || !self
+                .id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow!(
+                "Profile id must be non-empty and use only letters, digits, '-' and '_'"
+            ));
+        }
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("Profile name must not be empty"));
+        }
+        if self.system_prompt.trim().is_empty() {
+            return Err(anyhow!("System prompt must not be empty"));
+        }
+        Ok(())
+    }
+}
+
+pub fn builtin_profiles() -> Vec<PromptProfile> {
+    vec![
+        PromptProfile::builtin(
+            DEFAULT_PROFILE_ID,
+            "General medicine",
+            "Broad clinical support for resident doctors",
+            "You are an AI medical assistant designed to help healthcare professionals, particularly resident doctors in remote locations. You provide information about medical conditions, symptoms, differential diagnoses, and treatment options. Always remind users that your responses are for educational purposes and should not replace clinical judgment or proper medical evaluation.",
+        ),
+        PromptProfile::builtin(
+            "emergency_triage",
+            "Emergency triage",
+            "Rapid assessment and prioritisation in the emergency setting",
+            "You are an AI assistant supporting clinicians in emergency triage. Prioritise life-threatening conditions: address airway, breathing, circulation, disability and exposure first, name the red flags present, suggest a triage category and the immediate actions and investigations. Keep answers short and ordered by urgency. State clearly when immediate escalation or transfer is needed. Your output supports, and never replaces, the treating clinician's judgment.",
+        ),
+        PromptProfile::builtin(
+            "pediatrics",
+            "Pediatrics",
+            "Children and adolescents, with weight-based dosing",
+            "You are an AI assistant supporting clinicians caring for neonates, children and adolescents. Use age-appropriate normal ranges for vital signs and development, ask for the child's weight and age when they matter, and give drug doses per kilogram with the maximum dose. Highlight danger signs such as dehydration, sepsis and respiratory distress. Your output supports, and never replaces, the treating clinician's judgment.",
+        ),
+        PromptProfile::builtin(
+            "obstetrics",
+            "Obstetrics",
+            "Pregnancy, labour and the postpartum period",
+            "You are an AI assistant supporting clinicians in obstetric and postpartum care. Always consider gestational age, and flag pre-eclampsia, haemorrhage, sepsis and fetal distress. Check medication safety in pregnancy and breastfeeding before recommending a drug. Your output supports, and never replaces, the treating clinician's judgment.",
+        ),
+        PromptProfile::builtin(
+            "pharmacology",
+            "Pharmacology",
+            "Drug selection, dosing, interactions and adjustments",
+            "You are an AI assistant supporting clinicians with medication questions. Give drug names, dose, route, frequency and duration, adjustments for renal and hepatic impairment, major interactions, contraindications and monitoring. Say so when information depends on local formularies or is uncertain. Your output supports, and never replaces, the treating clinician's judgment.",
+        ),
+        PromptProfile::builtin(
+            "patient_education",
+            "Patient education",
+            "Plain-language explanations to share with patients",
+            "You help clinicians explain health information to patients and families. Write in plain, friendly language at about a 12-year-old reading level, avoid jargon or explain it, use short sentences and lists, and end with clear advice on when to seek care urgently. Remind the reader that the information does not replace advice from their own health worker.",
+        ),
+    ]
+}
+
+pub fn export_profiles(profiles: &[PromptProfile]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&ProfileExport {
+        version: PROFILE_EXPORT_VERSION,
+        profiles: profiles.to_vec(),
+    })?)
+}
+
+/// Reads profiles from an export file, or from a bare JSON array of
+/// profiles. Every profile is validated before any is returned.
+pub fn import_profiles(json: &str) -> Result<Vec<PromptProfile>> {
+    let value: serde_json::Value =
+        serde_json::from_str(json).map_err(|e| anyhow!("Invalid JSON: {}", e))?;
+
+    let profiles: Vec<PromptProfile> = if value.is_array() {
+        serde_json::from_value(value)?
+    } else {
+        let export: ProfileExport = serde_json::from_value(value)?;
+        if export.version > PROFILE_EXPORT_VERSION {
+            return Err(anyhow!(
+                "Profile file version {} is newer than this app supports",
+                export.version
+            ));
+        }
+        export.profiles
+    };
+
+    let builtin_ids: Vec<String> = builtin_profiles().into_iter().map(|p| p.id).collect();
+    profiles
+        .into_iter()
+        .map(|mut profile| {
+            profile
+                .validate()
+                .map_err(|e| anyhow!("Profile '{}': {}", profile.id, e))?;
+            // Whether a profile is built in is up to this app, not the file
+            profile.builtin = builtin_ids.contains(&profile.id);
+            Ok(profile)
+        })
+        .collect()
+}
@@ -0,0 +1,369 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// A single turn handed to a chat template.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptMessage {
+    pub role: String, // "system", "user" or "assistant"
+    pub content: String,
+}
+
+impl PromptMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+}
+
+/// The prompt formats we know how to render. Models whose format cannot be
+/// determined fall back to `Plain`, the original "Human:/Assistant:" transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatTemplate {
+    Llama3,
+    ChatMl,
+    Plain,
+}
+
+impl ChatTemplate {
+    /// Looks up a template by the name used in the model catalog.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "llama3" | "llama-3" => Some(ChatTemplate::Llama3),
+            "chatml" => Some(ChatTemplate::ChatMl),
+            "plain" => Some(ChatTemplate::Plain),
+            _ => None,
+        }
+    }
+
+    /// Recognises the template family from the Jinja source stored in a
+    /// GGUF's `tokenizer.chat_template`.
+    pub fn detect(jinja_template: &str) -> Option<Self> {
+        if jinja_template.contains("<|start_header_id|>") {
+            Some(ChatTemplate::Llama3)
+        } else if jinja_template.contains("<|im_start|>") {
+            Some(ChatTemplate::ChatMl)
+        } else {
+            None
+        }
+    }
+
+    /// Renders the conversation and opens an assistant turn for the model to
+    /// complete.
+    pub fn render(&self, messages: &[PromptMessage]) -> String {
+        let mut prompt = String::new();
+
+        match self {
+            // No <|begin_of_text|>: llama-server adds the BOS token itself
+            ChatTemplate::Llama3 => {
+                for message in messages {
+                    prompt.push_str(&format!(
+                        "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
+                        message.role,
+                        message.content.trim()
+                    ));
+                }
+                prompt.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
+            }
+            ChatTemplate::ChatMl => {
+                for message in messages {
+                    prompt.push_str(&format!(
+                        "<|im_start|>{}\n{}<|im_end|>\n",
+                        message.role,
+                        message.content.trim()
+                    ));
+                }
+                prompt.push_str("<|im_start|>assistant\n");
+            }
+            ChatTemplate::Plain => {
+                for message in messages {
+                    match message.role.as_str() {
+                        "system" => prompt.push_str(&format!("{}\n\n", message.content)),
+                        "user" => prompt.push_str(&format!("Human: {}\n", message.content)),
+                        "assistant" => {
+                            prompt.push_str(&format!("Assistant: {}\n", message.content))
+                        }
+                        _ => {}
+                    }
+                }
+                prompt.push_str("Assistant: ");
+            }
+        }
+
+        prompt
+    }
+
+    /// Strings that end the assistant's turn for this template.
+    pub fn stop_sequences(&self) -> Vec<&'static str> {
+        match self {
+            ChatTemplate::Llama3 => vec!["<|eot_id|>", "<|start_header_id|>"],
+            ChatTemplate::ChatMl => vec!["<|im_end|>", "<|im_start|>"],
+            ChatTemplate::Plain => vec!["Human:", "User:", "\n\n"],
+        }
+    }
+}
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+const GGUF_TYPE_STRING: u32 = 8;
+const GGUF_TYPE_ARRAY: u32 = 9;
+
+// Keys and chat templates are a few KB at most; a longer length means the
+// metadata is corrupt, and reading it could pull the whole model into memory
+const MAX_GGUF_STRING_LEN: u64 = 1024 * 1024;
+
+/// Reads `tokenizer.chat_template` from a GGUF file's metadata, if present.
+/// Only the header is parsed; tensor data is never touched.
+pub fn read_gguf_chat_template(model_path: &Path) -> Result<Option<String>> {
+    let mut reader = BufReader::new(File::open(model_path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != GGUF_MAGIC {
+        return Err(anyhow!("{:?} is not a GGUF file", model_path));
+    }
+
+    let version = read_u32(&mut reader)?;
+    if version < 2 {
+        return Err(anyhow!("Unsupported GGUF version {}", version));
+    }
+
+    let _tensor_count = read_u64(&mut reader)?;
+    let kv_count = read_u64(&mut reader)?;
+
+    for _ in 0..kv_count {
+        let key = read_string(&mut reader)?;
+        let value_type = read_u32(&mut reader)?;
+
+        if key == "tokenizer.chat_template" && value_type == GGUF_TYPE_STRING {
+            return Ok(Some(read_string(&mut reader)?));
+        }
+
+        skip_value(&mut reader, value_type)?;
+    }
+
+    Ok(None)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+    let len = read_u64(reader)?;
+    if len > MAX_GGUF_STRING_LEN {
+        return Err(anyhow!("Malformed GGUF metadata: string of {} bytes", len));
+    }
+    let mut buf = Vec::new();
+    reader.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(anyhow!("Unexpected end of GGUF metadata"));
+    }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn skip_value(reader: &mut BufReader<File>, value_type: u32) -> Result<()> {
+    match value_type {
+        GGUF_TYPE_STRING => {
+            let len = read_u64(reader)?;
+            reader.seek_relative(seek_offset(Some(len))?)?;
+        }
+        GGUF_TYPE_ARRAY => {
+            let element_type = read_u32(reader)?;
+            let count = read_u64(reader)?;
+            match scalar_size(element_type) {
+                Some(size) => {
+                    reader.seek_relative(seek_offset(size.checked_mul(count))?)?;
+                }
+                None => {
+                    for _ in 0..count {
+                        skip_value(reader, element_type)?;
+                    }
+                }
+            }
+        }
+        _ => {
+            let size = scalar_size(value_type)
+                .ok_or_else(|| anyhow!("Unknown GGUF metadata type {}", value_type))?;
+            reader.seek_relative(size as i64)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A byte count to skip as a seek offset. `None` (an overflowed size) or
+/// anything too large to seek by means the metadata is malformed.
+fn seek_offset(bytes: Option<u64>) -> Result<i64> {
+    bytes
+        .and_then(|bytes| i64::try_from(bytes).ok())
+        .ok_or_else(|| anyhow!("Malformed GGUF metadata: value too large"))
+}
+
+fn scalar_size(value_type: u32) -> Option<u64> {
+    match value_type {
+        0 | 1 | 7 => Some(1), // u8, i8, bool
+        2 | 3 => Some(2),     // u16, i16
+        4..=6 => Some(4),     // u32, i32, f32
+        10..=12 => Some(8),   // u64, i64, f64
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn messages() -> Vec<PromptMessage> {
+        vec![
+            PromptMessage::new("system", "Be brief."),
+            PromptMessage::new("user", " Fever? \n"),
+            PromptMessage::new("assistant", "How long?"),
+        ]
+    }
+
+    fn gguf_string(text: &str) -> Vec<u8> {
+        let mut bytes = (text.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(text.as_bytes());
+        bytes
+    }
+
+    /// A GGUF header with a u32 entry, a string array and then the given
+    /// entries, each a key with its type and raw value.
+    fn gguf(entries: &[(&str, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = GGUF_MAGIC.to_vec();
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&(entries.len() as u64 + 2).to_le_bytes());
+
+        bytes.extend(gguf_string("general.alignment"));
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.extend_from_slice(&32u32.to_le_bytes());
+
+        bytes.extend(gguf_string("tokenizer.ggml.tokens"));
+        bytes.extend_from_slice(&GGUF_TYPE_ARRAY.to_le_bytes());
+        bytes.extend_from_slice(&GGUF_TYPE_STRING.to_le_bytes());
+        bytes.extend_from_slice(&2u64.to_le_bytes());
+        bytes.extend(gguf_string("<s>"));
+        bytes.extend(gguf_string("</s>"));
+
+        for (key, value_type, value) in entries {
+            bytes.extend(gguf_string(key));
+            bytes.extend_from_slice(&value_type.to_le_bytes());
+            bytes.extend_from_slice(value);
+        }
+        bytes
+    }
+
+    fn read(name: &str, bytes: &[u8]) -> Result<Option<String>> {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("{}-{}.gguf", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let result = read_gguf_chat_template(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn renders_llama3_without_bos() {
+        assert_eq!(
+            ChatTemplate::Llama3.render(&messages()),
+            "<|start_header_id|>system<|end_header_id|>\n\nBe brief.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nFever?<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\nHow long?<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+    }
+
+    #[test]
+    fn renders_chatml_and_plain() {
+        assert_eq!(
+            ChatTemplate::ChatMl.render(&messages()),
+            "<|im_start|>system\nBe brief.<|im_end|>\n\
+             <|im_start|>user\nFever?<|im_end|>\n\
+             <|im_start|>assistant\nHow long?<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+        assert_eq!(
+            ChatTemplate::Plain.render(&messages()),
+            "Be brief.\n\nHuman:  Fever? \n\nAssistant: How long?\nAssistant: "
+        );
+    }
+
+    #[test]
+    fn detects_templates_from_jinja() {
+        assert_eq!(
+            ChatTemplate::detect("{{ '<|start_header_id|>' + role }}"),
+            Some(ChatTemplate::Llama3)
+        );
+        assert_eq!(
+            ChatTemplate::detect("{{ '<|im_start|>' + role }}"),
+            Some(ChatTemplate::ChatMl)
+        );
+        assert_eq!(ChatTemplate::detect("[INST]"), None);
+        assert_eq!(
+            ChatTemplate::from_name("Llama-3"),
+            Some(ChatTemplate::Llama3)
+        );
+    }
+
+    #[test]
+    fn reads_the_chat_template_after_other_entries() {
+        let bytes = gguf(&[(
+            "tokenizer.chat_template",
+            GGUF_TYPE_STRING,
+            gguf_string("<|im_start|>"),
+        )]);
+
+        assert_eq!(
+            read("template", &bytes).unwrap().as_deref(),
+            Some("<|im_start|>")
+        );
+        assert_eq!(read("no-template", &gguf(&[])).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_truncated_metadata() {
+        let bytes = gguf(&[(
+            "tokenizer.chat_template",
+            GGUF_TYPE_STRING,
+            gguf_string("<|im_start|>"),
+        )]);
+
+        assert!(read("truncated", &bytes[..bytes.len() - 4]).is_err());
+        assert!(read("header-only", &bytes[..12]).is_err());
+        assert!(read("not-gguf", b"GGML0000").is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_lengths() {
+        let oversized = (MAX_GGUF_STRING_LEN + 1).to_le_bytes().to_vec();
+        let template = gguf(&[("tokenizer.chat_template", GGUF_TYPE_STRING, oversized)]);
+        assert!(read("long-template", &template).is_err());
+
+        // A key claiming to be longer than the rest of the file
+        let mut key = gguf(&[]);
+        key.truncate(24);
+        key.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(read("long-key", &key).is_err());
+
+        // An array of u64s whose total size overflows
+        let mut array = 10u32.to_le_bytes().to_vec();
+        array.extend_from_slice(&u64::MAX.to_le_bytes());
+        let bytes = gguf(&[("huge", GGUF_TYPE_ARRAY, array)]);
+        assert!(read("long-array", &bytes).is_err());
+    }
+}
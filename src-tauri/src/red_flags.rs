@@ -0,0 +1,487 @@
+use crate::text::tokenize;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// File in the app data directory holding the rules. Written with the
+/// built-in rules on first start so it can be edited.
+pub const RULES_FILE_NAME: &str = "red_flag_rules.json";
+
+const RULES_FILE_VERSION: u32 = 1;
+
+// A term doesn't count when one of these governs it, as in "denies chest
+// pain", "no neck stiffness" or "negative for any rash". A cue further away
+// ("no relief from chest pain") or hedged ("not sure if seizure") doesn't.
+const NEGATION_CUES: &[&[&str]] = &[
+    &["no"],
+    &["not"],
+    &["denies"],
+    &["denied"],
+    &["without"],
+    &["negative", "for"],
+];
+// Words that may sit between a cue and the term it negates
+const NEGATION_FILLERS: &[&str] = &["a", "an", "the", "any"];
+
+// Negation doesn't reach past the end of a clause, so "no fever, lethargic"
+// and "no fever but lethargic" still count "lethargic"
+const CLAUSE_BREAKS: &[char] = &[',', '.', ';'];
+const CLAUSE_BREAK_WORDS: &[&str] = &["but"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertSeverity {
+    /// Needs emergency care now
+    Critical,
+    /// Needs same-day assessment
+    Urgent,
+}
+
+/// Where a red flag was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertSource {
+    UserMessage,
+    Reply,
+}
+
+/// A pattern of findings that needs urgent attention. Fires when any of the
+/// `any_of` terms appears, or when every `all_of` group has a term that
+/// appears. Terms match whole words, case-insensitively, and are ignored
+/// when negated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedFlagRule {
+    pub id: String,
+    pub name: String,
+    pub severity: AlertSeverity,
+    /// What the clinician should do about it
+    pub advice: String,
+    #[serde(default)]
+    pub any_of: Vec<String>,
+    #[serde(default)]
+    pub all_of: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RulesFile {
+    version: u32,
+    rules: Vec<RedFlagRule>,
+}
+
+/// A rule that fired on a chat exchange.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedFlagAlert {
+    pub rule_id: String,
+    pub name: String,
+    pub severity: AlertSeverity,
+    pub advice: String,
+    /// The terms that made the rule fire
+    pub matched_terms: Vec<String>,
+    pub found_in: Vec<AlertSource>,
+}
+
+impl RedFlagRule {
+    pub fn validate(&self) -> Result<()> {
+        if self.id.trim().is_empty() {
+            return Err(anyhow!("Rule id must not be empty"));
+        }
+        if self.any_of.is_empty() && self.all_of.is_empty() {
+            return Err(anyhow!("Rule '{}' has no terms", self.id));
+        }
+        if self.all_of.iter().any(|group| group.is_empty()) {
+            return Err(anyhow!("Rule '{}' has an empty all_of group", self.id));
+        }
+        let mut terms = self.any_of.iter().chain(self.all_of.iter().flatten());
+        if terms.any(|term| tokenize(term).is_empty()) {
+            return Err(anyhow!("Rule '{}' has a term without words", self.id));
+        }
+        Ok(())
+    }
+
+    /// The terms that make the rule fire on `clauses`, or `None`.
+    fn matches(&self, clauses: &[Vec<String>]) -> Option<Vec<String>> {
+        if let Some(term) = self.any_of.iter().find(|term| contains_term(clauses, term)) {
+            return Some(vec![term.clone()]);
+        }
+
+        if self.all_of.is_empty() {
+            return None;
+        }
+        self.all_of
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .find(|term| contains_term(clauses, term))
+                    .cloned()
+            })
+            .collect()
+    }
+}
+
+/// Checks both sides of a chat exchange against the red flag rules, so
+/// emergencies are flagged even when the model's reply misses them.
+pub struct RedFlagDetector {
+    rules: Vec<RedFlagRule>,
+}
+
+impl RedFlagDetector {
+    pub fn new(rules: Vec<RedFlagRule>) -> Result<Self> {
+        let mut ids = HashSet::new();
+        for rule in &rules {
+            rule.validate()?;
+            if !ids.insert(rule.id.as_str()) {
+                return Err(anyhow!("Rule id used twice: {}", rule.id));
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn builtin() -> Self {
+        Self {
+            rules: builtin_rules(),
+        }
+    }
+
+    /// Loads the rules file, creating it with the built-in rules if it
+    /// doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            let file = RulesFile {
+                version: RULES_FILE_VERSION,
+                rules: builtin_rules(),
+            };
+            std::fs::write(path, serde_json::to_string_pretty(&file)?)?;
+            return Ok(Self { rules: file.rules });
+        }
+
+        let contents = std::fs::read_to_string(path)?;
+        let file: RulesFile = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid red flag rules file: {}", e))?;
+        if file.version > RULES_FILE_VERSION {
+            return Err(anyhow!(
+                "Red flag rules file version {} is newer than this app supports",
+                file.version
+            ));
+        }
+        Self::new(file.rules)
+    }
+
+    pub fn rules(&self) -> &[RedFlagRule] {
+        &self.rules
+    }
+
+    /// Adds alerts for the rules that fire on `text` to `alerts`. A rule
+    /// that already fired elsewhere gets `source` added to its alert.
+    pub fn scan(&self, text: &str, source: AlertSource, alerts: &mut Vec<RedFlagAlert>) {
+        let clauses = clauses(text);
+
+        for rule in &self.rules {
+            let Some(matched_terms) = rule.matches(&clauses) else {
+                continue;
+            };
+
+            match alerts.iter_mut().find(|alert| alert.rule_id == rule.id) {
+                Some(alert) => {
+                    if !alert.found_in.contains(&source) {
+                        alert.found_in.push(source);
+                    }
+                    for term in matched_terms {
+                        if !alert.matched_terms.contains(&term) {
+                            alert.matched_terms.push(term);
+                        }
+                    }
+                }
+                None => alerts.push(RedFlagAlert {
+                    rule_id: rule.id.clone(),
+                    name: rule.name.clone(),
+                    severity: rule.severity,
+                    advice: rule.advice.clone(),
+                    matched_terms,
+                    found_in: vec![source],
+                }),
+            }
+        }
+
+        alerts.sort_by_key(|alert| alert.severity);
+    }
+}
+
+/// The words of each clause in `text`, split at punctuation that ends a
+/// clause and at "but".
+fn clauses(text: &str) -> Vec<Vec<String>> {
+    let mut clauses = Vec::new();
+    for part in text.split(CLAUSE_BREAKS) {
+        let mut clause = Vec::new();
+        for word in tokenize(part) {
+            if CLAUSE_BREAK_WORDS.contains(&word.as_str()) {
+                clauses.push(std::mem::take(&mut clause));
+            } else {
+                clause.push(word);
+            }
+        }
+        clauses.push(clause);
+    }
+    clauses.retain(|clause| !clause.is_empty());
+    clauses
+}
+
+/// Whether the words right before a term negate it.
+fn is_negated(preceding: &[String]) -> bool {
+    let end = preceding
+        .iter()
+        .rposition(|word| !NEGATION_FILLERS.contains(&word.as_str()))
+        .map_or(0, |last| last + 1);
+    let preceding = &preceding[..end];
+
+    NEGATION_CUES.iter().any(|cue| {
+        preceding.len() >= cue.len()
+            && preceding[preceding.len() - cue.len()..]
+                .iter()
+                .zip(cue.iter())
+                .all(|(word, cue_word)| word == cue_word)
+    })
+}
+
+/// Whether `term` appears in one of `clauses` at least once without being
+/// negated.
+fn contains_term(clauses: &[Vec<String>], term: &str) -> bool {
+    let term_words = tokenize(term);
+    if term_words.is_empty() {
+        return false;
+    }
+
+    clauses.iter().any(|words| {
+        words
+            .windows(term_words.len())
+            .enumerate()
+            .any(|(start, window)| window == term_words.as_slice() && !is_negated(&words[..start]))
+    })
+}
+
+fn rule(
+    id: &str,
+    name: &str,
+    severity: AlertSeverity,
+    advice: &str,
+    any_of: &[&str],
+    all_of: &[&[&str]],
+) -> RedFlagRule {
+    RedFlagRule {
+        id: id.to_string(),
+        name: name.to_string(),
+        severity,
+        advice: advice.to_string(),
+        any_of: any_of.iter().map(|term| term.to_string()).collect(),
+        all_of: all_of
+            .iter()
+            .map(|group| group.iter().map(|term| term.to_string()).collect())
+            .collect(),
+    }
+}
+
+pub fn builtin_rules() -> Vec<RedFlagRule> {
+    use AlertSeverity::{Critical, Urgent};
+
+    vec![
+        rule(
+            "acute_coronary_syndrome",
+            "Possible acute coronary syndrome",
+            Critical,
+            "Get a 12-lead ECG within 10 minutes, give aspirin unless contraindicated and arrange urgent transfer for cardiac care.",
+            &[],
+            &[
+                &["chest pain", "chest pressure", "chest tightness", "chest discomfort", "crushing chest"],
+                &["diaphoresis", "diaphoretic", "sweating", "sweaty", "clammy", "radiating to the arm", "radiating to the jaw", "left arm pain", "jaw pain"],
+            ],
+        ),
+        rule(
+            "stroke",
+            "Possible stroke",
+            Critical,
+            "Note the time of onset, check glucose and arrange immediate transfer to a stroke-capable facility; thrombolysis is time-critical.",
+            &["facial droop", "face drooping", "slurred speech", "hemiparesis", "hemiplegia", "one sided weakness", "unilateral weakness", "aphasia", "sudden loss of vision", "sudden vision loss"],
+            &[],
+        ),
+        rule(
+            "anaphylaxis",
+            "Possible anaphylaxis",
+            Critical,
+            "Give IM adrenaline 0.5 mg (0.01 mg/kg in children) into the anterolateral thigh now, lie the patient flat and call for help.",
+            &["anaphylaxis", "anaphylactic"],
+            &[
+                &["allergic reaction", "allergy", "sting", "hives", "urticaria", "after eating", "after the injection"],
+                &["throat swelling", "throat tightness", "tongue swelling", "lip swelling", "swollen lips", "swollen tongue", "stridor", "wheezing", "difficulty breathing", "low blood pressure", "hypotension", "collapse", "collapsed"],
+            ],
+        ),
+        rule(
+            "suicidal_ideation",
+            "Suicide or self-harm risk",
+            Critical,
+            "Ask directly about plans and means, do not leave the patient alone, remove means where possible and arrange urgent mental health assessment.",
+            &["suicidal", "suicide", "kill myself", "kill himself", "kill herself", "end my life", "want to die", "self harm", "no reason to live"],
+            &[],
+        ),
+        rule(
+            "pediatric_dehydration",
+            "Signs of dehydration in a child",
+            Urgent,
+            "Assess for severe dehydration and shock; start oral or IV rehydration per IMCI plan B or C and reassess frequently.",
+            &[],
+            &[
+                &["child", "baby", "infant", "toddler", "newborn", "neonate", "month old", "year old boy", "year old girl"],
+                &["sunken eyes", "sunken fontanelle", "no tears", "no wet diapers", "no wet nappies", "not passing urine", "lethargic", "floppy", "unable to drink", "drinking poorly", "skin pinch"],
+            ],
+        ),
+        rule(
+            "meningitis",
+            "Possible meningitis",
+            Critical,
+            "Give empirical IV antibiotics without delay (do not wait for lumbar puncture) and arrange urgent transfer.",
+            &["non blanching rash", "purpuric rash"],
+            &[
+                &["fever", "febrile", "high temperature"],
+                &["neck stiffness", "stiff neck", "photophobia", "bulging fontanelle", "reduced consciousness"],
+            ],
+        ),
+        rule(
+            "obstetric_emergency",
+            "Possible obstetric emergency",
+            Critical,
+            "Check blood pressure and bleeding, consider pre-eclampsia, eclampsia and haemorrhage, and arrange urgent obstetric review.",
+            &["eclampsia", "postpartum haemorrhage", "postpartum hemorrhage"],
+            &[
+                &["pregnant", "pregnancy", "weeks gestation", "postpartum"],
+                &["heavy bleeding", "vaginal bleeding", "seizure", "convulsion", "severe headache", "blurred vision", "reduced fetal movements"],
+            ],
+        ),
+        rule(
+            "respiratory_distress",
+            "Severe respiratory distress",
+            Critical,
+            "Assess the airway, give oxygen, check saturations and prepare for escalation of airway and breathing support.",
+            &["cannot breathe", "can t breathe", "cyanosis", "cyanotic", "blue lips", "unable to speak in full sentences", "silent chest"],
+            &[],
+        ),
+        rule(
+            "gi_bleed",
+            "Possible gastrointestinal bleeding",
+            Urgent,
+            "Check haemodynamics, gain IV access, send haemoglobin and crossmatch, and arrange urgent assessment.",
+            &["vomiting blood", "haematemesis", "hematemesis", "melaena", "melena", "black tarry stools"],
+            &[],
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector() -> RedFlagDetector {
+        RedFlagDetector::new(vec![
+            rule(
+                "any",
+                "Any term",
+                AlertSeverity::Urgent,
+                "Assess.",
+                &["chest pain", "seizure", "breathing", "lethargic", "rash"],
+                &[],
+            ),
+            rule(
+                "all",
+                "Every group",
+                AlertSeverity::Critical,
+                "Escalate.",
+                &[],
+                &[&["fever"], &["neck stiffness", "photophobia"]],
+            ),
+        ])
+        .unwrap()
+    }
+
+    fn fired(text: &str) -> Vec<String> {
+        let mut alerts = Vec::new();
+        detector().scan(text, AlertSource::UserMessage, &mut alerts);
+        alerts.into_iter().map(|alert| alert.rule_id).collect()
+    }
+
+    #[test]
+    fn matches_whole_words_case_insensitively() {
+        assert_eq!(fired("Sudden CHEST PAIN at rest"), vec!["any"]);
+        assert!(fired("seizures").is_empty());
+        assert!(fired("crash").is_empty());
+    }
+
+    #[test]
+    fn ignores_terms_a_cue_governs() {
+        assert!(fired("no chest pain").is_empty());
+        assert!(fired("denies any chest pain").is_empty());
+        assert!(fired("patient denied a seizure").is_empty());
+        assert!(fired("negative for the rash").is_empty());
+        assert!(fired("without a rash").is_empty());
+    }
+
+    #[test]
+    fn counts_terms_a_cue_does_not_govern() {
+        assert_eq!(fired("no relief from chest pain"), vec!["any"]);
+        assert_eq!(fired("no improvement in breathing"), vec!["any"]);
+        assert_eq!(fired("not sure if seizure"), vec!["any"]);
+        assert_eq!(fired("not sure it was a seizure"), vec!["any"]);
+    }
+
+    #[test]
+    fn counts_a_term_when_any_mention_is_not_negated() {
+        assert_eq!(
+            fired("no chest pain yesterday, chest pain today"),
+            vec!["any"]
+        );
+    }
+
+    #[test]
+    fn negation_stops_at_a_clause_break() {
+        assert!(fired("no fever").is_empty());
+        assert_eq!(fired("no rash, lethargic"), vec!["any"]);
+        assert_eq!(fired("no rash. lethargic"), vec!["any"]);
+        assert_eq!(fired("no rash; lethargic"), vec!["any"]);
+        assert_eq!(fired("no rash but lethargic"), vec!["any"]);
+    }
+
+    #[test]
+    fn all_of_needs_a_term_from_every_group() {
+        assert!(fired("fever").is_empty());
+        assert!(fired("fever with no photophobia").is_empty());
+        assert!(fired("no fever but neck stiffness").is_empty());
+
+        let mut alerts = Vec::new();
+        detector().scan(
+            "fever and photophobia",
+            AlertSource::UserMessage,
+            &mut alerts,
+        );
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule_id, "all");
+        assert_eq!(alerts[0].matched_terms, vec!["fever", "photophobia"]);
+    }
+
+    #[test]
+    fn merges_sources_into_one_alert() {
+        let detector = detector();
+        let mut alerts = Vec::new();
+        detector.scan("chest pain", AlertSource::UserMessage, &mut alerts);
+        detector.scan("seizure", AlertSource::Reply, &mut alerts);
+
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].matched_terms, vec!["chest pain", "seizure"]);
+        assert_eq!(
+            alerts[0].found_in,
+            vec![AlertSource::UserMessage, AlertSource::Reply]
+        );
+    }
+
+    #[test]
+    fn builtin_rules_are_valid() {
+        assert!(RedFlagDetector::new(builtin_rules()).is_ok());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::hardware::HardwareInfo;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// Sampling parameters sent to llama-server with every completion request.
+/// Stored as an application-wide default and optionally overridden per
+/// conversation or per request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationSettings {
+    pub temperature: f32,
+    pub top_p: f32,
+    pub top_k: u32,
+    pub repeat_penalty: f32,
+    pub n_predict: u32,
+    /// Extra stop strings, applied in addition to the chat template's own
+    pub stop: Vec<String>,
+}
+
+impl Default for GenerationSettings {
+    fn default() -> Self {
+        Self {
+            temperature: 0.7,
+            top_p: 0.9,
+            top_k: 40,
+            repeat_penalty: 1.1,
+            n_predict: 512,
+            stop: Vec::new(),
+        }
+    }
+}
+
+impl GenerationSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=2.0).contains(&self.temperature) {
+            return Err(anyhow!("Temperature must be between 0.0 and 2.0"));
+        }
+        if !(self.top_p > 0.0 && self.top_p <= 1.0) {
+            return Err(anyhow!("top_p must be greater than 0.0 and at most 1.0"));
+        }
+        if self.repeat_penalty <= 0.0 {
+            return Err(anyhow!("Repeat penalty must be positive"));
+        }
+        if !(1..=8192).contains(&self.n_predict) {
+            return Err(anyhow!("n_predict must be between 1 and 8192 tokens"));
+        }
+        if self.stop.iter().any(|stop| stop.is_empty()) {
+            return Err(anyhow!("Stop strings must not be empty"));
+        }
+        Ok(())
+    }
+}
+
+/// How replies are finished off before they are stored and shown.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplySettings {
+    /// Append `disclaimer` to every reply that doesn't already contain it
+    pub append_disclaimer: bool,
+    pub disclaimer: String,
+}
+
+impl Default for ReplySettings {
+    fn default() -> Self {
+        Self {
+            append_disclaimer: true,
+            disclaimer: "This information is for educational purposes and does not replace clinical judgment or proper medical evaluation.".to_string(),
+        }
+    }
+}
+
+impl ReplySettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.append_disclaimer && self.disclaimer.trim().is_empty() {
+            return Err(anyhow!("Disclaimer must not be empty when it is appended"));
+        }
+        Ok(())
+    }
+}
+
+/// Rough KV cache cost per context token for the 3B–8B Llama models we ship
+/// (f16 cache, grouped-query attention). Used only to sanity-check memory.
+const KV_CACHE_BYTES_PER_TOKEN: u64 = 128 * 1024;
+
+/// Which inference backend new engines use.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackendConfig {
+    /// Start and supervise our own llama-server with the selected model
+    #[default]
+    Managed,
+    /// Connect to an already-running server with an OpenAI-compatible API,
+    /// e.g. llama-server or Ollama
+    OpenAiCompatible {
+        base_url: String,
+        /// Model name sent with each request; the selected model's filename
+        /// when unset
+        #[serde(default)]
+        model: Option<String>,
+        #[serde(default)]
+        api_key: Option<String>,
+    },
+    /// Canned replies without any model, for development and testing
+    Mock,
+}
+
+/// User overrides for how llama-server is launched. Anything left unset is
+/// chosen from the detected hardware when the engine starts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    pub backend: BackendConfig,
+    pub context_size: Option<u32>,
+    pub threads: Option<u32>,
+    pub batch_size: Option<u32>,
+    pub use_mlock: bool,
+    pub disable_mmap: bool,
+    /// Loopback ports llama-server may listen on. When unset the OS picks a
+    /// free port.
+    pub port_range_start: Option<u16>,
+    pub port_range_end: Option<u16>,
+    /// Conversations llama-server keeps a prompt cache for at once. The
+    /// context size is shared between them.
+    pub parallel_slots: Option<u32>,
+    /// Save a conversation's prompt cache to disk when its slot is reused or
+    /// the engine is unloaded, and restore it when the conversation resumes
+    pub persist_prompt_cache: bool,
+}
+
+/// The concrete options llama-server is started with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineLaunchOptions {
+    pub context_size: u32,
+    pub threads: u32,
+    pub batch_size: u32,
+    pub use_mlock: bool,
+    pub use_mmap: bool,
+    pub port_range: Option<(u16, u16)>,
+    pub parallel_slots: u32,
+}
+
+impl EngineConfig {
+    /// Checks the overrides on their own, without knowing the model or the
+    /// machine.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(context_size) = self.context_size {
+            if !(512..=131_072).contains(&context_size) {
+                return Err(anyhow!(
+                    "Context size must be between 512 and 131072 tokens"
+                ));
+            }
+        }
+        if self.threads == Some(0) {
+            return Err(anyhow!("Thread count must be at least 1"));
+        }
+        if let Some(batch_size) = self.batch_size {
+            if !(32..=8192).contains(&batch_size) {
+                return Err(anyhow!("Batch size must be between 32 and 8192"));
+            }
+        }
+        match (self.port_range_start, self.port_range_end) {
+            (None, None) => {}
+            (Some(start), Some(end)) => {
+                if start < 1024 || start > end {
+                    return Err(anyhow!(
+                        "Port range must start at 1024 or above and not end before it starts"
+                    ));
+                }
+            }
+            _ => return Err(anyhow!("Port range needs both a start and an end")),
+        }
+        if let Some(parallel_slots) = self.parallel_slots {
+            if !(1..=8).contains(&parallel_slots) {
+                return Err(anyhow!("Parallel slots must be between 1 and 8"));
+            }
+        }
+        if let BackendConfig::OpenAiCompatible { base_url, .. } = &self.backend {
+            if !(base_url.starts_with("http://") || base_url.starts_with("https://")) {
+                return Err(anyhow!("Server URL must start with http:// or https://"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in everything the user did not override from the detected
+    /// hardware and checks that the result fits the machine and model.
+    pub fn resolve(&self, hardware: &HardwareInfo, model_size: u64) -> Result<EngineLaunchOptions> {
+        self.validate()?;
+
+        let threads = self.threads.unwrap_or(hardware.physical_cores.max(1));
+        if threads > hardware.logical_cores {
+            return Err(anyhow!(
+                "{} threads requested but this machine only has {} logical cores",
+                threads,
+                hardware.logical_cores
+            ));
+        }
+
+        let context_size = match self.context_size {
+            Some(context_size) => context_size,
+            None => auto_context_size(hardware, model_size),
+        };
+
+        let parallel_slots = self.parallel_slots.unwrap_or(1);
+        if context_size / parallel_slots < 512 {
+            return Err(anyhow!(
+                "A {}-token context split across {} slots leaves less than 512 tokens per conversation",
+                context_size,
+                parallel_slots
+            ));
+        }
+
+        let batch_size = self
+            .batch_size
+            .unwrap_or(if context_size <= 2048 { 256 } else { 512 });
+        if batch_size > context_size {
+            return Err(anyhow!(
+                "Batch size ({}) must not exceed the context size ({})",
+                batch_size,
+                context_size
+            ));
+        }
+
+        if let Some(available_memory) = hardware.available_memory {
+            let required = estimated_memory(model_size, context_size);
+            if required > available_memory {
+                return Err(anyhow!(
+                    "The model needs about {:.1} GB with a {}-token context but only {:.1} GB of memory is available; try a smaller context size or model",
+                    gigabytes(required),
+                    context_size,
+                    gigabytes(available_memory)
+                ));
+            }
+        }
+
+        Ok(EngineLaunchOptions {
+            context_size,
+            threads,
+            batch_size,
+            use_mlock: self.use_mlock,
+            use_mmap: !self.disable_mmap,
+            port_range: self.port_range_start.zip(self.port_range_end),
+            parallel_slots,
+        })
+    }
+}
+
+/// Largest of the usual context sizes that still fits in available memory,
+/// never going above 4096 unless the user asks for it.
+fn auto_context_size(hardware: &HardwareInfo, model_size: u64) -> u32 {
+    let Some(available_memory) = hardware.available_memory else {
+        return 4096;
+    };
+
+    [4096, 2048, 1024]
+        .into_iter()
+        .find(|&context_size| estimated_memory(model_size, context_size) <= available_memory)
+        .unwrap_or(512)
+}
+
+fn estimated_memory(model_size: u64, context_size: u32) -> u64 {
+    model_size + context_size as u64 * KV_CACHE_BYTES_PER_TOKEN
+}
+
+fn gigabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0 * 1024.0)
+}
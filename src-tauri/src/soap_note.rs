@@ -0,0 +1,119 @@
+use crate::ai_engine::AIEngine;
+use crate::database::Database;
+use crate::prompt_template::PromptMessage;
+use crate::settings::GenerationSettings;
+use crate::summarizer;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const SOAP_NOTE_MAX_TOKENS: u32 = 1024;
+
+const SOAP_NOTE_SYSTEM_PROMPT: &str = "You write clinical documentation. Turn the encounter described by the clinician into a SOAP note. Subjective: the history as reported by the patient or carer. Objective: vital signs, examination findings and results. Assessment: the working diagnosis and differentials with brief reasoning. Plan: investigations, treatment, follow-up and safety-netting. Only use information from the encounter; write \"Not documented\" for anything missing. Be concise and use standard clinical abbreviations.";
+
+/// A clinical note in SOAP format, as produced by the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SoapNote {
+    pub subjective: String,
+    pub objective: String,
+    pub assessment: String,
+    pub plan: String,
+}
+
+/// A generated note as stored with its conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SoapNoteRecord {
+    pub id: String,
+    pub conversation_id: String,
+    pub note: SoapNote,
+    /// Last message of the branch the note was written from
+    pub covers_message_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A stored note along with its Markdown rendering, as sent to the frontend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderedSoapNote {
+    #[serde(flatten)]
+    pub record: SoapNoteRecord,
+    pub markdown: String,
+}
+
+impl From<SoapNoteRecord> for RenderedSoapNote {
+    fn from(record: SoapNoteRecord) -> Self {
+        let markdown = record.note.to_markdown();
+        Self { record, markdown }
+    }
+}
+
+impl SoapNote {
+    /// The schema the model's output is constrained to.
+    pub fn json_schema() -> serde_json::Value {
+        let section = serde_json::json!({ "type": "string", "minLength": 1 });
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "subjective": section,
+                "objective": section,
+                "assessment": section,
+                "plan": section
+            },
+            "required": ["subjective", "objective", "assessment", "plan"],
+            "additionalProperties": false
+        })
+    }
+
+    pub fn to_markdown(&self) -> String {
+        [
+            ("Subjective", &self.subjective),
+            ("Objective", &self.objective),
+            ("Assessment", &self.assessment),
+            ("Plan", &self.plan),
+        ]
+        .iter()
+        .map(|(heading, text)| format!("## {}\n\n{}\n", heading, text.trim()))
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+}
+
+/// Writes a SOAP note from the conversation's active branch and stores it.
+pub async fn generate_soap_note(
+    engine: &AIEngine,
+    database: &Database,
+    conversation_id: &str,
+) -> Result<SoapNoteRecord> {
+    let (encounter, last_message_id) = summarizer::encounter_transcript(database, conversation_id)?;
+
+    let prompt = vec![
+        PromptMessage::new("system", SOAP_NOTE_SYSTEM_PROMPT),
+        PromptMessage::new(
+            "user",
+            &format!("Encounter:\n{}Write the SOAP note.", encounter),
+        ),
+    ];
+
+    let settings = GenerationSettings {
+        temperature: 0.2,
+        n_predict: SOAP_NOTE_MAX_TOKENS,
+        ..GenerationSettings::default()
+    };
+
+    let output = engine
+        .complete_json(prompt, &settings, &SoapNote::json_schema())
+        .await?;
+    let note: SoapNote = serde_json::from_str(&output)
+        .map_err(|e| anyhow!("Model returned an invalid SOAP note: {}", e))?;
+
+    let record = SoapNoteRecord {
+        id: Uuid::new_v4().to_string(),
+        conversation_id: conversation_id.to_string(),
+        note,
+        covers_message_id: Some(last_message_id),
+        created_at: Utc::now(),
+    };
+    database.save_soap_note(&record)?;
+
+    Ok(record)
+}
@@ -0,0 +1,283 @@
+use crate::ai_engine::{AIEngine, ChatMessage, ConversationContext};
+use crate::database::{ConversationSummary, Database};
+use crate::postprocess::without_footer;
+use crate::prompt_template::PromptMessage;
+use crate::settings::GenerationSettings;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+
+// Older messages are folded into the summary once this many are outside it,
+// leaving the newest KEEP_RECENT_MESSAGES to be sent verbatim
+const SUMMARY_TRIGGER_MESSAGES: usize = 12;
+const KEEP_RECENT_MESSAGES: usize = 6;
+
+const SUMMARY_MAX_TOKENS: u32 = 400;
+
+const SUMMARY_SYSTEM_PROMPT: &str = "You maintain a running clinical summary of a conversation between a clinician and an AI medical assistant. Merge the existing summary with the new exchanges into one concise summary. Keep patient demographics, presenting complaints, timeline, vital signs, examination findings, test results, diagnoses considered, medications and the current plan. Leave out pleasantries and general advice. Use short bullet points, at most 250 words.";
+
+/// The prompt context for a conversation: its system prompt, its stored
+/// summary if that applies to this branch, and the messages the summary
+/// doesn't cover.
+pub fn conversation_context(
+    system_prompt: &str,
+    mut history: Vec<ChatMessage>,
+    summary: Option<ConversationSummary>,
+) -> ConversationContext {
+    match summary.and_then(|summary| Some((covered_count(&history, &summary)?, summary))) {
+        Some((covered, summary)) => ConversationContext {
+            system_prompt: system_prompt.to_string(),
+            history: history.split_off(covered),
+            summary: Some(summary.content),
+            passages: Vec::new(),
+        },
+        None => ConversationContext {
+            system_prompt: system_prompt.to_string(),
+            history,
+            summary: None,
+            passages: Vec::new(),
+        },
+    }
+}
+
+/// How many of the leading `messages` the summary covers, or `None` when it
+/// was written for a branch these messages aren't on.
+fn covered_count(messages: &[ChatMessage], summary: &ConversationSummary) -> Option<usize> {
+    match &summary.covers_message_id {
+        Some(covered_id) => messages
+            .iter()
+            .position(|message| &message.id == covered_id)
+            .map(|position| position + 1),
+        // Summaries from before branching only know a point in time
+        None => Some(
+            messages
+                .iter()
+                .take_while(|message| message.timestamp <= summary.covers_until)
+                .count(),
+        ),
+    }
+}
+
+fn transcript_line(message: &ChatMessage) -> String {
+    let speaker = match message.role.as_str() {
+        "user" => "Clinician",
+        "tool" => "Tool",
+        _ => "Assistant",
+    };
+    format!("{}: {}", speaker, without_footer(&message.content))
+}
+
+/// The conversation's active branch as plain text for documentation prompts:
+/// the summary of older turns followed by the rest verbatim. Returned with
+/// the id of the last message it includes.
+pub fn encounter_transcript(
+    database: &Database,
+    conversation_id: &str,
+) -> Result<(String, String)> {
+    let history: Vec<ChatMessage> = database
+        .get_active_messages(conversation_id)?
+        .into_iter()
+        .map(|active| active.message)
+        .collect();
+    let Some(last_message_id) = history.last().map(|message| message.id.clone()) else {
+        return Err(anyhow!("Conversation has no messages"));
+    };
+
+    // Older turns may only survive in the summary
+    let summary = database.get_conversation_summary(conversation_id)?;
+    let context = conversation_context("", history, summary);
+
+    let mut transcript = String::new();
+    if let Some(summary) = &context.summary {
+        transcript.push_str(&format!("Summary of earlier discussion:\n{}\n\n", summary));
+    }
+    for message in &context.history {
+        transcript.push_str(&transcript_line(message));
+        transcript.push_str("\n\n");
+    }
+
+    Ok((transcript, last_message_id))
+}
+
+/// Folds older messages into the conversation's summary once enough of them
+/// have built up outside it. Returns whether the summary changed. Meant to
+/// run in the background after a reply has been stored.
+pub async fn update_summary(
+    engine: &AIEngine,
+    database: &Database,
+    conversation_id: &str,
+) -> Result<bool> {
+    let summary = database.get_conversation_summary(conversation_id)?;
+    let messages: Vec<ChatMessage> = database
+        .get_active_messages(conversation_id)?
+        .into_iter()
+        .map(|active| active.message)
+        .collect();
+
+    // A summary of another branch is of no use here; start a new one
+    let (summary, covered) = match summary {
+        Some(summary) => match covered_count(&messages, &summary) {
+            Some(covered) => (Some(summary), covered),
+            None => (None, 0),
+        },
+        None => (None, 0),
+    };
+    let pending = &messages[covered..];
+
+    if pending.len() < SUMMARY_TRIGGER_MESSAGES {
+        return Ok(false);
+    }
+
+    let previous_summary = summary
+        .as_ref()
+        .map(|summary| summary.content.as_str())
+        .unwrap_or("(none yet)");
+
+    // Only as many messages as fit in one request are folded in, oldest
+    // first; the rest wait for the next update
+    let budget = engine
+        .context_size()
+        .saturating_sub(SUMMARY_MAX_TOKENS as usize);
+    let mut used = 0;
+    for message in summary_prompt(previous_summary, &[]) {
+        used += engine.prompt_tokens(&message).await?;
+    }
+
+    let mut transcript = Vec::new();
+    for message in &pending[..pending.len() - KEEP_RECENT_MESSAGES] {
+        let line = PromptMessage::new("user", &transcript_line(message));
+        let tokens = engine.prompt_tokens(&line).await?;
+        if used + tokens > budget {
+            // A single message too long for the window is cut short rather
+            // than holding up the summary for good
+            if transcript.is_empty() {
+                let room = budget.saturating_sub(used);
+                if room == 0 {
+                    return Err(anyhow!("No room left in the context window to summarize"));
+                }
+                transcript.push(line.content.chars().take(room * 2).collect());
+            }
+            break;
+        }
+
+        used += tokens;
+        transcript.push(line.content);
+    }
+
+    let to_summarize = &pending[..transcript.len()];
+    let Some(last_summarized) = to_summarize.last() else {
+        return Ok(false);
+    };
+
+    let prompt = summary_prompt(previous_summary, &transcript);
+
+    let settings = GenerationSettings {
+        temperature: 0.2,
+        n_predict: SUMMARY_MAX_TOKENS,
+        ..GenerationSettings::default()
+    };
+
+    let content = engine.complete(prompt, &settings).await?;
+    if content.is_empty() {
+        return Ok(false);
+    }
+
+    database.save_conversation_summary(&ConversationSummary {
+        conversation_id: conversation_id.to_string(),
+        content,
+        covers_until: last_summarized.timestamp,
+        covers_message_id: Some(last_summarized.id.clone()),
+        message_count: summary.map_or(0, |summary| summary.message_count) + to_summarize.len(),
+        updated_at: Utc::now(),
+    })?;
+
+    Ok(true)
+}
+
+fn summary_prompt(previous_summary: &str, transcript: &[String]) -> Vec<PromptMessage> {
+    vec![
+        PromptMessage::new("system", SUMMARY_SYSTEM_PROMPT),
+        PromptMessage::new(
+            "user",
+            &format!(
+                "Existing summary:\n{}\n\nNew exchanges:\n{}\n\nWrite the updated summary.",
+                previous_summary,
+                transcript.join("\n\n")
+            ),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration};
+
+    fn messages(start: DateTime<Utc>) -> Vec<ChatMessage> {
+        (0..4)
+            .map(|i| ChatMessage {
+                id: format!("m{}", i),
+                role: if i % 2 == 0 { "user" } else { "assistant" }.to_string(),
+                content: format!("message {}", i),
+                timestamp: start + Duration::minutes(i),
+                cancelled: false,
+                parent_id: (i > 0).then(|| format!("m{}", i - 1)),
+                citations: Vec::new(),
+                tool_call: None,
+            })
+            .collect()
+    }
+
+    fn summary(
+        covers_until: DateTime<Utc>,
+        covers_message_id: Option<&str>,
+    ) -> ConversationSummary {
+        ConversationSummary {
+            conversation_id: "c".to_string(),
+            content: "summary".to_string(),
+            covers_until,
+            covers_message_id: covers_message_id.map(String::from),
+            message_count: 0,
+            updated_at: covers_until,
+        }
+    }
+
+    #[test]
+    fn counts_up_to_the_covered_message() {
+        let start = Utc::now();
+        let messages = messages(start);
+
+        assert_eq!(
+            covered_count(&messages, &summary(start, Some("m0"))),
+            Some(1)
+        );
+        assert_eq!(
+            covered_count(&messages, &summary(start, Some("m2"))),
+            Some(3)
+        );
+        assert_eq!(
+            covered_count(&messages, &summary(start, Some("m3"))),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn ignores_a_summary_from_another_branch() {
+        let start = Utc::now();
+        let messages = messages(start);
+
+        // The timestamp would cover everything; the id decides
+        let summary = summary(start + Duration::hours(1), Some("other"));
+        assert_eq!(covered_count(&messages, &summary), None);
+    }
+
+    #[test]
+    fn falls_back_to_the_timestamp_for_older_summaries() {
+        let start = Utc::now();
+        let messages = messages(start);
+
+        let covered = |until| covered_count(&messages, &summary(until, None));
+        assert_eq!(covered(start - Duration::minutes(1)), Some(0));
+        assert_eq!(covered(start + Duration::minutes(1)), Some(2));
+        assert_eq!(covered(start + Duration::hours(1)), Some(4));
+    }
+}
@@ -0,0 +1,10 @@
+/// Lowercase words with punctuation dropped, so "6-month-old" becomes
+/// "6 month old" and "Co-Trimoxazole" "co trimoxazole". Red flag terms and
+/// drug names are matched on these words, in the text and in the rules alike.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -0,0 +1,395 @@
+use crate::calculators::CalculationResult;
+use crate::interactions::InteractionReport;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Markers around a tool call in the model's output. The closing one is a
+/// stop string, so generation ends with the call.
+pub const TOOL_CALL_OPEN: &str = "<tool_call>";
+pub const TOOL_CALL_CLOSE: &str = "</tool_call>";
+
+/// A tool as offered to the model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments
+    pub parameters: serde_json::Value,
+}
+
+/// Something the model can call while answering.
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn spec(&self) -> ToolSpec;
+
+    /// Runs the tool. Errors are passed back to the model so it can correct
+    /// the call.
+    async fn call(&self, arguments: serde_json::Value) -> Result<ToolOutput>;
+}
+
+/// What a tool produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ToolOutput {
+    Calculation(CalculationResult),
+    Interactions(InteractionReport),
+}
+
+impl ToolOutput {
+    /// The output on one line, as the model and the conversation show it.
+    pub fn summary(&self) -> String {
+        match self {
+            ToolOutput::Calculation(calculation) => calculation.summary(),
+            ToolOutput::Interactions(report) => report.summary(),
+        }
+    }
+}
+
+/// A request from the model to run a tool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+}
+
+/// A tool call made while generating a reply and what came of it. Stored as
+/// a message of its own so the steps behind a reply can be reviewed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallRecord {
+    /// Empty when the call couldn't be parsed
+    pub name: String,
+    /// The raw call text when it couldn't be parsed
+    pub arguments: serde_json::Value,
+    #[serde(default)]
+    pub output: Option<ToolOutput>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl ToolCallRecord {
+    /// A call that couldn't be parsed, kept as the model wrote it.
+    pub fn invalid(call_text: &str, error: anyhow::Error) -> Self {
+        ToolCallRecord {
+            name: String::new(),
+            arguments: serde_json::Value::String(call_text.trim().to_string()),
+            output: None,
+            error: Some(error.to_string()),
+        }
+    }
+
+    /// A call that was understood but not run, e.g. because the reply has
+    /// used up its tool rounds.
+    pub fn rejected(call: ToolCall, reason: &str) -> Self {
+        ToolCallRecord {
+            name: call.name,
+            arguments: call.arguments,
+            output: None,
+            error: Some(reason.to_string()),
+        }
+    }
+
+    /// The message that tells the model how the call went.
+    pub fn result_message(&self) -> String {
+        match (&self.output, &self.error) {
+            (Some(output @ ToolOutput::Calculation(_)), _) => format!(
+                "Tool result: {}\nQuote the value exactly and list its inputs.",
+                output.summary()
+            ),
+            (Some(output), _) => format!("Tool result: {}", output.summary()),
+            (None, _) => format!(
+                "{}. Correct the call, or answer without the tool and say the result could not be obtained.",
+                self.describe()
+            ),
+        }
+    }
+
+    /// One line for the conversation: the tool and its result, or what went
+    /// wrong.
+    pub fn describe(&self) -> String {
+        let error = self.error.as_deref().unwrap_or("no result");
+        match &self.output {
+            Some(output) => format!("{}: {}", self.name, output.summary()),
+            None if self.name.is_empty() => format!("Invalid tool call: {}", error),
+            None => format!("{} failed: {}", self.name, error),
+        }
+    }
+}
+
+/// The tools available for a reply.
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    /// Adds `tool`, replacing any tool of the same name.
+    pub fn register(&mut self, tool: Arc<dyn Tool>) {
+        let name = tool.spec().name;
+        self.tools.retain(|existing| existing.spec().name != name);
+        self.tools.push(tool);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    pub fn specs(&self) -> Vec<ToolSpec> {
+        self.tools.iter().map(|tool| tool.spec()).collect()
+    }
+
+    /// Runs the tool `call` names. Failures end up in the record rather
+    /// than as an error, since the model gets to see them.
+    pub async fn call(&self, call: ToolCall) -> ToolCallRecord {
+        let result = match self.tools.iter().find(|tool| tool.spec().name == call.name) {
+            Some(tool) => tool
+                .call(call.arguments.clone())
+                .await
+                .map_err(|e| e.to_string()),
+            None => Err(format!(
+                "Unknown tool '{}'; use one of: {}",
+                call.name,
+                self.specs()
+                    .iter()
+                    .map(|spec| spec.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        };
+
+        let (output, error) = match result {
+            Ok(output) => (Some(output), None),
+            Err(e) => (None, Some(e)),
+        };
+        ToolCallRecord {
+            name: call.name,
+            arguments: call.arguments,
+            output,
+            error,
+        }
+    }
+
+    /// System prompt section offering the tools to the model. Empty when
+    /// there are none.
+    pub fn instructions(&self) -> String {
+        if self.tools.is_empty() {
+            return String::new();
+        }
+
+        let tools: Vec<String> = self
+            .specs()
+            .iter()
+            .map(|spec| {
+                format!(
+                    "- {}({}): {}",
+                    spec.name,
+                    signature(&spec.parameters),
+                    spec.description
+                )
+            })
+            .collect();
+
+        format!(
+            "\n\nYou can call tools. Never work out clinical scores, clearances, body size or doses yourself, and never check drug interactions from memory; call the matching tool. To call one, reply with only {}{{\"name\": \"<tool>\", \"arguments\": {{...}}}}{} and wait for the result. Give every measurement as {{\"value\": <number>, \"unit\": \"<unit>\"}} in one of the listed units. Quote results exactly and list their inputs in your answer. Tools:\n{}",
+            TOOL_CALL_OPEN,
+            TOOL_CALL_CLOSE,
+            tools.join("\n")
+        )
+    }
+}
+
+/// The tool call in `output`, if the model made one. `Some(Err)` means it
+/// tried but the call doesn't parse.
+pub fn parse_tool_call(output: &str) -> Option<Result<ToolCall>> {
+    let start = output.find(TOOL_CALL_OPEN)? + TOOL_CALL_OPEN.len();
+    let call = &output[start..];
+    let call = call.find(TOOL_CALL_CLOSE).map_or(call, |end| &call[..end]);
+
+    Some(
+        serde_json::from_str(call.trim())
+            .map_err(|e| anyhow!("Tool call is not valid JSON: {}", e)),
+    )
+}
+
+/// Tells streamed text from a tool call apart while tokens arrive, so the
+/// call never reaches the user. Text that could be the start of the opening
+/// marker is held back until it's clear either way.
+#[derive(Debug, Default)]
+pub struct ToolCallFilter {
+    // Bytes of the output already released
+    released: usize,
+    in_call: bool,
+}
+
+impl ToolCallFilter {
+    /// The part of `output` (everything generated so far) that can be shown
+    /// now and wasn't shown before.
+    pub fn visible<'a>(&mut self, output: &'a str) -> &'a str {
+        if self.in_call {
+            return "";
+        }
+
+        let end = match output.find(TOOL_CALL_OPEN) {
+            Some(start) => {
+                self.in_call = true;
+                start
+            }
+            None => output.len() - held_back(output),
+        };
+        // Held-back text is never released, so this only moves forward
+        let end = end.max(self.released);
+        let visible = &output[self.released..end];
+        self.released = end;
+        visible
+    }
+
+    /// What was held back, once generation is done without a tool call.
+    pub fn finish<'a>(&mut self, output: &'a str) -> &'a str {
+        if self.in_call {
+            return "";
+        }
+
+        let rest = &output[self.released..];
+        self.released = output.len();
+        rest
+    }
+}
+
+/// Length of the longest end of `output` that the opening marker starts
+/// with. The marker is ASCII, so this always falls on a char boundary.
+fn held_back(output: &str) -> usize {
+    (1..TOOL_CALL_OPEN.len())
+        .rev()
+        .find(|&len| output.ends_with(&TOOL_CALL_OPEN[..len]))
+        .unwrap_or(0)
+}
+
+/// A short form of an arguments schema, e.g. `age: number, weight: {value,
+/// unit: kg|g|lb}, doses_per_day?: integer`. Much cheaper in tokens than
+/// the schema itself.
+fn signature(parameters: &serde_json::Value) -> String {
+    let required: Vec<&str> = parameters["required"]
+        .as_array()
+        .map(|required| required.iter().filter_map(|name| name.as_str()).collect())
+        .unwrap_or_default();
+
+    parameters["properties"]
+        .as_object()
+        .map(|properties| {
+            properties
+                .iter()
+                .map(|(name, schema)| {
+                    let optional = if required.contains(&name.as_str()) {
+                        ""
+                    } else {
+                        "?"
+                    };
+                    format!("{}{}: {}", name, optional, type_signature(schema))
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .unwrap_or_default()
+}
+
+fn type_signature(schema: &serde_json::Value) -> String {
+    if let Some(options) = schema["enum"].as_array() {
+        let options: Vec<&str> = options
+            .iter()
+            .filter_map(|option| option.as_str())
+            .collect();
+        return options.join("|");
+    }
+    if schema["type"] == "array" {
+        return format!("[{}]", type_signature(&schema["items"]));
+    }
+    if schema["type"] == "object" {
+        return format!("{{{}}}", signature(schema));
+    }
+    schema["type"].as_str().unwrap_or("any").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `tokens` one at a time, as streaming does, and returns what
+    // became visible
+    fn stream(tokens: &[&str]) -> String {
+        let mut filter = ToolCallFilter::default();
+        let mut output = String::new();
+        let mut shown = String::new();
+        for token in tokens {
+            output.push_str(token);
+            shown.push_str(filter.visible(&output));
+        }
+        shown.push_str(filter.finish(&output));
+        shown
+    }
+
+    #[test]
+    fn parses_a_call_with_or_without_the_closing_marker() {
+        let call = parse_tool_call(
+            "Let me check.<tool_call>{\"name\": \"bmi\", \"arguments\": {\"age\": 40}}</tool_call>",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(call.name, "bmi");
+        assert_eq!(call.arguments["age"], 40);
+
+        // Generation stops at the closing marker, so it's usually missing
+        let call = parse_tool_call("<tool_call> {\"name\": \"qsofa\"} ")
+            .unwrap()
+            .unwrap();
+        assert_eq!(call.name, "qsofa");
+        assert!(call.arguments.is_null());
+    }
+
+    #[test]
+    fn reports_calls_that_dont_parse() {
+        assert!(parse_tool_call("No tools needed.").is_none());
+        assert!(parse_tool_call("<tool_call>{\"name\": ").unwrap().is_err());
+        assert!(parse_tool_call("<tool_call></tool_call>").unwrap().is_err());
+    }
+
+    #[test]
+    fn hides_the_call_and_everything_after_it() {
+        assert_eq!(
+            stream(&[
+                "Checking",
+                " now.",
+                "<tool_call>",
+                "{\"name\"",
+                "}</tool_call>"
+            ]),
+            "Checking now."
+        );
+    }
+
+    #[test]
+    fn hides_a_marker_split_across_tokens() {
+        assert_eq!(
+            stream(&["Dose ", "<", "tool", "_ca", "ll>{\"name\": \"bmi\"}"]),
+            "Dose "
+        );
+    }
+
+    #[test]
+    fn holds_back_a_partial_marker_until_it_turns_out_to_be_text() {
+        let mut filter = ToolCallFilter::default();
+        assert_eq!(filter.visible("a <to"), "a ");
+        assert_eq!(filter.visible("a <tom"), "<tom");
+        assert_eq!(filter.visible("a <tom <tool_c"), " ");
+        assert_eq!(filter.finish("a <tom <tool_c"), "<tool_c");
+    }
+
+    #[test]
+    fn releases_plain_text_unchanged() {
+        assert_eq!(
+            stream(&["Use ", "a < b", " and <b>bold</b>", " <"]),
+            "Use a < b and <b>bold</b> <"
+        );
+    }
+}
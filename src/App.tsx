@@ -1,5 +1,6 @@
 import { useState, useEffect, useRef } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { 
   Send, 
   MessageSquare, 
@@ -8,7 +9,19 @@ import {
   AlertCircle, 
   Settings,
   Trash2,
-  Plus
+  Plus,
+  Square,
+  SlidersHorizontal,
+  RefreshCw,
+  ChevronLeft,
+  ChevronRight,
+  Pencil,
+  FileText,
+  Stethoscope,
+  BookOpen,
+  Pill,
+  Calculator,
+  Wrench
 } from "lucide-react";
 import "./App.css";
 
@@ -18,6 +31,23 @@ interface ChatMessage {
   role: string;
   content: string;
   timestamp: string;
+  cancelled?: boolean;
+  parent_id?: string | null;
+  // Position among messages sharing a parent: alternative answers to a
+  // question, or edited versions of a question
+  sibling_index?: number;
+  sibling_count?: number;
+  citations?: Citation[];
+  // Set on "tool" messages, which record a tool call made for the reply
+  // that follows them
+  tool_call?: ToolCallRecord | null;
+}
+
+interface ToolCallRecord {
+  name: string;
+  arguments: unknown;
+  output?: unknown;
+  error?: string | null;
 }
 
 interface Conversation {
@@ -34,17 +64,188 @@ interface ModelInfo {
   download_url: string;
   filename: string;
   is_downloaded: boolean;
+  chat_template?: string | null;
+  embedding?: boolean;
+}
+
+interface GenerationSettings {
+  temperature: number;
+  top_p: number;
+  top_k: number;
+  repeat_penalty: number;
+  n_predict: number;
+  stop: string[];
 }
 
 interface ChatRequest {
   message: string;
   conversation_id?: string;
+  generation_settings?: GenerationSettings;
+  engine_id?: string;
+  profile_id?: string;
+}
+
+interface PromptProfile {
+  id: string;
+  name: string;
+  description: string;
+  system_prompt: string;
+  builtin: boolean;
+}
+
+interface SoapNote {
+  id: string;
+  conversation_id: string;
+  note: {
+    subjective: string;
+    objective: string;
+    assessment: string;
+    plan: string;
+  };
+  covers_message_id?: string | null;
+  created_at: string;
+  markdown: string;
+}
+
+type LikelihoodTier = "high" | "moderate" | "low";
+
+interface DifferentialEntry {
+  diagnosis: string;
+  likelihood: LikelihoodTier;
+  supporting_findings: string[];
+  against_findings: string[];
+  next_tests: string[];
+  red_flags: string[];
+}
+
+interface DifferentialRecord {
+  id: string;
+  conversation_id: string;
+  differential: { diagnoses: DifferentialEntry[] };
+  covers_message_id?: string | null;
+  created_at: string;
+}
+
+type DifferentialSortKey = "diagnosis" | "likelihood" | "red_flags";
+
+const LIKELIHOOD_RANK: Record<LikelihoodTier, number> = { high: 0, moderate: 1, low: 2 };
+
+interface RedFlagAlert {
+  rule_id: string;
+  name: string;
+  severity: "critical" | "urgent";
+  advice: string;
+  matched_terms: string[];
+  found_in: ("user_message" | "reply")[];
+}
+
+interface RedFlagEvent {
+  conversation_id: string;
+  message_id: string;
+  alerts: RedFlagAlert[];
+}
+
+interface SourceReference {
+  passage_id: string;
+  document_id: string;
+  title: string;
+  section?: string | null;
+  page?: number | null;
+  score: number;
+}
+
+interface Citation {
+  marker: number;
+  passage_id: string;
+  document_id: string;
+  title: string;
+  section?: string | null;
+  page?: number | null;
+}
+
+interface KnowledgeDocument {
+  id: string;
+  title: string;
+  source_path: string;
+  kind: "markdown" | "text" | "pdf";
+  chunk_count: number;
+  embedding_model: string;
+  added_at: string;
+}
+
+interface InteractionFinding {
+  drug_a: string;
+  drug_b: string;
+  severity: "contraindicated" | "major" | "moderate" | "minor";
+  effect: string;
+  management: string;
+}
+
+interface InteractionReport {
+  dataset_version: string;
+  dataset_source: "bundled" | "imported";
+  drugs: string[];
+  unrecognized: string[];
+  interactions: InteractionFinding[];
+}
+
+interface InteractionDatasetInfo {
+  version: string;
+  source: "bundled" | "imported";
+  drug_count: number;
+  interaction_count: number;
+}
+
+interface CalculationInput {
+  name: string;
+  value: string;
+}
+
+interface CalculationResult {
+  calculator: string;
+  name: string;
+  value: number;
+  unit: string;
+  display: string;
+  interpretation: string;
+  inputs: CalculationInput[];
 }
 
 interface ChatResponse {
   message: string;
   conversation_id: string;
   message_id: string;
+  cancelled: boolean;
+  alerts: RedFlagAlert[];
+  interactions?: InteractionReport | null;
+  calculations: CalculationResult[];
+  warnings: ("empty" | "truncated" | "unsupported_citation" | "tool_limit_reached")[];
+  sources: SourceReference[];
+  citations: Citation[];
+}
+
+interface ReplySettings {
+  append_disclaimer: boolean;
+  disclaimer: string;
+}
+
+interface EngineStatus {
+  state: "starting" | "loading" | "ready" | "crashed" | "restarting" | "failed" | "stopped";
+  reason?: string;
+  progress?: number;
+  attempt?: number;
+  max_attempts?: number;
+}
+
+interface EngineStatusEvent {
+  engine_id: string;
+  status: EngineStatus;
+}
+
+interface ChatStreamChunk {
+  conversation_id: string;
+  message_id: string;
+  content: string;
 }
 
 function App() {
@@ -59,16 +260,76 @@ function App() {
   const [isAiReady, setIsAiReady] = useState(false);
   const [showSetup, setShowSetup] = useState(true);
   const [downloadProgress, setDownloadProgress] = useState<{[key: string]: number}>({});
+  const [streamingMessage, setStreamingMessage] = useState<ChatMessage | null>(null);
+  const [generatingConversation, setGeneratingConversation] = useState<string | null>(null);
+  const [generationSettings, setGenerationSettings] = useState<GenerationSettings | null>(null);
+  const [showGenerationSettings, setShowGenerationSettings] = useState(false);
+  const [engineStatus, setEngineStatus] = useState<EngineStatus | null>(null);
+  const [editingMessage, setEditingMessage] = useState<{ id: string; content: string } | null>(null);
+  const [promptProfiles, setPromptProfiles] = useState<PromptProfile[]>([]);
+  const [activeProfile, setActiveProfile] = useState<PromptProfile | null>(null);
+  const [soapNotes, setSoapNotes] = useState<SoapNote[]>([]);
+  const [showSoapNotes, setShowSoapNotes] = useState(false);
+  const [isGeneratingNote, setIsGeneratingNote] = useState(false);
+  const [redFlagAlerts, setRedFlagAlerts] = useState<RedFlagAlert[]>([]);
+  const [replyWarnings, setReplyWarnings] = useState<ChatResponse["warnings"]>([]);
+  const [replySettings, setReplySettings] = useState<ReplySettings | null>(null);
+  const [replySources, setReplySources] = useState<SourceReference[]>([]);
+  const [knowledgeDocuments, setKnowledgeDocuments] = useState<KnowledgeDocument[]>([]);
+  const [showKnowledgeBase, setShowKnowledgeBase] = useState(false);
+  const [documentPath, setDocumentPath] = useState("");
+  const [isAddingDocument, setIsAddingDocument] = useState(false);
+  const [embeddingModel, setEmbeddingModel] = useState<string | null>(null);
+  const [replyInteractions, setReplyInteractions] = useState<InteractionReport | null>(null);
+  const [showInteractions, setShowInteractions] = useState(false);
+  const [replyCalculations, setReplyCalculations] = useState<CalculationResult[]>([]);
+  const [interactionQuery, setInteractionQuery] = useState("");
+  const [interactionReport, setInteractionReport] = useState<InteractionReport | null>(null);
+  const [interactionDataset, setInteractionDataset] = useState<InteractionDatasetInfo | null>(null);
+  const [datasetPath, setDatasetPath] = useState("");
+  const [differentials, setDifferentials] = useState<DifferentialRecord[]>([]);
+  const [showDifferential, setShowDifferential] = useState(false);
+  const [isGeneratingDifferential, setIsGeneratingDifferential] = useState(false);
+  const [differentialSort, setDifferentialSort] = useState<{ key: DifferentialSortKey; ascending: boolean }>({
+    key: "likelihood",
+    ascending: true,
+  });
   
   const messagesEndRef = useRef<HTMLDivElement>(null);
 
   useEffect(() => {
     initializeApp();
+
+    const unlisten = listen<EngineStatusEvent>("engine-status", (event) => {
+      setEngineStatus(event.payload.status);
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
   }, []);
 
+  // Red flags in a question arrive before its reply
+  useEffect(() => {
+    const unlisten = listen<RedFlagEvent>("red-flag-alerts", (event) => {
+      setRedFlagAlerts(event.payload.alerts);
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  useEffect(() => {
+    if (isInitialized) {
+      loadGenerationSettings(currentConversation);
+      loadConversationProfile(currentConversation);
+      loadSoapNotes(currentConversation);
+      loadDifferentials(currentConversation);
+    }
+  }, [isInitialized, currentConversation]);
+
   useEffect(() => {
     scrollToBottom();
-  }, [messages]);
+  }, [messages, streamingMessage]);
 
   const scrollToBottom = () => {
     messagesEndRef.current?.scrollIntoView({ behavior: "smooth" });
@@ -80,9 +341,13 @@ function App() {
       const models = await invoke("get_available_models") as ModelInfo[];
       setAvailableModels(models);
       setIsInitialized(true);
+      await loadPromptProfiles();
+      setReplySettings(await invoke("get_reply_settings") as ReplySettings);
+      await loadKnowledgeDocuments();
+      setInteractionDataset(await invoke("get_interaction_dataset_info") as InteractionDatasetInfo);
       
       // Check if any model is downloaded
-      const downloadedModel = models.find(m => m.is_downloaded);
+      const downloadedModel = models.find(m => m.is_downloaded && !m.embedding);
       if (downloadedModel) {
         setSelectedModel(downloadedModel.filename);
         await initializeAI(downloadedModel.filename);
@@ -113,6 +378,37 @@ function App() {
     }
   };
 
+  const loadGenerationSettings = async (conversationId: string | null) => {
+    try {
+      const settings = await invoke("get_generation_settings", { conversationId }) as GenerationSettings;
+      setGenerationSettings(settings);
+    } catch (error) {
+      console.error("Failed to load generation settings:", error);
+    }
+  };
+
+  const saveGenerationSettings = async (asDefault: boolean) => {
+    if (!generationSettings) return;
+
+    try {
+      if (asDefault || !currentConversation) {
+        await invoke("save_default_generation_settings", { settings: generationSettings });
+      } else {
+        await invoke("set_conversation_generation_settings", {
+          conversationId: currentConversation,
+          settings: generationSettings,
+        });
+      }
+    } catch (error) {
+      console.error("Failed to save generation settings:", error);
+    }
+  };
+
+  const updateGenerationSetting = (key: keyof GenerationSettings, value: string) => {
+    if (!generationSettings) return;
+    setGenerationSettings({ ...generationSettings, [key]: Number(value) });
+  };
+
   const downloadModel = async (model: ModelInfo) => {
     try {
       setDownloadProgress({...downloadProgress, [model.filename]: 0});
@@ -126,7 +422,7 @@ function App() {
       setDownloadProgress({...downloadProgress, [model.filename]: 100});
       
       // If no model is selected, select this one
-      if (!selectedModel) {
+      if (!selectedModel && !model.embedding) {
         setSelectedModel(model.filename);
         await initializeAI(model.filename);
       }
@@ -150,20 +446,312 @@ function App() {
     }
   };
 
+  const loadEmbeddingModel = async (modelFilename: string) => {
+    try {
+      setIsLoading(true);
+      await invoke("initialize_embedding_engine", { modelFilename });
+      setEmbeddingModel(modelFilename);
+    } catch (error) {
+      console.error("Failed to load embedding model:", error);
+    } finally {
+      setIsLoading(false);
+    }
+  };
+
+  const loadKnowledgeDocuments = async () => {
+    try {
+      setKnowledgeDocuments(await invoke("get_knowledge_documents") as KnowledgeDocument[]);
+    } catch (error) {
+      console.error("Failed to load knowledge base:", error);
+    }
+  };
+
+  const addKnowledgeDocument = async () => {
+    if (!documentPath.trim()) return;
+
+    setIsAddingDocument(true);
+    try {
+      await invoke("add_knowledge_document", { path: documentPath.trim() });
+      setDocumentPath("");
+      await loadKnowledgeDocuments();
+    } catch (error) {
+      console.error("Failed to add document:", error);
+    } finally {
+      setIsAddingDocument(false);
+    }
+  };
+
+  const deleteKnowledgeDocument = async (documentId: string) => {
+    try {
+      await invoke("delete_knowledge_document", { documentId });
+      await loadKnowledgeDocuments();
+    } catch (error) {
+      console.error("Failed to delete document:", error);
+    }
+  };
+
+  const checkInteractions = async () => {
+    const drugs = interactionQuery.split(",").map((drug) => drug.trim()).filter(Boolean);
+    if (drugs.length === 0) return;
+
+    try {
+      setInteractionReport(await invoke("check_interactions", { drugs }) as InteractionReport);
+    } catch (error) {
+      console.error("Failed to check interactions:", error);
+    }
+  };
+
+  const importInteractionDataset = async () => {
+    if (!datasetPath.trim()) return;
+
+    try {
+      setInteractionDataset(await invoke("import_interaction_dataset", { path: datasetPath.trim() }) as InteractionDatasetInfo);
+      setDatasetPath("");
+      setInteractionReport(null);
+    } catch (error) {
+      console.error("Failed to import interaction dataset:", error);
+    }
+  };
+
+  const renderInteractionReport = (report: InteractionReport) => (
+    <div className="space-y-1">
+      {report.interactions.length === 0 ? (
+        <p>
+          No interactions between {report.drugs.join(", ")} found in the {report.dataset_source} interaction dataset, which is
+          not exhaustive.
+        </p>
+      ) : (
+        report.interactions.map((finding) => (
+          <div key={`${finding.drug_a}-${finding.drug_b}-${finding.effect}`}>
+            <p className="font-semibold">
+              <span className={finding.severity === "contraindicated" || finding.severity === "major" ? "text-red-700" : "text-orange-700"}>
+                {finding.severity.charAt(0).toUpperCase() + finding.severity.slice(1)}
+              </span>
+              : {finding.drug_a} + {finding.drug_b}
+            </p>
+            <p>{finding.effect} {finding.management}</p>
+          </div>
+        ))
+      )}
+      {report.unrecognized.length > 0 && (
+        <p className="text-gray-500">Not in the dataset: {report.unrecognized.join(", ")}</p>
+      )}
+      <p className="text-xs text-gray-500">
+        Source: {report.dataset_source} interaction dataset, version {report.dataset_version}
+      </p>
+    </div>
+  );
+
+  const sourceLocation = (source: SourceReference | Citation) =>
+    [source.title, source.section, source.page ? `p. ${source.page}` : null].filter(Boolean).join(", ");
+
+  const saveReplySettings = async () => {
+    if (!replySettings) return;
+
+    try {
+      await invoke("save_reply_settings", { settings: replySettings });
+    } catch (error) {
+      console.error("Failed to save reply settings:", error);
+    }
+  };
+
+  const loadPromptProfiles = async () => {
+    try {
+      const profiles = await invoke("get_prompt_profiles") as PromptProfile[];
+      setPromptProfiles(profiles);
+    } catch (error) {
+      console.error("Failed to load prompt profiles:", error);
+    }
+  };
+
+  const loadConversationProfile = async (conversationId: string | null) => {
+    try {
+      const profile = await invoke("get_conversation_profile", { conversationId }) as PromptProfile;
+      setActiveProfile(profile);
+    } catch (error) {
+      console.error("Failed to load prompt profile:", error);
+    }
+  };
+
+  // For a new consultation the choice is sent along with the first message
+  const selectProfile = async (profileId: string) => {
+    const profile = promptProfiles.find((p) => p.id === profileId);
+    if (!profile) return;
+
+    try {
+      if (currentConversation) {
+        await invoke("set_conversation_profile", { conversationId: currentConversation, profileId });
+      }
+      setActiveProfile(profile);
+    } catch (error) {
+      console.error("Failed to select prompt profile:", error);
+    }
+  };
+
+  const saveActiveProfile = async () => {
+    if (!activeProfile) return;
+
+    try {
+      await invoke("save_prompt_profile", { profile: activeProfile });
+      await loadPromptProfiles();
+    } catch (error) {
+      console.error("Failed to save prompt profile:", error);
+    }
+  };
+
+  // Resets a built-in profile, removes a custom one
+  const deleteActiveProfile = async () => {
+    if (!activeProfile) return;
+
+    try {
+      await invoke("delete_prompt_profile", { profileId: activeProfile.id });
+      await loadPromptProfiles();
+      await loadConversationProfile(currentConversation);
+    } catch (error) {
+      console.error("Failed to delete prompt profile:", error);
+    }
+  };
+
+  const exportProfiles = async () => {
+    try {
+      const json = await invoke("export_prompt_profiles") as string;
+      const url = URL.createObjectURL(new Blob([json], { type: "application/json" }));
+      const link = document.createElement("a");
+      link.href = url;
+      link.download = "prompt-profiles.json";
+      link.click();
+      URL.revokeObjectURL(url);
+    } catch (error) {
+      console.error("Failed to export prompt profiles:", error);
+    }
+  };
+
+  const importProfiles = async (file: File) => {
+    try {
+      const json = await file.text();
+      await invoke("import_prompt_profiles", { json });
+      await loadPromptProfiles();
+    } catch (error) {
+      console.error("Failed to import prompt profiles:", error);
+    }
+  };
+
+  const loadSoapNotes = async (conversationId: string | null) => {
+    if (!conversationId) {
+      setSoapNotes([]);
+      return;
+    }
+
+    try {
+      const notes = await invoke("get_soap_notes", { conversationId }) as SoapNote[];
+      setSoapNotes(notes);
+    } catch (error) {
+      console.error("Failed to load SOAP notes:", error);
+    }
+  };
+
+  const generateSoapNote = async () => {
+    if (!currentConversation || isGeneratingNote) return;
+
+    setIsGeneratingNote(true);
+    setShowSoapNotes(true);
+    try {
+      const note = await invoke("generate_soap_note", { conversationId: currentConversation }) as SoapNote;
+      setSoapNotes((prev) => [...prev, note]);
+    } catch (error) {
+      console.error("Failed to generate SOAP note:", error);
+    } finally {
+      setIsGeneratingNote(false);
+    }
+  };
+
+  const loadDifferentials = async (conversationId: string | null) => {
+    if (!conversationId) {
+      setDifferentials([]);
+      return;
+    }
+
+    try {
+      const records = await invoke("get_differentials", { conversationId }) as DifferentialRecord[];
+      setDifferentials(records);
+    } catch (error) {
+      console.error("Failed to load differentials:", error);
+    }
+  };
+
+  const generateDifferential = async () => {
+    if (!currentConversation || isGeneratingDifferential) return;
+
+    setIsGeneratingDifferential(true);
+    setShowDifferential(true);
+    try {
+      const record = await invoke("generate_differential", { conversationId: currentConversation }) as DifferentialRecord;
+      setDifferentials((prev) => [...prev, record]);
+    } catch (error) {
+      console.error("Failed to generate differential:", error);
+    } finally {
+      setIsGeneratingDifferential(false);
+    }
+  };
+
+  const sortDifferentialBy = (key: DifferentialSortKey) => {
+    setDifferentialSort((prev) => ({ key, ascending: prev.key === key ? !prev.ascending : true }));
+  };
+
+  const sortedDiagnoses = (entries: DifferentialEntry[]) => {
+    const compare = (a: DifferentialEntry, b: DifferentialEntry) => {
+      switch (differentialSort.key) {
+        case "diagnosis":
+          return a.diagnosis.localeCompare(b.diagnosis);
+        case "likelihood":
+          return LIKELIHOOD_RANK[a.likelihood] - LIKELIHOOD_RANK[b.likelihood];
+        case "red_flags":
+          return b.red_flags.length - a.red_flags.length;
+      }
+    };
+    return [...entries].sort((a, b) => (differentialSort.ascending ? compare(a, b) : compare(b, a)));
+  };
+
   const sendMessage = async () => {
     if (!inputMessage.trim() || !isAiReady || isLoading) return;
 
     const userMessage = inputMessage.trim();
     setInputMessage("");
     setIsLoading(true);
+    setRedFlagAlerts([]);
+    setReplyWarnings([]);
+    setReplySources([]);
+    setReplyInteractions(null);
+    setReplyCalculations([]);
+
+    // Show the question immediately while the reply streams in
+    setMessages((prev) => [
+      ...prev,
+      {
+        id: `pending-${Date.now()}`,
+        role: "user",
+        content: userMessage,
+        timestamp: new Date().toISOString(),
+      },
+    ]);
+
+    setGeneratingConversation(currentConversation);
+
+    const unlisten = await listenForTokens();
 
     try {
       const request: ChatRequest = {
         message: userMessage,
-        conversation_id: currentConversation || undefined
+        conversation_id: currentConversation || undefined,
+        profile_id: currentConversation ? undefined : activeProfile?.id,
       };
 
-      const response = await invoke("send_chat_message", { request }) as ChatResponse;
+      const response = await invoke("stream_chat_message", { request }) as ChatResponse;
+      setRedFlagAlerts(response.alerts);
+      setReplyWarnings(response.warnings);
+      setReplySources(response.sources);
+      setReplyInteractions(response.interactions ?? null);
+      setReplyCalculations(response.calculations ?? []);
       
       // If this is a new conversation, update the conversation list
       if (!currentConversation) {
@@ -177,13 +765,155 @@ function App() {
     } catch (error) {
       console.error("Failed to send message:", error);
     } finally {
+      unlisten();
+      setStreamingMessage(null);
+      setGeneratingConversation(null);
+      setIsLoading(false);
+    }
+  };
+
+  const listenForTokens = () =>
+    listen<ChatStreamChunk>("chat-token", (event) => {
+      const chunk = event.payload;
+      setGeneratingConversation(chunk.conversation_id);
+      setStreamingMessage((prev) =>
+        prev && prev.id === chunk.message_id
+          ? { ...prev, content: prev.content + chunk.content }
+          : {
+              id: chunk.message_id,
+              role: "assistant",
+              content: chunk.content,
+              timestamp: new Date().toISOString(),
+            }
+      );
+    });
+
+  const regenerateMessage = async (messageId: string) => {
+    if (!currentConversation || !isAiReady || isLoading) return;
+
+    setIsLoading(true);
+    setRedFlagAlerts([]);
+    setReplyWarnings([]);
+    setReplySources([]);
+    setReplyInteractions(null);
+    setReplyCalculations([]);
+    setGeneratingConversation(currentConversation);
+
+    // The new answer replaces this one and everything after it on screen
+    setMessages((prev) => prev.slice(0, prev.findIndex((m) => m.id === messageId)));
+
+    const unlisten = await listenForTokens();
+
+    try {
+      const response = await invoke("regenerate_message", {
+        request: { conversation_id: currentConversation, message_id: messageId },
+      }) as ChatResponse;
+      setRedFlagAlerts(response.alerts);
+      setReplyWarnings(response.warnings);
+      setReplySources(response.sources);
+      setReplyInteractions(response.interactions ?? null);
+      setReplyCalculations(response.calculations ?? []);
+    } catch (error) {
+      console.error("Failed to regenerate message:", error);
+    } finally {
+      unlisten();
+      setStreamingMessage(null);
+      setGeneratingConversation(null);
+      setIsLoading(false);
+      await loadConversationMessages(currentConversation);
+    }
+  };
+
+  const editMessage = async () => {
+    if (!editingMessage || !editingMessage.content.trim() || !currentConversation || !isAiReady || isLoading) return;
+
+    const { id: messageId, content } = editingMessage;
+    setEditingMessage(null);
+    setIsLoading(true);
+    setRedFlagAlerts([]);
+    setReplyWarnings([]);
+    setReplySources([]);
+    setReplyInteractions(null);
+    setReplyCalculations([]);
+    setGeneratingConversation(currentConversation);
+
+    // Show the corrected question in place of the original and drop what
+    // followed it; the old branch stays stored
+    setMessages((prev) => [
+      ...prev.slice(0, prev.findIndex((m) => m.id === messageId)),
+      {
+        id: `pending-${Date.now()}`,
+        role: "user",
+        content: content.trim(),
+        timestamp: new Date().toISOString(),
+      },
+    ]);
+
+    const unlisten = await listenForTokens();
+
+    try {
+      const response = await invoke("edit_message", {
+        request: {
+          conversation_id: currentConversation,
+          message_id: messageId,
+          content: content.trim(),
+        },
+      }) as ChatResponse;
+      setRedFlagAlerts(response.alerts);
+      setReplyWarnings(response.warnings);
+      setReplySources(response.sources);
+      setReplyInteractions(response.interactions ?? null);
+      setReplyCalculations(response.calculations ?? []);
+    } catch (error) {
+      console.error("Failed to edit message:", error);
+    } finally {
+      unlisten();
+      setStreamingMessage(null);
+      setGeneratingConversation(null);
       setIsLoading(false);
+      await loadConversationMessages(currentConversation);
+    }
+  };
+
+  const switchAlternative = async (message: ChatMessage, step: number) => {
+    if (!currentConversation || isLoading) return;
+
+    try {
+      const alternatives = await invoke("get_message_alternatives", {
+        conversationId: currentConversation,
+        messageId: message.id,
+      }) as ChatMessage[];
+      const target = alternatives[(message.sibling_index ?? 0) + step];
+      if (!target) return;
+
+      await invoke("set_active_alternative", {
+        conversationId: currentConversation,
+        messageId: target.id,
+      });
+      await loadConversationMessages(currentConversation);
+    } catch (error) {
+      console.error("Failed to switch alternative:", error);
+    }
+  };
+
+  const cancelGeneration = async () => {
+    if (!generatingConversation) return;
+
+    try {
+      await invoke("cancel_generation", { conversationId: generatingConversation });
+    } catch (error) {
+      console.error("Failed to cancel generation:", error);
     }
   };
 
   const startNewConversation = () => {
     setCurrentConversation(null);
     setMessages([]);
+    setRedFlagAlerts([]);
+    setReplyWarnings([]);
+    setReplySources([]);
+    setReplyInteractions(null);
+    setReplyCalculations([]);
   };
 
   const deleteConversation = async (conversationId: string) => {
@@ -264,7 +994,16 @@ function App() {
                           </button>
                         )}
                       </div>
-                      {model.is_downloaded && (
+                      {model.is_downloaded && model.embedding && (
+                        <button
+                          onClick={() => loadEmbeddingModel(model.filename)}
+                          disabled={isLoading || embeddingModel === model.filename}
+                          className="bg-green-600 text-white px-4 py-2 rounded text-sm hover:bg-green-700 disabled:opacity-50"
+                        >
+                          {embeddingModel === model.filename ? "Knowledge base ready" : "Use for knowledge base"}
+                        </button>
+                      )}
+                      {model.is_downloaded && !model.embedding && (
                         <button
                           onClick={() => {
                             setSelectedModel(model.filename);
@@ -273,7 +1012,11 @@ function App() {
                           disabled={isLoading}
                           className="bg-green-600 text-white px-4 py-2 rounded text-sm hover:bg-green-700 disabled:opacity-50"
                         >
-                          {isLoading && selectedModel === model.filename ? "Starting..." : "Use Model"}
+                          {isLoading && selectedModel === model.filename
+                            ? engineStatus?.state === "loading"
+                              ? `Loading... ${Math.round((engineStatus.progress ?? 0) * 100)}%`
+                              : "Starting..."
+                            : "Use Model"}
                         </button>
                       )}
                     </div>
@@ -312,7 +1055,14 @@ function App() {
                   className={`p-3 rounded cursor-pointer group flex items-center justify-between ${
                     currentConversation === conv.id ? 'bg-blue-50 border border-blue-200' : 'hover:bg-gray-50'
                   }`}
-                  onClick={() => loadConversationMessages(conv.id)}
+                  onClick={() => {
+                    setRedFlagAlerts([]);
+                    setReplyWarnings([]);
+                    setReplySources([]);
+                    setReplyInteractions(null);
+                    setReplyCalculations([]);
+                    loadConversationMessages(conv.id);
+                  }}
                 >
                   <div className="flex-1 min-w-0">
                     <p className="text-sm font-medium text-gray-800 truncate">{conv.title}</p>
@@ -354,12 +1104,436 @@ function App() {
               {currentConversation ? "Medical Consultation" : "New Consultation"}
             </h2>
             <div className="flex items-center text-sm text-gray-500">
-              <CheckCircle size={16} className="mr-1 text-green-500" />
-              AI Ready - {selectedModel.replace('-', ' ').replace('.gguf', '')}
+              {engineStatus && engineStatus.state !== "ready" ? (
+                <span className="flex items-center text-red-600" title={engineStatus.reason}>
+                  <AlertCircle size={16} className="mr-1" />
+                  {engineStatus.state === "restarting"
+                    ? `AI restarting (attempt ${engineStatus.attempt}/${engineStatus.max_attempts})`
+                    : `AI ${engineStatus.state}`}
+                </span>
+              ) : (
+                <>
+                  <CheckCircle size={16} className="mr-1 text-green-500" />
+                  AI Ready - {selectedModel.replace('-', ' ').replace('.gguf', '')}
+                </>
+              )}
+              <select
+                value={activeProfile?.id ?? ""}
+                onChange={(e) => selectProfile(e.target.value)}
+                className="ml-3 border border-gray-300 rounded px-2 py-1 text-gray-700"
+                title={activeProfile?.description}
+              >
+                {promptProfiles.map((profile) => (
+                  <option key={profile.id} value={profile.id}>
+                    {profile.name}
+                  </option>
+                ))}
+              </select>
+              {currentConversation && (
+                <button
+                  onClick={() => setShowSoapNotes(!showSoapNotes)}
+                  className="ml-3 text-gray-500 hover:text-gray-700 p-1"
+                  title="SOAP notes"
+                >
+                  <FileText size={16} />
+                </button>
+              )}
+              {currentConversation && (
+                <button
+                  onClick={() => setShowDifferential(!showDifferential)}
+                  className="ml-3 text-gray-500 hover:text-gray-700 p-1"
+                  title="Differential diagnosis"
+                >
+                  <Stethoscope size={16} />
+                </button>
+              )}
+              <button
+                onClick={() => setShowInteractions(!showInteractions)}
+                className="ml-3 text-gray-500 hover:text-gray-700 p-1"
+                title="Drug interactions"
+              >
+                <Pill size={16} />
+              </button>
+              <button
+                onClick={() => setShowKnowledgeBase(!showKnowledgeBase)}
+                className="ml-3 text-gray-500 hover:text-gray-700 p-1"
+                title="Knowledge base"
+              >
+                <BookOpen size={16} />
+              </button>
+              <button
+                onClick={() => setShowGenerationSettings(!showGenerationSettings)}
+                className="ml-3 text-gray-500 hover:text-gray-700 p-1"
+                title="Generation settings"
+              >
+                <SlidersHorizontal size={16} />
+              </button>
             </div>
           </div>
+          {showGenerationSettings && generationSettings && (
+            <div className="mt-3 flex flex-wrap items-end gap-3 text-sm">
+              {(["temperature", "top_p", "top_k", "repeat_penalty", "n_predict"] as const).map((key) => (
+                <label key={key} className="flex flex-col text-gray-600">
+                  {key}
+                  <input
+                    type="number"
+                    step={key === "top_k" || key === "n_predict" ? 1 : 0.05}
+                    value={generationSettings[key]}
+                    onChange={(e) => updateGenerationSetting(key, e.target.value)}
+                    className="w-24 border border-gray-300 rounded px-2 py-1"
+                  />
+                </label>
+              ))}
+              {currentConversation && (
+                <button
+                  onClick={() => saveGenerationSettings(false)}
+                  className="bg-blue-600 text-white px-3 py-1 rounded hover:bg-blue-700"
+                >
+                  Save for conversation
+                </button>
+              )}
+              <button
+                onClick={() => saveGenerationSettings(true)}
+                className="bg-gray-100 text-gray-700 px-3 py-1 rounded hover:bg-gray-200"
+              >
+                Save as default
+              </button>
+            </div>
+          )}
+          {showGenerationSettings && activeProfile && (
+            <div className="mt-3 text-sm">
+              <label className="flex flex-col text-gray-600">
+                System prompt ({activeProfile.name})
+                <textarea
+                  value={activeProfile.system_prompt}
+                  onChange={(e) => setActiveProfile({ ...activeProfile, system_prompt: e.target.value })}
+                  rows={4}
+                  className="border border-gray-300 rounded px-2 py-1 mt-1"
+                />
+              </label>
+              <div className="mt-2 flex flex-wrap gap-3">
+                <button
+                  onClick={saveActiveProfile}
+                  className="bg-blue-600 text-white px-3 py-1 rounded hover:bg-blue-700"
+                >
+                  Save profile
+                </button>
+                <button
+                  onClick={deleteActiveProfile}
+                  className="bg-gray-100 text-gray-700 px-3 py-1 rounded hover:bg-gray-200"
+                >
+                  {activeProfile.builtin ? "Reset profile" : "Delete profile"}
+                </button>
+                <button
+                  onClick={exportProfiles}
+                  className="bg-gray-100 text-gray-700 px-3 py-1 rounded hover:bg-gray-200"
+                >
+                  Export profiles
+                </button>
+                <label className="bg-gray-100 text-gray-700 px-3 py-1 rounded hover:bg-gray-200 cursor-pointer">
+                  Import profiles
+                  <input
+                    type="file"
+                    accept="application/json"
+                    className="hidden"
+                    onChange={(e) => {
+                      const file = e.target.files?.[0];
+                      if (file) importProfiles(file);
+                      e.target.value = "";
+                    }}
+                  />
+                </label>
+              </div>
+            </div>
+          )}
+          {showGenerationSettings && replySettings && (
+            <div className="mt-3 flex flex-wrap items-center gap-3 text-sm">
+              <label className="flex items-center text-gray-600">
+                <input
+                  type="checkbox"
+                  checked={replySettings.append_disclaimer}
+                  onChange={(e) => setReplySettings({ ...replySettings, append_disclaimer: e.target.checked })}
+                  className="mr-2"
+                />
+                Disclaimer
+              </label>
+              <input
+                type="text"
+                value={replySettings.disclaimer}
+                onChange={(e) => setReplySettings({ ...replySettings, disclaimer: e.target.value })}
+                className="flex-1 min-w-64 border border-gray-300 rounded px-2 py-1"
+              />
+              <button
+                onClick={saveReplySettings}
+                className="bg-gray-100 text-gray-700 px-3 py-1 rounded hover:bg-gray-200"
+              >
+                Save disclaimer
+              </button>
+            </div>
+          )}
+          {showInteractions && (
+            <div className="mt-3 text-sm">
+              {interactionDataset && (
+                <p className="text-gray-600">
+                  Dataset {interactionDataset.version} ({interactionDataset.source}): {interactionDataset.drug_count} drugs, {interactionDataset.interaction_count} interactions
+                </p>
+              )}
+              <div className="mt-2 flex items-center gap-3">
+                <input
+                  type="text"
+                  value={interactionQuery}
+                  onChange={(e) => setInteractionQuery(e.target.value)}
+                  onKeyDown={(e) => e.key === "Enter" && checkInteractions()}
+                  placeholder="Drugs, separated by commas"
+                  className="flex-1 border border-gray-300 rounded px-2 py-1"
+                />
+                <button
+                  onClick={checkInteractions}
+                  className="bg-blue-600 text-white px-3 py-1 rounded hover:bg-blue-700"
+                >
+                  Check interactions
+                </button>
+              </div>
+              {interactionReport && <div className="mt-2 text-gray-800">{renderInteractionReport(interactionReport)}</div>}
+              <div className="mt-2 flex items-center gap-3">
+                <input
+                  type="text"
+                  value={datasetPath}
+                  onChange={(e) => setDatasetPath(e.target.value)}
+                  placeholder="Path to a .json or .csv interaction dataset"
+                  className="flex-1 border border-gray-300 rounded px-2 py-1"
+                />
+                <button
+                  onClick={importInteractionDataset}
+                  disabled={!datasetPath.trim()}
+                  className="bg-gray-100 text-gray-700 px-3 py-1 rounded hover:bg-gray-200 disabled:opacity-50"
+                >
+                  Import dataset
+                </button>
+              </div>
+            </div>
+          )}
+          {showKnowledgeBase && (
+            <div className="mt-3 text-sm">
+              <p className="text-gray-600">
+                {embeddingModel
+                  ? `Embedding with ${embeddingModel}`
+                  : "No embedding model loaded; documents are embedded with the chat model"}
+              </p>
+              <div className="mt-2 flex items-center gap-3">
+                <input
+                  type="text"
+                  value={documentPath}
+                  onChange={(e) => setDocumentPath(e.target.value)}
+                  placeholder="Path to a .md, .txt or .pdf file"
+                  className="flex-1 border border-gray-300 rounded px-2 py-1"
+                />
+                <button
+                  onClick={addKnowledgeDocument}
+                  disabled={isAddingDocument || !documentPath.trim()}
+                  className="bg-blue-600 text-white px-3 py-1 rounded hover:bg-blue-700 disabled:bg-gray-300"
+                >
+                  {isAddingDocument ? "Adding..." : "Add document"}
+                </button>
+              </div>
+              {knowledgeDocuments.length > 0 && (
+                <ul className="mt-2 max-h-48 overflow-y-auto divide-y divide-gray-200 border border-gray-200 rounded">
+                  {knowledgeDocuments.map((document) => (
+                    <li key={document.id} className="p-2 flex items-center justify-between">
+                      <div className="min-w-0">
+                        <p className="font-medium text-gray-800 truncate">{document.title}</p>
+                        <p className="text-xs text-gray-500 truncate">
+                          {document.chunk_count} passages, embedded with {document.embedding_model}
+                        </p>
+                      </div>
+                      <button
+                        onClick={() => deleteKnowledgeDocument(document.id)}
+                        className="text-red-500 hover:text-red-700 p-1"
+                        title="Remove from knowledge base"
+                      >
+                        <Trash2 size={14} />
+                      </button>
+                    </li>
+                  ))}
+                </ul>
+              )}
+            </div>
+          )}
+          {showSoapNotes && currentConversation && (
+            <div className="mt-3 text-sm">
+              <div className="flex items-center gap-3">
+                <button
+                  onClick={generateSoapNote}
+                  disabled={isGeneratingNote || !isAiReady}
+                  className="bg-blue-600 text-white px-3 py-1 rounded hover:bg-blue-700 disabled:bg-gray-300"
+                >
+                  {isGeneratingNote ? "Writing SOAP note..." : "Generate SOAP note"}
+                </button>
+                {soapNotes.length > 0 && (
+                  <button
+                    onClick={() => navigator.clipboard.writeText(soapNotes[soapNotes.length - 1].markdown)}
+                    className="bg-gray-100 text-gray-700 px-3 py-1 rounded hover:bg-gray-200"
+                  >
+                    Copy as Markdown
+                  </button>
+                )}
+                {soapNotes.length > 1 && (
+                  <span className="text-gray-500">{soapNotes.length} notes, showing the latest</span>
+                )}
+              </div>
+              {soapNotes.length > 0 && (
+                <pre className="mt-2 max-h-64 overflow-y-auto whitespace-pre-wrap bg-gray-50 border border-gray-200 rounded p-3 text-gray-800 font-sans">
+                  {soapNotes[soapNotes.length - 1].markdown}
+                </pre>
+              )}
+            </div>
+          )}
+          {showDifferential && currentConversation && (
+            <div className="mt-3 text-sm">
+              <button
+                onClick={generateDifferential}
+                disabled={isGeneratingDifferential || !isAiReady}
+                className="bg-blue-600 text-white px-3 py-1 rounded hover:bg-blue-700 disabled:bg-gray-300"
+              >
+                {isGeneratingDifferential ? "Building differential..." : "Generate differential"}
+              </button>
+              {differentials.length > 0 && (
+                <div className="mt-2 max-h-80 overflow-auto">
+                  <table className="w-full border border-gray-200 text-left">
+                    <thead className="bg-gray-50 text-gray-600">
+                      <tr>
+                        {([
+                          ["diagnosis", "Diagnosis"],
+                          ["likelihood", "Likelihood"],
+                        ] as const).map(([key, label]) => (
+                          <th key={key} className="p-2 cursor-pointer" onClick={() => sortDifferentialBy(key)}>
+                            {label}
+                            {differentialSort.key === key && (differentialSort.ascending ? " ▲" : " ▼")}
+                          </th>
+                        ))}
+                        <th className="p-2">Supporting</th>
+                        <th className="p-2">Against</th>
+                        <th className="p-2">Next tests</th>
+                        <th className="p-2 cursor-pointer" onClick={() => sortDifferentialBy("red_flags")}>
+                          Red flags
+                          {differentialSort.key === "red_flags" && (differentialSort.ascending ? " ▲" : " ▼")}
+                        </th>
+                      </tr>
+                    </thead>
+                    <tbody>
+                      {sortedDiagnoses(differentials[differentials.length - 1].differential.diagnoses).map((entry) => (
+                        <tr key={entry.diagnosis} className="border-t border-gray-200 align-top">
+                          <td className="p-2 font-medium text-gray-800">{entry.diagnosis}</td>
+                          <td className="p-2 capitalize">{entry.likelihood}</td>
+                          {([entry.supporting_findings, entry.against_findings, entry.next_tests] as const).map((items, i) => (
+                            <td key={i} className="p-2">
+                              <ul className="list-disc pl-4">
+                                {items.map((item) => <li key={item}>{item}</li>)}
+                              </ul>
+                            </td>
+                          ))}
+                          <td className="p-2 text-red-600">
+                            <ul className="list-disc pl-4">
+                              {entry.red_flags.map((item) => <li key={item}>{item}</li>)}
+                            </ul>
+                          </td>
+                        </tr>
+                      ))}
+                    </tbody>
+                  </table>
+                </div>
+              )}
+            </div>
+          )}
         </div>
 
+        {replyWarnings.includes("truncated") && (
+          <div className="bg-yellow-50 border-b border-yellow-200 p-3 text-sm text-yellow-800 flex items-center">
+            <AlertCircle size={16} className="mr-2" />
+            The last reply hit the token limit and is cut off. Raise n_predict or ask the assistant to continue.
+          </div>
+        )}
+
+        {replyWarnings.includes("unsupported_citation") && (
+          <div className="bg-yellow-50 border-b border-yellow-200 p-3 text-sm text-yellow-800 flex items-center">
+            <AlertCircle size={16} className="mr-2" />
+            The last reply cited passages it was not given; those citations were removed.
+          </div>
+        )}
+
+        {replyWarnings.includes("tool_limit_reached") && (
+          <div className="bg-yellow-50 border-b border-yellow-200 p-3 text-sm text-yellow-800 flex items-center">
+            <AlertCircle size={16} className="mr-2" />
+            The last reply needed more tool calls than allowed, so some results may be missing from it.
+          </div>
+        )}
+
+        {replyInteractions && (
+          <div className="bg-orange-50 border-b border-orange-200 p-3 text-sm text-gray-800">
+            <p className="font-medium flex items-center">
+              <Pill size={16} className="mr-2" />
+              Interaction check for the drugs in the last exchange
+            </p>
+            {renderInteractionReport(replyInteractions)}
+          </div>
+        )}
+
+        {replyCalculations.length > 0 && (
+          <div className="bg-blue-50 border-b border-blue-200 p-3 text-sm text-gray-800">
+            <p className="font-medium flex items-center">
+              <Calculator size={16} className="mr-2" />
+              Calculated for the last reply
+            </p>
+            {replyCalculations.map((calculation, index) => (
+              <div key={index} className="mt-1">
+                <p>
+                  <span className="font-medium">{calculation.name}: {calculation.display}</span> — {calculation.interpretation}
+                </p>
+                <p className="text-xs text-gray-600">
+                  {calculation.inputs.map((input) => `${input.name}: ${input.value}`).join("; ")}
+                </p>
+              </div>
+            ))}
+          </div>
+        )}
+
+        {replySources.length > 0 && (
+          <div className="bg-gray-50 border-b border-gray-200 p-3 text-sm text-gray-700">
+            <p className="font-medium">Sources given with the last question</p>
+            <ol className="list-decimal pl-5">
+              {replySources.map((source) => (
+                <li key={source.passage_id}>{sourceLocation(source)}</li>
+              ))}
+            </ol>
+          </div>
+        )}
+
+        {redFlagAlerts.length > 0 && (
+          <div className="bg-red-50 border-b border-red-200 p-3 space-y-2">
+            {redFlagAlerts.map((alert) => (
+              <div key={alert.rule_id} className="flex items-start text-sm">
+                <AlertCircle
+                  size={16}
+                  className={`mr-2 mt-0.5 flex-shrink-0 ${alert.severity === "critical" ? "text-red-600" : "text-orange-500"}`}
+                />
+                <div>
+                  <p className="font-semibold text-red-800">
+                    {alert.severity === "critical" ? "Critical" : "Urgent"}: {alert.name}
+                    <span className="font-normal text-red-700"> ({alert.matched_terms.join(", ")})</span>
+                  </p>
+                  <p className="text-red-700">{alert.advice}</p>
+                </div>
+              </div>
+            ))}
+            <button
+              onClick={() => setRedFlagAlerts([])}
+              className="text-xs text-red-700 hover:text-red-900 underline"
+            >
+              Dismiss
+            </button>
+          </div>
+        )}
+
         <div className="flex-1 overflow-y-auto scrollbar-thin p-4 space-y-4">
           {messages.length === 0 ? (
             <div className="text-center py-12">
@@ -371,7 +1545,24 @@ function App() {
               </p>
             </div>
           ) : (
-            messages.map((message) => (
+            messages.map((message, index) => message.role === "tool" ? (
+              <div key={message.id} className="flex justify-start">
+                <div className={`max-w-3xl px-3 py-2 rounded-lg border text-sm ${
+                  message.tool_call?.error ? 'bg-yellow-50 border-yellow-200 text-yellow-800' : 'bg-gray-50 border-gray-200 text-gray-600'
+                }`}>
+                  <p className="flex items-center">
+                    <Wrench size={14} className="mr-2 flex-shrink-0" />
+                    {message.content}
+                  </p>
+                  {message.tool_call && (
+                    <details className="mt-1 text-xs text-gray-500">
+                      <summary className="cursor-pointer">Arguments</summary>
+                      <pre className="whitespace-pre-wrap">{JSON.stringify(message.tool_call.arguments, null, 2)}</pre>
+                    </details>
+                  )}
+                </div>
+              </div>
+            ) : (
               <div
                 key={message.id}
                 className={`flex ${message.role === 'user' ? 'justify-end' : 'justify-start'}`}
@@ -383,18 +1574,113 @@ function App() {
                       : 'bg-white border border-gray-200 text-gray-800'
                   }`}
                 >
-                  <div className="whitespace-pre-wrap">{message.content}</div>
+                  {editingMessage?.id === message.id ? (
+                    <div className="space-y-2">
+                      <textarea
+                        value={editingMessage.content}
+                        onChange={(e) => setEditingMessage({ id: message.id, content: e.target.value })}
+                        className="w-full min-w-[20rem] text-gray-800 rounded p-2"
+                        rows={3}
+                      />
+                      <div className="flex justify-end space-x-2 text-sm">
+                        <button
+                          onClick={() => setEditingMessage(null)}
+                          className="px-3 py-1 rounded bg-blue-500 hover:bg-blue-400"
+                        >
+                          Cancel
+                        </button>
+                        <button
+                          onClick={editMessage}
+                          disabled={!editingMessage.content.trim() || isLoading || !isAiReady}
+                          className="px-3 py-1 rounded bg-white text-blue-700 hover:bg-blue-50 disabled:opacity-50"
+                        >
+                          Save & send
+                        </button>
+                      </div>
+                    </div>
+                  ) : (
+                    <div className="whitespace-pre-wrap">
+                      {message.content || (message.role === "assistant" && <span className="italic text-gray-500">No answer was generated. Try regenerating.</span>)}
+                    </div>
+                  )}
+                  {message.citations && message.citations.length > 0 && (
+                    <ul className="mt-2 pt-2 border-t border-gray-200 text-xs text-gray-500">
+                      {message.citations.map((citation) => (
+                        <li key={citation.marker}>
+                          [{citation.marker}] {sourceLocation(citation)}
+                        </li>
+                      ))}
+                    </ul>
+                  )}
                   <div className={`text-xs mt-2 ${
                     message.role === 'user' ? 'text-blue-100' : 'text-gray-400'
                   }`}>
                     {new Date(message.timestamp).toLocaleTimeString()}
+                    {message.cancelled && " · cancelled"}
                   </div>
+                  {!message.id.startsWith("pending-") && (
+                    <div className={`flex items-center space-x-2 mt-1 text-xs ${
+                      message.role === 'user' ? 'text-blue-100 justify-end' : 'text-gray-400'
+                    }`}>
+                      {(message.sibling_count ?? 1) > 1 && (
+                        <>
+                          <button
+                            onClick={() => switchAlternative(message, -1)}
+                            disabled={isLoading || (message.sibling_index ?? 0) === 0}
+                            className="hover:opacity-70 disabled:opacity-30"
+                            title={message.role === 'user' ? "Previous version" : "Previous answer"}
+                          >
+                            <ChevronLeft size={14} />
+                          </button>
+                          <span>
+                            {(message.sibling_index ?? 0) + 1}/{message.sibling_count}
+                          </span>
+                          <button
+                            onClick={() => switchAlternative(message, 1)}
+                            disabled={isLoading || (message.sibling_index ?? 0) + 1 === message.sibling_count}
+                            className="hover:opacity-70 disabled:opacity-30"
+                            title={message.role === 'user' ? "Next version" : "Next answer"}
+                          >
+                            <ChevronRight size={14} />
+                          </button>
+                        </>
+                      )}
+                      {message.role === 'user' && editingMessage?.id !== message.id && (
+                        <button
+                          onClick={() => setEditingMessage({ id: message.id, content: message.content })}
+                          disabled={isLoading}
+                          className="hover:opacity-70 disabled:opacity-30"
+                          title="Edit and branch from here"
+                        >
+                          <Pencil size={14} />
+                        </button>
+                      )}
+                      {message.role === 'assistant' && index === messages.length - 1 && (
+                        <button
+                          onClick={() => regenerateMessage(message.id)}
+                          disabled={isLoading || !isAiReady}
+                          className="hover:opacity-70 disabled:opacity-30"
+                          title="Generate another answer"
+                        >
+                          <RefreshCw size={14} />
+                        </button>
+                      )}
+                    </div>
+                  )}
                 </div>
               </div>
             ))
           )}
           
-          {isLoading && (
+          {streamingMessage && (
+            <div className="flex justify-start">
+              <div className="max-w-3xl px-4 py-3 rounded-lg bg-white border border-gray-200 text-gray-800">
+                <div className="whitespace-pre-wrap">{streamingMessage.content}</div>
+              </div>
+            </div>
+          )}
+
+          {isLoading && !streamingMessage && (
             <div className="flex justify-start">
               <div className="bg-white border border-gray-200 rounded-lg px-4 py-3">
                 <div className="flex items-center space-x-2">
@@ -419,13 +1705,22 @@ function App() {
               className="flex-1 border border-gray-300 rounded-lg px-4 py-2 focus:ring-2 focus:ring-blue-500 focus:border-transparent"
               disabled={isLoading}
             />
-            <button
-              onClick={sendMessage}
-              disabled={!inputMessage.trim() || isLoading}
-              className="bg-blue-600 text-white px-6 py-2 rounded-lg hover:bg-blue-700 disabled:opacity-50 disabled:cursor-not-allowed flex items-center"
-            >
-              <Send size={16} />
-            </button>
+            {isLoading && generatingConversation ? (
+              <button
+                onClick={cancelGeneration}
+                className="bg-red-600 text-white px-6 py-2 rounded-lg hover:bg-red-700 flex items-center"
+              >
+                <Square size={16} />
+              </button>
+            ) : (
+              <button
+                onClick={sendMessage}
+                disabled={!inputMessage.trim() || isLoading}
+                className="bg-blue-600 text-white px-6 py-2 rounded-lg hover:bg-blue-700 disabled:opacity-50 disabled:cursor-not-allowed flex items-center"
+              >
+                <Send size={16} />
+              </button>
+            )}
           </div>
           <p className="text-xs text-gray-500 mt-2 text-center">
             AI responses are for educational purposes only. Always consult with qualified medical professionals for clinical decisions.